@@ -0,0 +1,104 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use http::StatusCode;
+use rusticore::{shared_stream, Request, Response, Server, StaticFiles};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+/// The size in bytes of the file downloaded in every iteration.
+const FILE_SIZE: usize = 16 * 1024 * 1024;
+
+/// How the server side writes the file to the connection.
+#[derive(Clone, Copy)]
+enum Mode {
+    /// `StaticFiles` on a plain `TcpStream`, which uses `sendfile(2)` on Linux.
+    Sendfile,
+    /// `StaticFiles` on a wrapped `TcpStream`, which falls back to buffered streaming.
+    Buffered,
+    /// The whole file read into memory and sent with `Response::bytes`.
+    WriteAll,
+}
+
+/// Downloads the file once over a loopback connection.
+async fn download(
+    listener: &TcpListener,
+    server: &Arc<Server>,
+    files: &StaticFiles,
+    path: &Path,
+    mode: Mode,
+) {
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+    client
+        .write_all(b"GET /assets/data.bin HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+    let reader = tokio::spawn(async move {
+        let mut buffer = vec![0; 256 * 1024];
+        let mut total = 0;
+        loop {
+            match client.read(&mut buffer).await.unwrap() {
+                0 => break total,
+                n => total += n,
+            }
+        }
+    });
+
+    let stream = match mode {
+        Mode::Buffered => shared_stream(BufStream::new(stream)),
+        _ => shared_stream(stream),
+    };
+    let mut req = Request::new(stream.clone(), server.clone()).await.unwrap();
+    req.path_params
+        .insert("path".to_string(), "data.bin".to_string());
+    let mut res = Response::new(stream.clone(), "HTTP/1.1", server.clone());
+    match mode {
+        Mode::Sendfile | Mode::Buffered => files.serve(&mut req, &mut res).await,
+        Mode::WriteAll => {
+            let body = tokio::fs::read(path).await.unwrap();
+            res.bytes(&body, "application/octet-stream", StatusCode::OK)
+                .await;
+        }
+    }
+    let _ = stream.lock().await.shutdown().await;
+    drop((req, res, stream));
+
+    assert!(reader.await.unwrap() > FILE_SIZE);
+}
+
+fn static_files(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let dir = std::env::temp_dir().join("rusticore-bench");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    let path = dir.join("data.bin");
+    std::fs::write(&path, data).unwrap();
+
+    let files = StaticFiles::new(&dir);
+    let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+    let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+
+    let mut group = c.benchmark_group("static_files");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(20);
+    for (name, mode) in [
+        ("sendfile", Mode::Sendfile),
+        ("buffered", Mode::Buffered),
+        ("write_all", Mode::WriteAll),
+    ] {
+        group.bench_function(name, |b| {
+            b.to_async(&runtime)
+                .iter(|| download(&listener, &server, &files, &path, mode));
+        });
+    }
+    group.finish();
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+criterion_group!(benches, static_files);
+criterion_main!(benches);
@@ -0,0 +1,408 @@
+use crate::compression::{self, Encoder};
+use crate::response::{is_valid_header, Response};
+use crate::transport::SharedStream;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use http::StatusCode;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+/// The size in bytes of the chunks an `AsyncRead` body is streamed in.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Debug)]
+/// Writes the body of a streaming response, returned by `Response::start_stream`.
+///
+/// On HTTP/1.1 every write is sent as one chunk of a `Transfer-Encoding: chunked` body. On
+/// HTTP/1.0 the body is close-delimited: it is written as is, and the end of the body is
+/// signalled by closing the connection. Writes wait until the client has accepted the data,
+/// so a slow client slows the producer down instead of filling memory.
+///
+/// The body must be ended with `finish` or `finish_with_trailers`; a body dropped before
+/// that is seen by the client as truncated.
+pub struct BodyWriter {
+    /// The connection the body is written to.
+    stream: SharedStream,
+    /// Whether the body uses chunked transfer coding.
+    chunked: bool,
+    /// The compressor of the body, if the `Compression` middleware negotiated an encoding.
+    encoder: Option<Encoder>,
+    /// Whether the response is complete, set once the body is finished. A body dropped
+    /// unfinished leaves the connection unusable for further responses.
+    sent: Arc<AtomicBool>,
+    /// Whether the body is left out, as the response to a `HEAD` request or with a status
+    /// that has no body.
+    discard: bool,
+}
+
+impl BodyWriter {
+    /// Writes a part of the body.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The bytes to write. Empty writes are ignored, since an empty chunk ends the body.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` indicating whether the data was written, e.g. an error if the client disconnected.
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() || self.discard {
+            return Ok(());
+        }
+        let compressed;
+        let data = match self.encoder {
+            Some(ref mut encoder) => {
+                compressed = encoder.write(data)?;
+                if compressed.is_empty() {
+                    return Ok(());
+                }
+                &compressed[..]
+            }
+            None => data,
+        };
+
+        let mut stream = self.stream.lock().await;
+        if self.chunked {
+            stream
+                .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+                .await?;
+            stream.write_all(data).await?;
+            stream.write_all(b"\r\n").await?;
+        } else {
+            stream.write_all(data).await?;
+        }
+        stream.flush().await
+    }
+
+    /// Ends the body.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` indicating whether the end of the body was written.
+    pub async fn finish(self) -> io::Result<()> {
+        self.finish_with_trailers(&[]).await
+    }
+
+    /// Ends the body, followed by trailer fields, e.g. a checksum computed while streaming.
+    ///
+    /// Trailers can only be sent with chunked transfer coding, so they are dropped on HTTP/1.0.
+    /// Announce them in a `Trailer` header before starting the stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `trailers` - The trailer field names and values.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` indicating whether the end of the body was written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` error, without ending the body, if a trailer name is not a
+    /// token or a value contains CR, LF or NUL.
+    pub async fn finish_with_trailers(mut self, trailers: &[(&str, &str)]) -> io::Result<()> {
+        if !trailers
+            .iter()
+            .all(|(key, value)| is_valid_header(key, value))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid trailer field",
+            ));
+        }
+        if self.discard {
+            self.sent.store(true, Ordering::Release);
+            return Ok(());
+        }
+        if let Some(encoder) = self.encoder.take() {
+            let rest = encoder.finish()?;
+            self.write(&rest).await?;
+        }
+
+        let mut stream = self.stream.lock().await;
+        if self.chunked {
+            let mut end = String::from("0\r\n");
+            for (key, value) in trailers {
+                end.push_str(&format!("{key}: {value}\r\n"));
+            }
+            end.push_str("\r\n");
+            stream.write_all(end.as_bytes()).await?;
+            stream.flush().await?;
+        } else {
+            stream.shutdown().await?;
+        }
+        self.sent.store(true, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl Response {
+    /// Sends the response head and returns a writer for a body of unknown length.
+    ///
+    /// # Arguments
+    ///
+    /// * `content_type` - The value of the `Content-Type` header.
+    /// * `status_code` - The HTTP status code for the response.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the `BodyWriter` the body is written to.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of writing the head, e.g. if the client has gone away.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use rusticore::Response;
+    /// # use http::StatusCode;
+    /// # async fn handler(res: &mut Response) -> std::io::Result<()> {
+    /// res.set_header("Trailer", "X-Row-Count");
+    /// let mut body = res.start_stream("text/csv", StatusCode::OK).await?;
+    /// body.write(b"id,name\n").await?;
+    /// for id in 0..1000 {
+    ///     body.write(format!("{id},user{id}\n").as_bytes()).await?;
+    /// }
+    /// body.finish_with_trailers(&[("X-Row-Count", "1000")]).await
+    /// # }
+    /// ```
+    pub async fn start_stream(
+        &mut self,
+        content_type: &str,
+        status_code: StatusCode,
+    ) -> io::Result<BodyWriter> {
+        self.status_code = status_code;
+        self.set_header("Content-Type", content_type);
+        self.headers
+            .retain(|(k, _)| !k.eq_ignore_ascii_case("Content-Length"));
+
+        // Informational, `204 No Content` and `304 Not Modified` responses end with their head.
+        let bodyless = status_code.is_informational()
+            || status_code == StatusCode::NO_CONTENT
+            || status_code == StatusCode::NOT_MODIFIED;
+        let chunked = !bodyless && self.http_version.as_str() != "HTTP/1.0";
+        if chunked {
+            self.set_header("Transfer-Encoding", "chunked");
+        } else if !bodyless {
+            self.set_header("Connection", "close");
+        }
+        let encoder = if bodyless {
+            None
+        } else {
+            compression::stream_encoder(self)
+        };
+        self.send_head().await?;
+
+        Ok(BodyWriter {
+            stream: self.tcp_stream.clone(),
+            chunked,
+            encoder,
+            sent: self.sent.clone(),
+            discard: self.head_only || bodyless,
+        })
+    }
+
+    /// Sends a response whose body is produced by a stream, e.g. rows of a generated export.
+    ///
+    /// The next item is only polled once the previous one has been written to the client.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The stream of body parts. An error ends the response early, truncating the body.
+    /// * `content_type` - The value of the `Content-Type` header.
+    /// * `status_code` - The HTTP status code for the response.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` indicating whether the whole body was sent.
+    pub async fn stream<S>(
+        &mut self,
+        body: S,
+        content_type: &str,
+        status_code: StatusCode,
+    ) -> io::Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send,
+    {
+        let mut writer = self.start_stream(content_type, status_code).await?;
+        let mut body = std::pin::pin!(body);
+        while let Some(part) = body.next().await {
+            writer.write(&part?).await?;
+        }
+        writer.finish().await
+    }
+
+    /// Sends a response whose body is read from an `AsyncRead`, e.g. a child process's output.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The reader the body is read from until it reaches end of file.
+    /// * `content_type` - The value of the `Content-Type` header.
+    /// * `status_code` - The HTTP status code for the response.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` indicating whether the whole body was sent.
+    pub async fn stream_reader<R>(
+        &mut self,
+        body: R,
+        content_type: &str,
+        status_code: StatusCode,
+    ) -> io::Result<()>
+    where
+        R: AsyncRead + Send,
+    {
+        let mut writer = self.start_stream(content_type, status_code).await?;
+        let mut body = std::pin::pin!(body);
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            match body.read(&mut buffer).await? {
+                0 => break,
+                n => writer.write(&buffer[..n]).await?,
+            }
+        }
+        writer.finish().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::shared_stream;
+    use crate::Server;
+    use std::sync::Arc;
+    use tokio::io::duplex;
+
+    /// Runs a streaming handler against a response and returns what the client received.
+    async fn collect<F>(http_version: &str, handler: F) -> String
+    where
+        F: AsyncFnOnce(&mut Response) -> io::Result<()>,
+    {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (mut client, server_stream) = duplex(64);
+        let reader = tokio::spawn(async move {
+            let mut output = String::new();
+            client.read_to_string(&mut output).await.unwrap();
+            output
+        });
+        let mut res = Response::new(shared_stream(server_stream), http_version, server);
+        handler(&mut res).await.unwrap();
+        drop(res);
+        reader.await.unwrap()
+    }
+
+    #[tokio::test]
+    /// Tests chunked streaming on HTTP/1.1, including trailers, refused trailers, a stream body
+    /// and a status without a body.
+    async fn chunked() {
+        let output = collect("HTTP/1.1", async |res| {
+            let mut body = res.start_stream("text/csv", StatusCode::OK).await?;
+            body.write(b"id\n").await?;
+            body.write(b"").await?;
+            body.write(&[b'x'; 300]).await?;
+            body.finish_with_trailers(&[("X-Rows", "1")]).await
+        })
+        .await;
+        let (head, body) = output.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        assert_eq!(
+            body,
+            format!(
+                "3\r\nid\n\r\n12c\r\n{}\r\n0\r\nX-Rows: 1\r\n\r\n",
+                "x".repeat(300)
+            )
+        );
+
+        let output = collect("HTTP/1.1", async |res| {
+            let parts = ["a", "bc"].map(|part| Ok(Bytes::from(part)));
+            res.stream(futures::stream::iter(parts), "text/plain", StatusCode::OK)
+                .await
+        })
+        .await;
+        assert!(output.ends_with("\r\n\r\n1\r\na\r\n2\r\nbc\r\n0\r\n\r\n"));
+
+        let output = collect("HTTP/1.1", async |res| {
+            let body = res.start_stream("text/plain", StatusCode::OK).await?;
+            let injected = body.finish_with_trailers(&[("X-Rows", "1\r\nX-Admin: 1")]);
+            assert_eq!(
+                injected.await.unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+            Ok(())
+        })
+        .await;
+        assert!(!output.contains("X-Admin"));
+
+        let output = collect("HTTP/1.1", async |res| {
+            let mut body = res
+                .start_stream("text/plain", StatusCode::NO_CONTENT)
+                .await?;
+            body.write(b"ignored").await?;
+            body.finish().await
+        })
+        .await;
+        assert!(output.starts_with("HTTP/1.1 204"));
+        assert!(!output.contains("Transfer-Encoding"));
+        assert!(output.ends_with("\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    /// Tests close-delimited streaming on HTTP/1.0, where trailers cannot be sent.
+    async fn close_delimited() {
+        let data = "0123456789".repeat(5000);
+        let expected = data.clone();
+        let output = collect("HTTP/1.0", async move |res| {
+            res.stream_reader(data.as_bytes(), "text/plain", StatusCode::OK)
+                .await
+        })
+        .await;
+        let (head, body) = output.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Connection: close"));
+        assert!(!head.contains("Transfer-Encoding"));
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    /// Tests that a connection is closed after a body dropped without being finished, instead
+    /// of writing the next response inside it.
+    async fn unfinished() {
+        use crate::server::ConnectionInfo;
+        use crate::{Request, Route};
+
+        /// Starts a chunked body and abandons it.
+        async fn abandon(_req: &mut Request, res: &mut Response) {
+            let mut body = res
+                .start_stream("text/plain", StatusCode::OK)
+                .await
+                .unwrap();
+            body.write(b"partial").await.unwrap();
+        }
+
+        let mut server = Server::new("localhost", 8080, false, None, None);
+        server
+            .add_route(Route::new(
+                "GET",
+                "/abandon",
+                Arc::new(|req, res| Box::pin(abandon(req, res))),
+            ))
+            .await;
+        let (mut client, server_stream) = duplex(4096);
+        let connection = tokio::spawn(Server::handle_connection(
+            Arc::new(server),
+            server_stream,
+            ConnectionInfo::default(),
+        ));
+        client
+            .write_all(b"GET /abandon HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        connection.await.unwrap();
+        assert!(output.ends_with("7\r\npartial\r\n"));
+        assert_eq!(output.matches("HTTP/1.1 ").count(), 1);
+    }
+}
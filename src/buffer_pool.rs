@@ -84,7 +84,6 @@ impl BufferPool {
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
     use crate::server::Server;
@@ -0,0 +1,594 @@
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+use futures::future::BoxFuture;
+use http::StatusCode;
+use std::io;
+#[cfg(any(feature = "brotli", feature = "gzip", feature = "deflate"))]
+use std::io::Write;
+
+/// The media types that are already compressed, or not worth compressing.
+const INCOMPRESSIBLE_TYPES: [&str; 12] = [
+    "image/",
+    "video/",
+    "audio/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/zstd",
+    "application/octet-stream",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A content coding supported by the compression middleware.
+pub(crate) enum Encoding {
+    #[cfg(feature = "brotli")]
+    /// Brotli, `br`.
+    Brotli,
+    #[cfg(feature = "gzip")]
+    /// Gzip, `gzip`.
+    Gzip,
+    #[cfg(feature = "deflate")]
+    /// The zlib format, `deflate`.
+    Deflate,
+}
+
+impl Encoding {
+    /// The encodings enabled by cargo features, in order of preference.
+    const ENABLED: &'static [Encoding] = &[
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli,
+        #[cfg(feature = "gzip")]
+        Encoding::Gzip,
+        #[cfg(feature = "deflate")]
+        Encoding::Deflate,
+    ];
+
+    /// Returns the token of the encoding in `Accept-Encoding` and `Content-Encoding` headers.
+    pub(crate) fn token(self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => "gzip",
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Chooses the preferred encoding of an `Accept-Encoding` header, following RFC 9110 section 12.5.3.
+///
+/// # Arguments
+///
+/// * `accept_encoding` - The value of the `Accept-Encoding` header.
+///
+/// # Returns
+///
+/// An `Option<Encoding>` with the highest q-value, ties going to the best codec, or `None` if
+/// no enabled encoding is acceptable.
+pub(crate) fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut weights = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let token = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let mut q = 1.0;
+        for param in params {
+            if let Some((key, value)) = param.split_once('=')
+                && key.trim().eq_ignore_ascii_case("q")
+            {
+                q = value.trim().parse::<f32>().unwrap_or(0.0);
+            }
+        }
+        if !token.is_empty() {
+            weights.push((token, q));
+        }
+    }
+
+    let weight = |encoding: Encoding| {
+        let named = weights.iter().find(|(token, _)| {
+            token == encoding.token() || (token == "x-gzip" && encoding.token() == "gzip")
+        });
+        named
+            .or_else(|| weights.iter().find(|(token, _)| token == "*"))
+            .map_or(0.0, |&(_, q)| q)
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for &encoding in Encoding::ENABLED {
+        let q = weight(encoding);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Checks whether a body of a media type is worth compressing.
+fn is_compressible(content_type: &str) -> bool {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    if content_type.starts_with("image/svg+xml") {
+        return true;
+    }
+    !INCOMPRESSIBLE_TYPES
+        .iter()
+        .any(|prefix| content_type.starts_with(prefix))
+}
+
+/// Compresses a body incrementally, flushing after every write so streamed parts reach the client.
+pub(crate) enum Encoder {
+    #[cfg(feature = "brotli")]
+    /// A Brotli compressor.
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    #[cfg(feature = "gzip")]
+    /// A gzip compressor.
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    #[cfg(feature = "deflate")]
+    /// A zlib compressor.
+    Deflate(flate2::write::ZlibEncoder<Vec<u8>>),
+}
+
+impl std::fmt::Debug for Encoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Encoder")
+    }
+}
+
+impl Encoder {
+    /// Creates a compressor for an encoding, with a level suited to dynamic responses.
+    pub(crate) fn new(encoding: Encoding) -> Self {
+        match encoding {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                5,
+                22,
+            ))),
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => Encoder::Deflate(flate2::write::ZlibEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+        }
+    }
+
+    /// Compresses a part of the body and flushes it.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the compressed bytes produced so far.
+    // Without any codec feature `Encoder` has no variants, so `data` is never used.
+    #[cfg_attr(
+        not(any(feature = "brotli", feature = "gzip", feature = "deflate")),
+        allow(unused_variables, unused_mut, unreachable_code)
+    )]
+    pub(crate) fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(ref mut encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(ref mut encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            #[cfg(feature = "deflate")]
+            Encoder::Deflate(ref mut encoder) => {
+                encoder.write_all(data)?;
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    /// Ends the compressed stream.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the remaining compressed bytes.
+    pub(crate) fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "deflate")]
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Returns the value of an `Accept-Encoding` header listing the encodings request bodies may use.
+pub(crate) fn accepted_encodings() -> String {
+    let mut tokens: Vec<&str> = Encoding::ENABLED.iter().map(|e| e.token()).collect();
+    tokens.push("identity");
+    tokens.join(", ")
+}
+
+/// Decodes a request body sent with a `Content-Encoding` header.
+///
+/// # Arguments
+///
+/// * `content_encoding` - The value of the `Content-Encoding` header, listing the codings in
+///   the order they were applied.
+/// * `body` - The encoded body.
+/// * `limit` - The maximum size in bytes of the decoded body.
+///
+/// # Returns
+///
+/// A `Result` containing the decoded body.
+///
+/// # Errors
+///
+/// Returns `415 Unsupported Media Type` for encodings that are not enabled, `413 Payload Too
+/// Large` if the decoded body would exceed `limit`, and `400 Bad Request` for corrupt data.
+pub(crate) fn decode(
+    content_encoding: &str,
+    body: &[u8],
+    limit: usize,
+) -> Result<Vec<u8>, StatusCode> {
+    let mut codings = Vec::new();
+    for token in content_encoding.split(',') {
+        let token = token.trim().to_ascii_lowercase();
+        let encoding = Encoding::ENABLED
+            .iter()
+            .copied()
+            .find(|e| e.token() == token || (token == "x-gzip" && e.token() == "gzip"));
+        match (encoding, token.as_str()) {
+            (Some(encoding), _) => codings.push(encoding),
+            (None, "identity" | "") => {}
+            (None, _) => return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+        }
+    }
+
+    let mut decoded = body.to_vec();
+    for encoding in codings.into_iter().rev() {
+        decoded = decode_one(encoding, &decoded, limit)?;
+    }
+    Ok(decoded)
+}
+
+/// Removes one content coding from a body, stopping as soon as the output exceeds `limit`.
+// Without any codec feature `Encoding` has no variants, so the body is unreachable.
+#[cfg_attr(
+    not(any(feature = "brotli", feature = "gzip", feature = "deflate")),
+    allow(unused_variables, unreachable_code)
+)]
+fn decode_one(encoding: Encoding, data: &[u8], limit: usize) -> Result<Vec<u8>, StatusCode> {
+    let read_limited = |reader: &mut dyn io::Read| {
+        let mut output = Vec::new();
+        // Reading one byte past the limit tells a body at the limit from a larger one.
+        let mut limited = io::Read::take(reader, limit as u64 + 1);
+        match io::Read::read_to_end(&mut limited, &mut output) {
+            Ok(_) if output.len() > limit => Err(StatusCode::PAYLOAD_TOO_LARGE),
+            Ok(_) => Ok(output),
+            Err(_) => Err(StatusCode::BAD_REQUEST),
+        }
+    };
+
+    match encoding {
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli => read_limited(&mut brotli::Decompressor::new(data, 4096)),
+        #[cfg(feature = "gzip")]
+        Encoding::Gzip => read_limited(&mut flate2::read::MultiGzDecoder::new(data)),
+        // `deflate` is meant to be the zlib format, but some clients send raw deflate data.
+        #[cfg(feature = "deflate")]
+        Encoding::Deflate => {
+            read_limited(&mut flate2::read::ZlibDecoder::new(data)).or_else(|status| match status {
+                StatusCode::BAD_REQUEST => {
+                    read_limited(&mut flate2::read::DeflateDecoder::new(data))
+                }
+                status => Err(status),
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The encoding negotiated by the `Compression` middleware, stored in the response's extensions.
+pub(crate) struct Negotiated {
+    /// The encoding accepted by the client, or `None` to send bodies as is.
+    encoding: Option<Encoding>,
+    /// The size in bytes below which bodies are sent as is.
+    min_size: usize,
+}
+
+/// Prepares the headers of a response about to be compressed.
+///
+/// # Returns
+///
+/// An `Option<Encoding>` to compress the body with, or `None` to send it as is.
+fn prepare(res: &mut Response, len: Option<usize>) -> Option<Encoding> {
+    let negotiated = *res.extensions.get::<Negotiated>()?;
+    if matches!(
+        res.status_code,
+        StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED | StatusCode::PARTIAL_CONTENT
+    ) || res.status_code.is_informational()
+        || res.get_header("Content-Encoding").is_some()
+        || res.get_header("Content-Range").is_some()
+        || !res.get_header("Content-Type").is_some_and(is_compressible)
+    {
+        return None;
+    }
+
+    // The body depends on `Accept-Encoding` even when it is not compressed for this client.
+    let varies = res.headers.iter().any(|(key, value)| {
+        key.eq_ignore_ascii_case("Vary")
+            && value
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case("Accept-Encoding") || v.trim() == "*")
+    });
+    if !varies {
+        res.add_header("Vary", "Accept-Encoding");
+    }
+
+    let encoding = negotiated.encoding?;
+    if len.is_some_and(|len| len < negotiated.min_size) {
+        return None;
+    }
+    res.set_header("Content-Encoding", encoding.token());
+    // Ranges and validators of the uncompressed body do not apply to the compressed one.
+    res.headers
+        .retain(|(key, _)| !key.eq_ignore_ascii_case("Accept-Ranges"));
+    if let Some(etag) = res.get_header("ETag")
+        && !etag.starts_with("W/")
+    {
+        let weak = format!("W/{etag}");
+        res.set_header("ETag", weak);
+    }
+    Some(encoding)
+}
+
+/// Compresses a complete body if the `Compression` middleware is in use and the client accepts it.
+///
+/// # Returns
+///
+/// An `Option<Vec<u8>>` containing the compressed body, or `None` to send the body as is.
+// Without any codec feature there is no `Encoder` to call, so the body is unreachable.
+#[cfg_attr(
+    not(any(feature = "brotli", feature = "gzip", feature = "deflate")),
+    allow(unused_variables, unused_mut, unreachable_code)
+)]
+pub(crate) fn compress_body(res: &mut Response, body: &[u8]) -> Option<Vec<u8>> {
+    let encoding = prepare(res, Some(body.len()))?;
+    let mut encoder = Encoder::new(encoding);
+    let compressed = encoder.write(body).and_then(|mut compressed| {
+        compressed.extend(encoder.finish()?);
+        Ok(compressed)
+    });
+    match compressed {
+        Ok(compressed) => {
+            if res.get_header("Content-Length").is_some() {
+                res.set_header("Content-Length", compressed.len().to_string());
+            }
+            Some(compressed)
+        }
+        Err(_) => {
+            res.headers
+                .retain(|(key, _)| !key.eq_ignore_ascii_case("Content-Encoding"));
+            None
+        }
+    }
+}
+
+/// Creates a compressor for a streamed body if the `Compression` middleware is in use and the
+/// client accepts it. Streamed bodies have no known size, so `min_size` does not apply.
+pub(crate) fn stream_encoder(res: &mut Response) -> Option<Encoder> {
+    prepare(res, None).map(Encoder::new)
+}
+
+#[derive(Debug, Clone)]
+/// A middleware compressing response bodies with the best encoding the client accepts.
+///
+/// Each encoding is enabled by its own cargo feature: `brotli`, `gzip` and `deflate`, preferred
+/// in that order when the client weighs them equally. Bodies of already-compressed media types,
+/// such as images, video, audio and archives, are sent as is, and so are bodies smaller than
+/// `min_size` and partial responses. Streamed bodies are compressed as they are written.
+/// Static files are sent as is too, so they can still be sent with `sendfile`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use rusticore::{Compression, Server};
+/// # async fn setup(server: &mut Server) {
+/// server.add_middleware(Compression::new().min_size(512)).await;
+/// # }
+/// ```
+pub struct Compression {
+    /// The size in bytes below which bodies are sent as is.
+    min_size: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new()
+    }
+}
+
+impl Compression {
+    /// Creates a new compression middleware, compressing bodies of at least 1 KiB.
+    ///
+    /// # Returns
+    ///
+    /// A new `Compression` instance.
+    pub fn new() -> Self {
+        Compression { min_size: 1024 }
+    }
+
+    /// Sets the size in bytes below which bodies are sent as is, since compressing them saves little.
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+}
+
+impl Middleware for Compression {
+    fn before<'a>(&'a self, req: &'a mut Request, res: &'a mut Response) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            res.extensions.insert(Negotiated {
+                encoding: req.get_header("Accept-Encoding").and_then(negotiate),
+                min_size: self.min_size,
+            });
+            true
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests which media types are worth compressing.
+    fn compressible_types() {
+        assert!(is_compressible("application/json"));
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("video/mp4"));
+        assert!(!is_compressible("application/zip"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    /// Tests the negotiation of encodings with q-values, wildcards and refusals, and decoding bodies.
+    fn negotiate_encodings() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, gzip;q=0.9"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("x-gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0.1, br;q=0"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("gzip;q=0, deflate;q=0, br;q=0"), None);
+        assert_eq!(negotiate(""), None);
+
+        // Every encoder's output decodes back, including stacked codings.
+        let body = "hello hello hello".repeat(50);
+        for &encoding in Encoding::ENABLED {
+            let mut encoder = Encoder::new(encoding);
+            let mut encoded = encoder.write(body.as_bytes()).unwrap();
+            encoded.extend(encoder.finish().unwrap());
+            assert_eq!(
+                decode(encoding.token(), &encoded, 10_000).unwrap(),
+                body.as_bytes()
+            );
+            assert_eq!(
+                decode(encoding.token(), &encoded, 100),
+                Err(StatusCode::PAYLOAD_TOO_LARGE)
+            );
+        }
+        let mut gzip = Encoder::new(Encoding::Gzip);
+        let mut encoded = gzip.write(b"abc").unwrap();
+        encoded.extend(gzip.finish().unwrap());
+        let mut brotli = Encoder::new(Encoding::Brotli);
+        let mut twice = brotli.write(&encoded).unwrap();
+        twice.extend(brotli.finish().unwrap());
+        assert_eq!(decode("gzip, br", &twice, 100).unwrap(), b"abc");
+        assert_eq!(
+            decode("gzip", b"garbage", 100),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "gzip")]
+    /// Tests compressing buffered and streamed bodies, and skipping small and incompressible ones.
+    async fn compress_responses() {
+        use crate::transport::shared_stream;
+        use crate::Server;
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+        use std::sync::Arc;
+        use tokio::io::{duplex, AsyncReadExt};
+
+        /// Sends a response with the compression middleware negotiated to gzip.
+        async fn send<F>(handler: F) -> (String, Vec<u8>)
+        where
+            F: AsyncFnOnce(&mut Response),
+        {
+            let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+            let (mut client, server_stream) = duplex(1 << 16);
+            let mut res = Response::new(shared_stream(server_stream), "HTTP/1.1", server);
+            res.extensions.insert(Negotiated {
+                encoding: Some(Encoding::Gzip),
+                min_size: 100,
+            });
+            handler(&mut res).await;
+            drop(res);
+
+            let mut output = Vec::new();
+            client.read_to_end(&mut output).await.unwrap();
+            let end = output.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let head = String::from_utf8(output[..end].to_vec()).unwrap();
+            (head, output[end + 4..].to_vec())
+        }
+
+        let gunzip = |body: &[u8]| {
+            let mut output = String::new();
+            GzDecoder::new(body).read_to_string(&mut output).unwrap();
+            output
+        };
+
+        let json = format!("[{}]", "{\"id\":1},".repeat(100));
+        let (head, body) = send(async |res| res.json(&json, StatusCode::OK).await).await;
+        assert!(head.contains("Content-Encoding: gzip"));
+        assert!(head.contains("Vary: Accept-Encoding"));
+        assert!(body.len() < json.len());
+        assert_eq!(gunzip(&body), json);
+
+        let (head, body) = send(async |res| res.text("short", StatusCode::OK).await).await;
+        assert!(!head.contains("Content-Encoding"));
+        assert!(head.contains("Vary: Accept-Encoding"));
+        assert_eq!(body, b"short");
+
+        let png = "\u{1}".repeat(500);
+        let (head, _) = send(async |res| res.image_png(&png, StatusCode::OK).await).await;
+        assert!(!head.contains("Content-Encoding"));
+        assert!(!head.contains("Vary"));
+
+        let (head, body) = send(async |res| {
+            let mut writer = res
+                .start_stream("text/plain", StatusCode::OK)
+                .await
+                .unwrap();
+            writer.write(b"hello ").await.unwrap();
+            writer.write(b"world").await.unwrap();
+            writer.finish().await.unwrap();
+        })
+        .await;
+        assert!(head.contains("Content-Encoding: gzip"));
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        // Reassemble the chunks before decompressing.
+        let mut chunks = &body[..];
+        let mut compressed = Vec::new();
+        loop {
+            let line_end = chunks.windows(2).position(|w| w == b"\r\n").unwrap();
+            let size = usize::from_str_radix(std::str::from_utf8(&chunks[..line_end]).unwrap(), 16)
+                .unwrap();
+            if size == 0 {
+                break;
+            }
+            compressed.extend_from_slice(&chunks[line_end + 2..line_end + 2 + size]);
+            chunks = &chunks[line_end + 4 + size..];
+        }
+        assert_eq!(gunzip(&compressed), "hello world");
+    }
+}
@@ -0,0 +1,318 @@
+use crate::request::is_token;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Represents the `SameSite` attribute of a cookie.
+pub enum SameSite {
+    /// The cookie is only sent with same-site requests.
+    Strict,
+    /// The cookie is also sent with top-level cross-site navigations.
+    Lax,
+    /// The cookie is sent with all requests. Browsers require `Secure` with this value.
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+            SameSite::None => write!(f, "None"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents a cookie sent to the client in a `Set-Cookie` header.
+///
+/// # Examples
+///
+/// ```
+/// use rusticore::{Cookie, SameSite};
+///
+/// let cookie = Cookie::new("sid", "abc123")
+///     .path("/")
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// assert_eq!(cookie.to_string(), "sid=abc123; Path=/; HttpOnly; SameSite=Lax");
+/// ```
+pub struct Cookie {
+    /// The name of the cookie.
+    name: String,
+    /// The value of the cookie.
+    value: String,
+    /// The path the cookie is restricted to.
+    path: Option<String>,
+    /// The domain the cookie is sent to.
+    domain: Option<String>,
+    /// The number of seconds until the cookie expires.
+    max_age: Option<u64>,
+    /// The date at which the cookie expires.
+    expires: Option<SystemTime>,
+    /// Whether the cookie is only sent over HTTPS.
+    secure: bool,
+    /// Whether the cookie is hidden from JavaScript.
+    http_only: bool,
+    /// The cross-site policy of the cookie.
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Creates a new session cookie with no attributes.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie.
+    /// * `value` - The value of the cookie.
+    ///
+    /// # Returns
+    ///
+    /// A new `Cookie` instance.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Creates a cookie that asks the client to delete the cookie with the same name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie to delete.
+    ///
+    /// # Returns
+    ///
+    /// A `Cookie` with an empty value, `Max-Age=0` and an `Expires` date in the past.
+    pub fn removal(name: impl Into<String>) -> Self {
+        Cookie::new(name, "")
+            .max_age(Duration::ZERO)
+            .expires(SystemTime::UNIX_EPOCH)
+    }
+
+    /// Restricts the cookie to a path.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Sets the domain the cookie is sent to.
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Sets how long the cookie lives, with second precision.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age.as_secs());
+        self
+    }
+
+    /// Sets the date at which the cookie expires.
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Sets whether the cookie is only sent over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets whether the cookie is hidden from JavaScript.
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets the cross-site policy of the cookie.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Replaces the value of the cookie, keeping its attributes.
+    pub(crate) fn with_value(mut self, value: String) -> Self {
+        self.value = value;
+        self
+    }
+
+    /// Checks whether the cookie can be sent as is (RFC 6265): the name is a token, the value
+    /// only has cookie octets, optionally in double quotes, and no attribute can end itself or
+    /// the header early.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the cookie is valid. `Response::add_cookie` refuses invalid cookies.
+    pub fn is_valid(&self) -> bool {
+        let value = self
+            .value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(&self.value);
+        // Any visible ASCII except double quotes, commas, semicolons and backslashes.
+        let is_cookie_octet =
+            |b: u8| matches!(b, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e);
+        let is_attribute_value = |v: &String| v.bytes().all(|b| b != b';' && !b.is_ascii_control());
+        is_token(self.name.as_bytes())
+            && value.bytes().all(is_cookie_octet)
+            && self.path.as_ref().is_none_or(is_attribute_value)
+            && self.domain.as_ref().is_none_or(is_attribute_value)
+    }
+
+    /// Returns the name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for Cookie {
+    /// Formats the cookie as the value of a `Set-Cookie` header.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(ref domain) = self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={max_age}")?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", httpdate::fmt_http_date(expires))?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={same_site}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the value of a `Cookie` request header, e.g. `a=1; b="2"`.
+///
+/// # Arguments
+///
+/// * `header` - The value of the `Cookie` header.
+///
+/// # Returns
+///
+/// An iterator over the cookie names and values, with surrounding quotes removed from the values.
+pub(crate) fn parse_cookie_header(header: &str) -> impl Iterator<Item = (&str, &str)> {
+    header.split(';').filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        Some((name, value))
+    })
+}
+
+/// Collects the cookies of `Cookie` request headers into a map.
+///
+/// # Arguments
+///
+/// * `headers` - The values of every `Cookie` header of the request.
+///
+/// # Returns
+///
+/// A `HashMap` of cookie names to values. When a name is repeated, the first value wins.
+pub(crate) fn collect_cookies<'a>(
+    headers: impl IntoIterator<Item = &'a str>,
+) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    for header in headers {
+        for (name, value) in parse_cookie_header(header) {
+            cookies
+                .entry(name.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+    }
+    cookies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests the formatting of a cookie with every attribute set.
+    fn format() {
+        let cookie = Cookie::new("id", "a3fWa")
+            .path("/docs")
+            .domain("example.com")
+            .max_age(Duration::from_secs(3600))
+            .expires(SystemTime::UNIX_EPOCH + Duration::from_secs(1_445_412_480))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Strict);
+        assert_eq!(
+            cookie.to_string(),
+            "id=a3fWa; Path=/docs; Domain=example.com; Max-Age=3600; \
+             Expires=Wed, 21 Oct 2015 07:28:00 GMT; Secure; HttpOnly; SameSite=Strict"
+        );
+        assert_eq!(
+            Cookie::removal("id").to_string(),
+            "id=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    /// Tests the parsing of `Cookie` headers, including quoted values and malformed pairs.
+    fn parse() {
+        let cookies = collect_cookies(["a=1; b=\"two\";c=x=y; ;bad", "a=ignored; d="]);
+        assert_eq!(cookies.len(), 4);
+        assert_eq!(cookies["a"], "1");
+        assert_eq!(cookies["b"], "two");
+        assert_eq!(cookies["c"], "x=y");
+        assert_eq!(cookies["d"], "");
+    }
+
+    #[test]
+    /// Tests that names, values and attributes that could inject attributes or headers are
+    /// refused.
+    fn validate() {
+        assert!(Cookie::new("sid", "abc123").path("/").is_valid());
+        assert!(Cookie::new("theme", "\"dark\"").is_valid());
+        assert!(Cookie::removal("sid").is_valid());
+        for cookie in [
+            Cookie::new("", "x"),
+            Cookie::new("a b", "x"),
+            Cookie::new("a=b", "x"),
+            Cookie::new("sid", "x; Domain=evil.example"),
+            Cookie::new("sid", "a,b"),
+            Cookie::new("sid", "x\r\nSet-Cookie: admin=1"),
+            Cookie::new("sid", "x").path("/; Secure"),
+            Cookie::new("sid", "x").domain("example.com\r\n"),
+        ] {
+            assert!(!cookie.is_valid(), "{cookie}");
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use crate::cookie::Cookie;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The minimum length in bytes of a master key.
+const MIN_KEY_LEN: usize = 32;
+
+/// The length in bytes of an AES-GCM nonce.
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone)]
+/// The signing and encryption keys derived from one master key.
+struct Key {
+    /// The HMAC-SHA256 key used to sign cookies.
+    signing: [u8; 32],
+    /// The AES-256-GCM key used to encrypt cookies.
+    encryption: [u8; 32],
+}
+
+impl Key {
+    /// Derives the signing and encryption keys from a master key.
+    ///
+    /// # Arguments
+    ///
+    /// * `master` - The master key, at least 32 bytes long.
+    fn derive(master: &[u8]) -> Self {
+        let derive = |label: &[u8]| -> [u8; 32] {
+            let mut mac =
+                <HmacSha256 as Mac>::new_from_slice(master).expect("HMAC accepts any key length");
+            mac.update(label);
+            mac.finalize().into_bytes().into()
+        };
+
+        Key {
+            signing: derive(b"rusticore cookie signing"),
+            encryption: derive(b"rusticore cookie encryption"),
+        }
+    }
+
+    /// Computes the signature of a cookie, binding its value to its name.
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.signing)
+            .expect("HMAC accepts any key length");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+}
+
+#[derive(Clone)]
+/// A keyed cookie jar that signs or encrypts cookies with a server-configured key.
+///
+/// Signed cookies are readable by the client but cannot be modified, while private cookies are
+/// encrypted and authenticated with AES-256-GCM. Older keys can be kept so cookies issued before a
+/// key rotation are still accepted; new cookies are always sealed with the current key.
+///
+/// # Examples
+///
+/// ```
+/// use rusticore::{Cookie, CookieJar};
+///
+/// let jar = CookieJar::new(b"a very long and very secret master key!!").unwrap();
+/// let cookie = jar.sign(Cookie::new("user_id", "42"));
+/// assert_eq!(jar.verify("user_id", cookie.value()), Some("42".to_string()));
+/// assert_eq!(jar.verify("user_id", "43"), None);
+/// ```
+pub struct CookieJar {
+    /// The key used to seal new cookies.
+    current: Key,
+    /// Older keys that are still accepted when reading cookies.
+    previous: Vec<Key>,
+}
+
+impl fmt::Debug for CookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieJar")
+            .field("current", &"<key>")
+            .field("previous", &self.previous.len())
+            .finish()
+    }
+}
+
+impl CookieJar {
+    /// Creates a new `CookieJar` from a master key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The master key, at least 32 bytes of secret random data.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `CookieJar`, or an error message if the key is too short.
+    pub fn new(key: &[u8]) -> Result<Self, &'static str> {
+        if key.len() < MIN_KEY_LEN {
+            return Err("Cookie key must be at least 32 bytes long");
+        }
+
+        Ok(CookieJar {
+            current: Key::derive(key),
+            previous: Vec::new(),
+        })
+    }
+
+    /// Adds previously used master keys, so cookies sealed with them are still accepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The old master keys, most recent first.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the updated `CookieJar`, or an error message if a key is too short.
+    pub fn with_previous_keys(mut self, keys: &[&[u8]]) -> Result<Self, &'static str> {
+        for key in keys {
+            if key.len() < MIN_KEY_LEN {
+                return Err("Cookie key must be at least 32 bytes long");
+            }
+            self.previous.push(Key::derive(key));
+        }
+        Ok(self)
+    }
+
+    /// Returns the current key followed by the previous keys.
+    fn keys(&self) -> impl Iterator<Item = &Key> {
+        std::iter::once(&self.current).chain(self.previous.iter())
+    }
+
+    /// Signs a cookie, appending an HMAC-SHA256 signature to its value.
+    ///
+    /// # Arguments
+    ///
+    /// * `cookie` - The cookie to sign; its attributes are kept unchanged.
+    ///
+    /// # Returns
+    ///
+    /// The cookie with a value of the form `<value>.<signature>`.
+    pub fn sign(&self, cookie: Cookie) -> Cookie {
+        let tag = self
+            .current
+            .mac(cookie.name(), cookie.value())
+            .finalize()
+            .into_bytes();
+        let value = format!("{}.{}", cookie.value(), URL_SAFE_NO_PAD.encode(tag));
+        cookie.with_value(value)
+    }
+
+    /// Verifies a signed cookie value against the current and previous keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie.
+    /// * `value` - The signed value sent by the client.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<String>` containing the original value, or `None` if the signature is invalid.
+    pub fn verify(&self, name: &str, value: &str) -> Option<String> {
+        let (plain, tag) = value.rsplit_once('.')?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+        self.keys()
+            .any(|key| key.mac(name, plain).verify_slice(&tag).is_ok())
+            .then(|| plain.to_string())
+    }
+
+    /// Encrypts a cookie with AES-256-GCM, binding it to its name.
+    ///
+    /// # Arguments
+    ///
+    /// * `cookie` - The cookie to encrypt; its attributes are kept unchanged.
+    ///
+    /// # Returns
+    ///
+    /// The cookie with its value replaced by the encoded nonce and ciphertext.
+    pub fn encrypt(&self, cookie: Cookie) -> Cookie {
+        let cipher = Aes256Gcm::new(&self.current.encryption.into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: cookie.value().as_bytes(),
+            aad: cookie.name().as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .expect("AES-GCM encryption does not fail for cookie-sized values");
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        let value = URL_SAFE_NO_PAD.encode(sealed);
+        cookie.with_value(value)
+    }
+
+    /// Decrypts an encrypted cookie value with the current and previous keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie.
+    /// * `value` - The encrypted value sent by the client.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<String>` containing the original value, or `None` if it cannot be authenticated.
+    pub fn decrypt(&self, name: &str, value: &str) -> Option<String> {
+        let sealed = URL_SAFE_NO_PAD.decode(value).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::from_slice(nonce);
+
+        self.keys().find_map(|key| {
+            let cipher = Aes256Gcm::new(&key.encryption.into());
+            let payload = Payload {
+                msg: ciphertext,
+                aad: name.as_bytes(),
+            };
+            let plain = cipher.decrypt(nonce, payload).ok()?;
+            String::from_utf8(plain).ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+    const OLD_KEY: &[u8] = b"fedcba9876543210fedcba9876543210";
+
+    #[test]
+    /// Tests signing and verifying cookies, including tampering, renaming and key rotation.
+    fn signed() {
+        let jar = CookieJar::new(KEY).unwrap();
+        let cookie = jar.sign(Cookie::new("user", "alice").path("/"));
+        assert!(cookie.value().starts_with("alice."));
+        assert!(cookie.to_string().ends_with("; Path=/"));
+        assert_eq!(
+            jar.verify("user", cookie.value()),
+            Some("alice".to_string())
+        );
+
+        let tampered = cookie.value().replacen("alice", "admin", 1);
+        assert_eq!(jar.verify("user", &tampered), None);
+        assert_eq!(jar.verify("other", cookie.value()), None);
+        assert_eq!(jar.verify("user", "alice"), None);
+
+        let old = CookieJar::new(OLD_KEY)
+            .unwrap()
+            .sign(Cookie::new("user", "bob"));
+        assert_eq!(jar.verify("user", old.value()), None);
+        let rotated = CookieJar::new(KEY)
+            .unwrap()
+            .with_previous_keys(&[OLD_KEY])
+            .unwrap();
+        assert_eq!(rotated.verify("user", old.value()), Some("bob".to_string()));
+    }
+
+    #[test]
+    /// Tests encrypting and decrypting cookies, including tampering, renaming and key rotation.
+    fn private() {
+        let jar = CookieJar::new(KEY).unwrap();
+        let cookie = jar.encrypt(Cookie::new("flash", "Saved!"));
+        assert!(!cookie.value().contains("Saved"));
+        assert_eq!(
+            jar.decrypt("flash", cookie.value()),
+            Some("Saved!".to_string())
+        );
+        assert_eq!(jar.decrypt("other", cookie.value()), None);
+        assert_eq!(jar.decrypt("flash", "garbage"), None);
+
+        let mut bytes = URL_SAFE_NO_PAD.decode(cookie.value()).unwrap();
+        bytes[NONCE_LEN] ^= 1;
+        assert_eq!(jar.decrypt("flash", &URL_SAFE_NO_PAD.encode(bytes)), None);
+
+        let rotated = CookieJar::new(OLD_KEY)
+            .unwrap()
+            .with_previous_keys(&[KEY])
+            .unwrap();
+        assert_eq!(
+            rotated.decrypt("flash", cookie.value()),
+            Some("Saved!".to_string())
+        );
+        assert!(CookieJar::new(b"too short").is_err());
+    }
+}
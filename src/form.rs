@@ -0,0 +1,177 @@
+use http::StatusCode;
+use std::error::Error;
+use std::fmt;
+
+/// The media type of URL-encoded form bodies.
+pub const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Represents a decoded `application/x-www-form-urlencoded` body.
+///
+/// Keys may appear more than once (e.g. checkboxes or multi-selects), so the
+/// fields are kept in the order they were sent.
+pub struct Form {
+    /// The decoded key/value pairs of the form.
+    fields: Vec<(String, String)>,
+}
+
+impl Form {
+    /// Decodes a URL-encoded form body.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The raw URL-encoded bytes, e.g. `name=John+Doe&tag=a&tag=b`.
+    ///
+    /// # Returns
+    ///
+    /// A `Form` containing every decoded key/value pair in order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rusticore::Form;
+    ///
+    /// let form = Form::parse(b"name=John+Doe&tag=a&tag=b");
+    /// assert_eq!(form.get("name"), Some("John Doe"));
+    /// assert_eq!(form.get_all("tag"), vec!["a", "b"]);
+    /// ```
+    pub fn parse(bytes: &[u8]) -> Self {
+        Form {
+            fields: form_urlencoded::parse(bytes).into_owned().collect(),
+        }
+    }
+
+    /// Returns the first value of a field.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The name of the field to look for.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&str>` containing the first value of the field if found, or `None` otherwise.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns every value of a field, in the order they were sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The name of the field to look for.
+    ///
+    /// # Returns
+    ///
+    /// A vector of string slices, empty if the field does not exist.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Checks whether the form contains a field.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The name of the field to look for.
+    ///
+    /// # Returns
+    ///
+    /// `true` if at least one value exists for the field, `false` otherwise.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.fields.iter().any(|(k, _)| k == key)
+    }
+
+    /// Returns an iterator over all key/value pairs of the form.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the number of key/value pairs in the form.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Checks whether the form has no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents the errors that can occur while reading a form from a request.
+pub enum FormError {
+    /// The `Content-Type` header is missing or is not `application/x-www-form-urlencoded`.
+    UnsupportedContentType,
+    /// The body is larger than the configured form size limit.
+    PayloadTooLarge {
+        /// The size of the body in bytes.
+        size: usize,
+        /// The maximum size allowed in bytes.
+        limit: usize,
+    },
+    #[cfg(feature = "form")]
+    /// The form could not be deserialized into the requested type.
+    Deserialize(String),
+}
+
+impl FormError {
+    /// Returns the HTTP status code a handler should respond with for this error.
+    ///
+    /// # Returns
+    ///
+    /// `415 Unsupported Media Type`, `413 Payload Too Large` or `400 Bad Request`.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            FormError::UnsupportedContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FormError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            #[cfg(feature = "form")]
+            FormError::Deserialize(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::UnsupportedContentType => {
+                write!(f, "expected Content-Type: {FORM_CONTENT_TYPE}")
+            }
+            FormError::PayloadTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "form body of {size} bytes exceeds the limit of {limit} bytes"
+                )
+            }
+            #[cfg(feature = "form")]
+            FormError::Deserialize(e) => write!(f, "failed to deserialize form: {e}"),
+        }
+    }
+}
+
+impl Error for FormError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests the decoding of a URL-encoded body into a `Form`.
+    /// It checks that percent-escapes and `+` are decoded and that repeated keys are all kept.
+    fn parse() {
+        let form = Form::parse(b"name=John+Doe&city=S%C3%A3o%20Paulo&tag=a&tag=b&empty=");
+        assert_eq!(form.len(), 5);
+        assert_eq!(form.get("name"), Some("John Doe"));
+        assert_eq!(form.get("city"), Some("São Paulo"));
+        assert_eq!(form.get("tag"), Some("a"));
+        assert_eq!(form.get_all("tag"), vec!["a", "b"]);
+        assert_eq!(form.get("empty"), Some(""));
+        assert!(!form.contains_key("missing"));
+        assert!(Form::parse(b"").is_empty());
+    }
+}
@@ -0,0 +1,513 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A range of IP addresses in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`.
+///
+/// # Examples
+///
+/// ```
+/// use rusticore::Cidr;
+///
+/// let private: Cidr = "10.0.0.0/8".parse().unwrap();
+/// assert!(private.contains("10.1.2.3".parse().unwrap()));
+/// assert!(!private.contains("192.0.2.1".parse().unwrap()));
+/// ```
+pub struct Cidr {
+    /// The first address of the range.
+    network: IpAddr,
+    /// The number of leading bits every address of the range shares.
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Creates a range from an address and a prefix length.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Any address in the range; the bits past the prefix are ignored.
+    /// * `prefix` - The prefix length, at most 32 for IPv4 and 128 for IPv6.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the range.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the prefix is longer than the address.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, &'static str> {
+        let network = match addr {
+            IpAddr::V4(ip) if prefix <= 32 => IpAddr::V4(Ipv4Addr::from(
+                u32::from(ip) & u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0),
+            )),
+            IpAddr::V6(ip) if prefix <= 128 => IpAddr::V6(Ipv6Addr::from(
+                u128::from(ip) & u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0),
+            )),
+            _ => return Err("The prefix is longer than the address"),
+        };
+        Ok(Cidr { network, prefix })
+    }
+
+    /// Checks whether an address is in the range. IPv4 addresses mapped into IPv6, as
+    /// reported by dual-stack listeners, match IPv4 ranges.
+    ///
+    /// # Arguments
+    ///
+    /// * `ip` - The address to check.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the address is in the range.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match Cidr::new(ip.to_canonical(), self.prefix) {
+            Ok(range) => range.network == self.network,
+            Err(_) => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = &'static str;
+
+    /// Parses a range such as `192.168.0.0/16`, or a single address such as `::1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| "Invalid address in the CIDR range")?;
+        let prefix = match prefix {
+            Some(prefix) if prefix.bytes().all(|b| b.is_ascii_digit()) => prefix
+                .parse::<u8>()
+                .map_err(|_| "Invalid prefix length in the CIDR range")?,
+            Some(_) => return Err("Invalid prefix length in the CIDR range"),
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Cidr::new(addr, prefix)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// The family of headers the trusted proxies write to describe the connections they forward.
+///
+/// Only that family is read: a proxy passes headers of the other family through unchanged, so
+/// they come from the client and could name any address.
+pub enum ForwardedHeaders {
+    /// The `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers, as written
+    /// by most proxies and load balancers by default.
+    #[default]
+    XForwarded,
+    /// The standard `Forwarded` header of RFC 7239.
+    Forwarded,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// What one proxy reported about the connection it received.
+pub(crate) struct Hop {
+    /// The address the connection came from, or `None` if it is unknown or obfuscated.
+    pub(crate) client: Option<IpAddr>,
+    /// The scheme the connection used, e.g. `https`.
+    pub(crate) proto: Option<String>,
+    /// The `Host` the connection asked for.
+    pub(crate) host: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The client connection seen through the trusted proxies in front of the server.
+pub(crate) struct Forwarded {
+    /// The address of the client, or of the first proxy that could not identify it, or
+    /// `None` if the trusted Unix socket peer did not report one.
+    pub(crate) client: Option<IpAddr>,
+    /// The scheme the client used, if a trusted proxy reported it.
+    pub(crate) proto: Option<String>,
+    /// The `Host` the client asked for, if a trusted proxy reported it.
+    pub(crate) host: Option<String>,
+}
+
+/// Walks the hops reported by the proxies, from the closest one outwards, as long as the
+/// address the information came from is trusted.
+///
+/// # Arguments
+///
+/// * `peer` - The address of the peer the request was received from, or `None` for a trusted
+///   peer without one, i.e. a proxy on a Unix socket.
+/// * `hops` - The hops in the order the proxies appended them, the closest proxy's last.
+/// * `trusted` - The ranges of the trusted proxies.
+///
+/// # Returns
+///
+/// The client connection, as far as the trusted proxies can tell.
+pub(crate) fn resolve(peer: Option<IpAddr>, hops: Vec<Hop>, trusted: &[Cidr]) -> Forwarded {
+    let mut forwarded = Forwarded {
+        client: peer.map(|peer| peer.to_canonical()),
+        proto: None,
+        host: None,
+    };
+    for hop in hops.into_iter().rev() {
+        if let Some(client) = forwarded.client
+            && !trusted.iter().any(|range| range.contains(client))
+        {
+            break;
+        }
+        // A proxy that only reports some details keeps those of the proxies behind it.
+        forwarded.proto = hop.proto.or(forwarded.proto);
+        forwarded.host = hop.host.or(forwarded.host);
+        match hop.client {
+            Some(client) => forwarded.client = Some(client.to_canonical()),
+            None => break,
+        }
+    }
+    forwarded
+}
+
+/// Parses the `Forwarded` headers of a request (RFC 7239).
+///
+/// # Arguments
+///
+/// * `values` - The values of every `Forwarded` header, in order.
+///
+/// # Returns
+///
+/// The hops in the order they were appended.
+pub(crate) fn parse_forwarded(values: &[&str]) -> Vec<Hop> {
+    values
+        .iter()
+        .flat_map(|value| split_unquoted(value, ','))
+        .map(|element| {
+            let mut hop = Hop::default();
+            for pair in split_unquoted(element, ';') {
+                let Some((name, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                let value = unquote(value.trim());
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "for" => hop.client = parse_node(&value),
+                    "proto" => hop.proto = parse_scheme(&value),
+                    "host" => hop.host = parse_host(&value),
+                    _ => {}
+                }
+            }
+            hop
+        })
+        .collect()
+}
+
+/// Parses the `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers of a
+/// request, matching their lists up from the end.
+///
+/// # Arguments
+///
+/// * `clients` - The values of every `X-Forwarded-For` header, in order.
+/// * `protos` - The values of every `X-Forwarded-Proto` header, in order.
+/// * `hosts` - The values of every `X-Forwarded-Host` header, in order.
+///
+/// # Returns
+///
+/// The hops in the order they were appended.
+pub(crate) fn parse_x_forwarded(clients: &[&str], protos: &[&str], hosts: &[&str]) -> Vec<Hop> {
+    let list = |values: &[&str]| -> Vec<String> {
+        values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|item| item.trim().to_string())
+            .collect()
+    };
+    let (clients, protos, hosts) = (list(clients), list(protos), list(hosts));
+    let length = clients.len().max(protos.len()).max(hosts.len());
+    // The last item of each list belongs to the closest proxy.
+    let from_end = |items: &[String], i: usize| -> Option<String> {
+        let index = items.len().checked_sub(length - i)?;
+        items.get(index).cloned()
+    };
+    (0..length)
+        .map(|i| Hop {
+            client: from_end(&clients, i).and_then(|client| parse_node(&client)),
+            proto: from_end(&protos, i).and_then(|proto| parse_scheme(&proto)),
+            host: from_end(&hosts, i).and_then(|host| parse_host(&host)),
+        })
+        .collect()
+}
+
+/// Splits a header value at a separator, except inside quoted strings.
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if c == separator && !quoted => {
+                parts.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(value[start..].trim());
+    parts.into_iter().filter(|part| !part.is_empty()).collect()
+}
+
+/// Removes the quotes and escapes of a quoted string, leaving a token as it is.
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => {
+            let mut unquoted = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                unquoted.extend(if c == '\\' { chars.next() } else { Some(c) });
+            }
+            unquoted
+        }
+        None => value.to_string(),
+    }
+}
+
+/// Parses the address of a node, e.g. `192.0.2.43`, `192.0.2.43:47011`, `[2001:db8::17]:4711`
+/// or a bare IPv6 address. `unknown` and obfuscated identifiers such as `_hidden` give `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, port) = rest.split_once(']')?;
+        if !port.is_empty() && !port.starts_with(':') {
+            return None;
+        }
+        return ip.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    let (ip, _port) = node.split_once(':')?;
+    ip.parse::<Ipv4Addr>().ok().map(IpAddr::V4)
+}
+
+/// Parses a URI scheme, returning it in lower case.
+fn parse_scheme(scheme: &str) -> Option<String> {
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then(|| scheme.to_ascii_lowercase())
+}
+
+/// Checks that a host, with an optional port, cannot smuggle anything else into a URL.
+fn parse_host(host: &str) -> Option<String> {
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':' | '[' | ']'));
+    valid.then(|| host.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests parsing CIDR ranges and matching addresses against them.
+    fn cidr_ranges() {
+        let range: Cidr = "192.168.1.77/24".parse().unwrap();
+        assert!(range.contains("192.168.1.1".parse().unwrap()));
+        assert!(range.contains("::ffff:192.168.1.1".parse().unwrap()));
+        assert!(!range.contains("192.168.2.1".parse().unwrap()));
+        let range: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(range.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!range.contains("10.0.0.1".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        assert!("::1"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains("::1".parse().unwrap()));
+        for invalid in ["10.0.0.0/33", "10.0.0.0/+8", "10.0.0/8", "::/129", ""] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    /// Tests walking the hops of `Forwarded` and `X-Forwarded-*` headers up to the first
+    /// untrusted address.
+    fn resolve_hops() {
+        let trusted = ["10.0.0.0/8".parse::<Cidr>().unwrap()];
+        let peer = Some("10.0.0.1".parse().unwrap());
+
+        let hops = parse_forwarded(&[
+            r#"for=192.0.2.60;proto=http, for="[2001:db8::17]:4711""#,
+            r#"for=10.0.0.2;proto=HTTPS;host="example.com:8443""#,
+        ]);
+        let forwarded = resolve(peer, hops, &trusted);
+        assert_eq!(forwarded.client, "2001:db8::17".parse().ok());
+        assert_eq!(forwarded.proto.as_deref(), Some("https"));
+        assert_eq!(forwarded.host.as_deref(), Some("example.com:8443"));
+
+        // An obfuscated client stops the walk at the proxy that hid it.
+        let hops = parse_forwarded(&["for=_hidden, for=10.0.0.2"]);
+        let forwarded = resolve(peer, hops, &trusted);
+        assert_eq!(forwarded.client, "10.0.0.2".parse().ok());
+
+        let hops = parse_x_forwarded(&["203.0.113.9, 198.51.100.7", "10.0.0.3"], &["https"], &[]);
+        let forwarded = resolve(peer, hops.clone(), &trusted);
+        assert_eq!(forwarded.client, "198.51.100.7".parse().ok());
+        assert_eq!(forwarded.proto.as_deref(), Some("https"));
+        assert_eq!(forwarded.host, None);
+
+        // Nothing an untrusted peer sends is believed.
+        let forwarded = resolve("192.0.2.1".parse().ok(), hops, &trusted);
+        assert_eq!(forwarded.client, "192.0.2.1".parse().ok());
+        assert_eq!(forwarded.proto, None);
+    }
+
+    #[tokio::test]
+    /// Tests the client address, scheme and host of requests from trusted and untrusted peers.
+    async fn request_behind_trusted_proxy() {
+        use crate::{Request, Response, Route, Server};
+        use http::StatusCode;
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpStream;
+
+        /// Responds with what the request says about the client.
+        async fn whoami(req: &mut Request, res: &mut Response) {
+            let body = format!("{:?} {} {:?}", req.client_ip(), req.scheme(), req.host());
+            res.text(&body, StatusCode::OK).await;
+        }
+
+        /// Sends a request with forwarding headers and returns the response body.
+        async fn get(addr: std::net::SocketAddr) -> String {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client
+                .write_all(
+                    b"GET /whoami HTTP/1.1\r\nHost: internal:8080\r\nConnection: close\r\n\
+                    Forwarded: for=198.51.100.7;proto=https;host=example.com\r\n\
+                    X-Forwarded-For: 203.0.113.9\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            response
+                .split("\r\n\r\n")
+                .nth(1)
+                .unwrap_or_default()
+                .to_string()
+        }
+
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.trusted_proxies = vec!["127.0.0.0/8".parse().unwrap()];
+        server.forwarded_headers = ForwardedHeaders::Forwarded;
+        server
+            .add_route(Route::new(
+                "GET",
+                "/whoami",
+                Arc::new(|req, res| Box::pin(whoami(req, res))),
+            ))
+            .await;
+        server.start().await.unwrap();
+        let addr = server.local_addr().unwrap();
+        assert_eq!(
+            get(addr).await,
+            r#"Some(198.51.100.7) https Some("example.com")"#
+        );
+        server.shutdown().await;
+
+        // Behind a proxy that only writes `X-Forwarded-For`, `Forwarded` is the client's own.
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.trusted_proxies = vec!["127.0.0.0/8".parse().unwrap()];
+        server
+            .add_route(Route::new(
+                "GET",
+                "/whoami",
+                Arc::new(|req, res| Box::pin(whoami(req, res))),
+            ))
+            .await;
+        server.start().await.unwrap();
+        let addr = server.local_addr().unwrap();
+        assert_eq!(
+            get(addr).await,
+            r#"Some(203.0.113.9) http Some("internal:8080")"#
+        );
+        server.shutdown().await;
+
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        server
+            .add_route(Route::new(
+                "GET",
+                "/whoami",
+                Arc::new(|req, res| Box::pin(whoami(req, res))),
+            ))
+            .await;
+        server.start().await.unwrap();
+        let addr = server.local_addr().unwrap();
+        assert_eq!(
+            get(addr).await,
+            r#"Some(127.0.0.1) http Some("internal:8080")"#
+        );
+        server.shutdown().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    /// Tests that a proxy on a Unix socket is only believed on a listener that trusts its peers.
+    async fn request_behind_unix_proxy() {
+        use crate::{ListenerConfig, Request, Response, Route, Server, UnixSocket};
+        use http::StatusCode;
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::UnixStream;
+
+        /// Responds with what the request says about the client.
+        async fn whoami(req: &mut Request, res: &mut Response) {
+            let body = format!("{:?} {} {:?}", req.client_ip(), req.scheme(), req.host());
+            res.text(&body, StatusCode::OK).await;
+        }
+
+        /// Sends a request with forwarding headers over a Unix socket and returns the body.
+        async fn get(path: &std::path::Path) -> String {
+            let mut client = UnixStream::connect(path).await.unwrap();
+            client
+                .write_all(
+                    b"GET /whoami HTTP/1.1\r\nHost: internal\r\nConnection: close\r\n\
+                    X-Forwarded-For: 198.51.100.7\r\nX-Forwarded-Proto: https\r\n\
+                    X-Forwarded-Host: example.com\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            response
+                .split("\r\n\r\n")
+                .nth(1)
+                .unwrap_or_default()
+                .to_string()
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let trusted = dir.path().join("trusted.sock");
+        let untrusted = dir.path().join("untrusted.sock");
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.listen_on_host = false;
+        server.listeners = vec![
+            ListenerConfig::unix(UnixSocket::new(&trusted)).trust_unix_peers(true),
+            ListenerConfig::unix(UnixSocket::new(&untrusted)),
+        ];
+        server
+            .add_route(Route::new(
+                "GET",
+                "/whoami",
+                Arc::new(|req, res| Box::pin(whoami(req, res))),
+            ))
+            .await;
+        server.start().await.unwrap();
+
+        assert_eq!(
+            get(&trusted).await,
+            r#"Some(198.51.100.7) https Some("example.com")"#
+        );
+        assert_eq!(get(&untrusted).await, r#"None http Some("internal")"#);
+        server.shutdown().await;
+    }
+}
@@ -0,0 +1,646 @@
+use crate::server::ConnectionInfo;
+use crate::Server;
+use bytes::Bytes;
+use futures::future::poll_fn;
+use h2::server::SendResponse;
+use h2::{Reason, RecvStream, SendStream};
+use http::header::{HeaderName, HeaderValue};
+use http::{HeaderMap, Method, StatusCode};
+use log::info;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{
+    duplex, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+    DuplexStream, ReadHalf,
+};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+/// The connection preface every HTTP/2 client sends first.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The size in bytes of the in-memory pipe between a stream and its handler.
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// The headers that only apply to a single HTTP/1.1 connection, which HTTP/2 forbids.
+const CONNECTION_HEADERS: [&str; 6] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+    "te",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The limits of HTTP/2 connections.
+pub struct Http2Config {
+    /// The maximum number of streams a client may have open at once on one connection.
+    pub max_concurrent_streams: u32,
+    /// The flow control window of each stream, in bytes.
+    pub initial_window_size: u32,
+    /// The flow control window of the whole connection, in bytes.
+    pub initial_connection_window_size: u32,
+    /// The largest frame payload accepted, in bytes.
+    pub max_frame_size: u32,
+    /// The largest decoded header list accepted, in bytes.
+    pub max_header_list_size: u32,
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Http2Config {
+            max_concurrent_streams: 100,
+            initial_window_size: 1024 * 1024,
+            initial_connection_window_size: 4 * 1024 * 1024,
+            max_frame_size: 16 * 1024,
+            max_header_list_size: 16 * 1024,
+        }
+    }
+}
+
+/// Checks whether a plain connection starts with the HTTP/2 preface, i.e. an `h2c` client with
+/// prior knowledge, without consuming any bytes.
+pub(crate) async fn has_preface(stream: &TcpStream) -> bool {
+    let mut buffer = [0u8; PREFACE.len()];
+    loop {
+        let n = match stream.peek(&mut buffer).await {
+            Ok(n) => n,
+            Err(_) => return false,
+        };
+        if n == 0 || buffer[..n] != PREFACE[..n] {
+            return false;
+        }
+        if n == PREFACE.len() {
+            return true;
+        }
+        // Only part of the preface has arrived; peeking again right away would spin.
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    }
+}
+
+/// Serves an HTTP/2 connection until the client closes it or the server shuts down.
+///
+/// Every stream is handed to the routes as an HTTP/1.1 exchange over an in-memory pipe, so
+/// handlers, middlewares and response helpers work unchanged; the `h2` crate takes care of
+/// multiplexing, flow control and header compression.
+///
+/// # Arguments
+///
+/// * `server` - The server the connection was accepted by.
+/// * `io` - The connection, after the TLS handshake if any.
+/// * `info` - What is known about the connection, shared by all its requests.
+pub(crate) async fn serve<T>(server: Arc<Server>, io: T, info: ConnectionInfo)
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let target = server.get_target();
+    let config = server.http2;
+    let mut builder = h2::server::Builder::new();
+    builder
+        .max_concurrent_streams(config.max_concurrent_streams)
+        .initial_window_size(config.initial_window_size)
+        .initial_connection_window_size(config.initial_connection_window_size)
+        .max_frame_size(config.max_frame_size)
+        .max_header_list_size(config.max_header_list_size);
+
+    let mut connection = match builder.handshake::<_, Bytes>(io).await {
+        Ok(connection) => connection,
+        Err(e) => {
+            info!(target: target, "HTTP/2 handshake failed: {e}");
+            return;
+        }
+    };
+
+    let mut shutdown = server.shutdown_signal();
+    let mut streams = JoinSet::new();
+    let mut closing = false;
+    loop {
+        // Accepting also drives the connection, so it keeps going until every stream is done.
+        let accepted = tokio::select! {
+            accepted = connection.accept() => accepted,
+            _ = shutdown.wait_for(|stop| *stop), if !closing => {
+                connection.graceful_shutdown();
+                closing = true;
+                continue;
+            }
+        };
+        match accepted {
+            Some(Ok((request, respond))) => {
+                streams.spawn(serve_stream(server.clone(), request, respond, info.clone()));
+            }
+            Some(Err(e)) => {
+                info!(target: target, "HTTP/2 connection error: {e}");
+                break;
+            }
+            None => break,
+        }
+        while streams.try_join_next().is_some() {}
+    }
+    while streams.join_next().await.is_some() {}
+}
+
+/// Serves one HTTP/2 stream by running the routes against an HTTP/1.1 rendering of it.
+async fn serve_stream(
+    server: Arc<Server>,
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    info: ConnectionInfo,
+) {
+    let (parts, mut body) = request.into_parts();
+    let head_only = parts.method == Method::HEAD;
+
+    // Bodies of unknown length are buffered, since `Request` needs a `Content-Length`.
+    let content_length = parts
+        .headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<usize>().ok());
+    let buffered = match content_length {
+        Some(_) => None,
+        None if body.is_end_stream() => Some(Vec::new()),
+        None => match read_body(&mut body, server.max_body_size).await {
+            Ok(buffered) => Some(buffered),
+            Err(status) => {
+                let _ = respond.send_response(empty_response(status), true);
+                return;
+            }
+        },
+    };
+
+    let mut head = format!(
+        "{} {} HTTP/2\r\n",
+        parts.method,
+        parts.uri.path_and_query().map_or("/", |p| p.as_str())
+    );
+    if !parts.headers.contains_key(http::header::HOST)
+        && let Some(authority) = parts.uri.authority()
+    {
+        head.push_str(&format!("Host: {authority}\r\n"));
+    }
+    for (name, value) in parts.headers.iter() {
+        if let Ok(value) = value.to_str() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    if let Some(ref buffered) = buffered {
+        head.push_str(&format!("Content-Length: {}\r\n", buffered.len()));
+    }
+    head.push_str("\r\n");
+
+    let (client, handler_end) = duplex(PIPE_SIZE);
+    let (reader, mut writer) = tokio::io::split(client);
+    let handler = tokio::spawn(Server::handle_connection(server, handler_end, info));
+
+    // The request is written while the response is read, so large bodies cannot deadlock the
+    // pipe. The writing half stays open until the stream ends, since handlers treat a closed
+    // connection as a client that went away.
+    let write_request = async {
+        writer.write_all(head.as_bytes()).await?;
+        match buffered {
+            Some(buffered) => writer.write_all(&buffered).await?,
+            None => {
+                while let Some(data) = body.data().await {
+                    let data = data.map_err(io::Error::other)?;
+                    let _ = body.flow_control().release_capacity(data.len());
+                    writer.write_all(&data).await?;
+                }
+            }
+        }
+        writer.flush().await
+    };
+    let forward_response = forward_response(reader, &mut respond, head_only);
+    let (_, forwarded) = tokio::join!(write_request, forward_response);
+    if forwarded.is_err() {
+        respond.send_reset(Reason::INTERNAL_ERROR);
+    }
+
+    drop(writer);
+    let _ = handler.await;
+}
+
+/// Reads a whole request body, up to a limit.
+async fn read_body(body: &mut RecvStream, limit: usize) -> Result<Vec<u8>, StatusCode> {
+    let mut buffered = Vec::new();
+    while let Some(data) = body.data().await {
+        let data = data.map_err(|_| StatusCode::BAD_REQUEST)?;
+        let _ = body.flow_control().release_capacity(data.len());
+        if buffered.len() + data.len() > limit {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        buffered.extend_from_slice(&data);
+    }
+    Ok(buffered)
+}
+
+/// Builds a response without headers.
+fn empty_response(status: StatusCode) -> http::Response<()> {
+    let mut response = http::Response::new(());
+    *response.status_mut() = status;
+    response
+}
+
+/// How the body of a response written by a handler is delimited.
+enum Framing {
+    /// No body follows the head.
+    Empty,
+    /// A body of a known length.
+    Length(u64),
+    /// A `Transfer-Encoding: chunked` body.
+    Chunked,
+    /// A body ending when the handler closes the connection.
+    Close,
+}
+
+/// Reads the HTTP/1.1 response written by a handler and sends it as an HTTP/2 response.
+///
+/// # Returns
+///
+/// An `io::Result` indicating whether the whole response was sent. If the client resets the
+/// stream, the handler is left to notice the closed connection.
+async fn forward_response(
+    reader: ReadHalf<DuplexStream>,
+    respond: &mut SendResponse<Bytes>,
+    head_only: bool,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        // No route matched, so the handler wrote nothing.
+        let _ = respond.send_response(empty_response(StatusCode::NOT_FOUND), true);
+        return Ok(());
+    }
+    let status = line
+        .split(' ')
+        .nth(1)
+        .and_then(|code| StatusCode::from_bytes(code.as_bytes()).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid status line"))?;
+
+    let mut headers = HeaderMap::new();
+    let mut framing = Framing::Close;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim().to_ascii_lowercase(), value.trim());
+        if name == "transfer-encoding" && value.eq_ignore_ascii_case("chunked") {
+            framing = Framing::Chunked;
+        } else if name == "content-length"
+            && let Ok(len) = value.parse::<u64>()
+            && !matches!(framing, Framing::Chunked)
+        {
+            framing = Framing::Length(len);
+        }
+        if CONNECTION_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.append(name, value);
+        }
+    }
+    if head_only
+        || status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || matches!(framing, Framing::Length(0))
+    {
+        framing = Framing::Empty;
+    }
+
+    let mut response = empty_response(status);
+    *response.headers_mut() = headers;
+    let end_of_stream = matches!(framing, Framing::Empty);
+    let mut send = respond
+        .send_response(response, end_of_stream)
+        .map_err(io::Error::other)?;
+
+    let mut buffer = vec![0u8; 16 * 1024];
+    match framing {
+        Framing::Empty => {}
+        Framing::Length(mut remaining) => {
+            while remaining > 0 {
+                let max = buffer.len().min(remaining as usize);
+                let n = read_or_reset(&mut reader, &mut buffer[..max], &mut send).await?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                remaining -= n as u64;
+                send_data(
+                    &mut send,
+                    Bytes::copy_from_slice(&buffer[..n]),
+                    remaining == 0,
+                )
+                .await?;
+            }
+        }
+        Framing::Close => loop {
+            let n = read_or_reset(&mut reader, &mut buffer, &mut send).await?;
+            if n == 0 {
+                send_data(&mut send, Bytes::new(), true).await?;
+                break;
+            }
+            send_data(&mut send, Bytes::copy_from_slice(&buffer[..n]), false).await?;
+        },
+        Framing::Chunked => loop {
+            line.clear();
+            let read = tokio::select! {
+                read = reader.read_line(&mut line) => read?,
+                reason = poll_fn(|cx| send.poll_reset(cx)) => return reset(reason),
+            };
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let size = line.trim_end().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size.trim(), 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid chunk size"))?;
+            if size == 0 {
+                let trailers = read_trailers(&mut reader).await?;
+                if trailers.is_empty() {
+                    send_data(&mut send, Bytes::new(), true).await?;
+                } else {
+                    send.send_trailers(trailers).map_err(io::Error::other)?;
+                }
+                break;
+            }
+            let mut chunk = vec![0u8; size];
+            reader.read_exact(&mut chunk).await?;
+            let mut crlf = [0u8; 2];
+            reader.read_exact(&mut crlf).await?;
+            send_data(&mut send, Bytes::from(chunk), false).await?;
+        },
+    }
+    Ok(())
+}
+
+/// Reads from the handler, unless the client resets the stream first.
+async fn read_or_reset<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    send: &mut SendStream<Bytes>,
+) -> io::Result<usize> {
+    tokio::select! {
+        n = reader.read(buffer) => n,
+        reason = poll_fn(|cx| send.poll_reset(cx)) => reset(reason),
+    }
+}
+
+/// Turns the reset of a stream by the client into an error ending the response.
+fn reset<T>(reason: Result<Reason, h2::Error>) -> io::Result<T> {
+    let message = match reason {
+        Ok(reason) => format!("Stream reset by client: {reason}"),
+        Err(e) => e.to_string(),
+    };
+    Err(io::Error::new(io::ErrorKind::ConnectionAborted, message))
+}
+
+/// Reads the trailer section ending a chunked body.
+async fn read_trailers<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> io::Result<HeaderMap> {
+    let mut trailers = HeaderMap::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(trailers);
+        }
+        let trailer = line.trim_end_matches(['\r', '\n']);
+        if trailer.is_empty() {
+            return Ok(trailers);
+        }
+        if let Some((name, value)) = trailer.split_once(':')
+            && let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.trim().to_ascii_lowercase().as_bytes()),
+                HeaderValue::from_str(value.trim()),
+            )
+        {
+            trailers.append(name, value);
+        }
+    }
+}
+
+/// Sends data on a stream, waiting for the client's flow control window to allow it.
+async fn send_data(send: &mut SendStream<Bytes>, mut data: Bytes, end: bool) -> io::Result<()> {
+    if data.is_empty() {
+        return send.send_data(data, end).map_err(io::Error::other);
+    }
+    while !data.is_empty() {
+        send.reserve_capacity(data.len());
+        let capacity = match poll_fn(|cx| send.poll_capacity(cx)).await {
+            Some(capacity) => capacity.map_err(io::Error::other)?,
+            None => return Err(io::ErrorKind::BrokenPipe.into()),
+        };
+        let chunk = data.split_to(capacity.min(data.len()));
+        send.send_data(chunk, end && data.is_empty())
+            .map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Request, Response, Route};
+    use h2::client::SendRequest;
+
+    /// Echoes the request body along with the HTTP version and `Host` header.
+    async fn echo(req: &mut Request, res: &mut Response) {
+        let body = format!(
+            "{} {} {}",
+            req.http_version(),
+            req.get_header("Host").unwrap_or_default(),
+            String::from_utf8_lossy(req.body())
+        );
+        res.text(&body, StatusCode::OK).await;
+    }
+
+    /// Streams numbered lines, ending with a trailer.
+    async fn stream(_req: &mut Request, res: &mut Response) {
+        res.set_header("Trailer", "X-Lines");
+        let mut body = res
+            .start_stream("text/plain", StatusCode::OK)
+            .await
+            .unwrap();
+        for line in 0..100 {
+            body.write(format!("line {line}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+        body.finish_with_trailers(&[("X-Lines", "100")])
+            .await
+            .unwrap();
+    }
+
+    /// Starts a server with the `echo` and `stream` routes and connects an `h2c` client to it.
+    async fn connect() -> (Server, SendRequest<Bytes>) {
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server
+            .add_routes(vec![
+                Route::new(
+                    "POST",
+                    "/echo",
+                    Arc::new(|req, res| Box::pin(echo(req, res))),
+                ),
+                Route::new(
+                    "GET",
+                    "/stream",
+                    Arc::new(|req, res| Box::pin(stream(req, res))),
+                ),
+            ])
+            .await;
+        server.start().await.unwrap();
+
+        let tcp = TcpStream::connect(server.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (client, connection) = h2::client::handshake(tcp).await.unwrap();
+        tokio::spawn(connection);
+        (server, client)
+    }
+
+    /// Sends a request and returns the response with its whole body.
+    async fn send(
+        client: &mut SendRequest<Bytes>,
+        method: &str,
+        path: &str,
+        body: &'static [u8],
+    ) -> (http::response::Parts, Vec<u8>, Option<HeaderMap>) {
+        let request = http::Request::builder()
+            .method(method)
+            .uri(format!("http://localhost{path}"))
+            .body(())
+            .unwrap();
+        let (response, mut send) = client
+            .clone()
+            .ready()
+            .await
+            .unwrap()
+            .send_request(request, body.is_empty())
+            .unwrap();
+        if !body.is_empty() {
+            send.send_data(Bytes::from_static(body), true).unwrap();
+        }
+        let (parts, mut recv) = response.await.unwrap().into_parts();
+        let mut received = Vec::new();
+        while let Some(data) = recv.data().await {
+            let data = data.unwrap();
+            recv.flow_control().release_capacity(data.len()).unwrap();
+            received.extend_from_slice(&data);
+        }
+        let trailers = recv.trailers().await.unwrap();
+        (parts, received, trailers)
+    }
+
+    #[tokio::test]
+    /// Tests that a client stalling partway through the preface is disconnected.
+    async fn stalled_preface() {
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.keep_alive_timeout = std::time::Duration::from_millis(100);
+        server.start().await.unwrap();
+
+        let mut tcp = TcpStream::connect(server.local_addr().unwrap())
+            .await
+            .unwrap();
+        tcp.write_all(b"PRI").await.unwrap();
+        let mut buffer = [0u8; 16];
+        let read = tokio::time::timeout(std::time::Duration::from_secs(5), tcp.read(&mut buffer))
+            .await
+            .expect("The stalled connection was not closed");
+        assert_eq!(read.unwrap_or(0), 0);
+    }
+
+    #[tokio::test]
+    /// Tests serving concurrent `h2c` streams with prior knowledge, including request bodies
+    /// without a `Content-Length` and unmatched paths.
+    async fn serve_h2c() {
+        let (server, mut client) = connect().await;
+
+        let (parts, body, _) = send(&mut client, "GET", "/", b"").await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert!(parts.headers.get("connection").is_none());
+        assert!(!body.is_empty());
+
+        let (parts, _, _) = send(&mut client, "GET", "/missing", b"").await;
+        assert_eq!(parts.status, StatusCode::NOT_FOUND);
+
+        let requests = (0..10).map(|_| {
+            let mut client = client.clone();
+            async move { send(&mut client, "POST", "/echo", b"ping").await }
+        });
+        for (parts, body, _) in futures::future::join_all(requests).await {
+            assert_eq!(parts.status, StatusCode::OK);
+            assert_eq!(body, b"HTTP/2 localhost ping");
+        }
+
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    /// Tests that streamed responses are forwarded as DATA frames followed by trailers.
+    async fn stream_response() {
+        let (server, mut client) = connect().await;
+
+        let (parts, body, trailers) = send(&mut client, "GET", "/stream", b"").await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert!(parts.headers.get("transfer-encoding").is_none());
+        let expected: String = (0..100).map(|line| format!("line {line}\n")).collect();
+        assert_eq!(body, expected.as_bytes());
+        assert_eq!(trailers.unwrap().get("x-lines").unwrap(), "100");
+
+        server.shutdown().await;
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    /// Tests that TLS clients offering `h2` over ALPN are served HTTP/2.
+    async fn serve_alpn() {
+        use rustls::pki_types::ServerName;
+        use rustls::{ClientConfig, RootCertStore};
+        use tokio_rustls::TlsConnector;
+
+        let dir = tempfile::tempdir().unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let (cert_path, key_path) = (dir.path().join("cert.pem"), dir.path().join("key.pem"));
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.tls = Some(crate::TlsConfig::from_pem_files(&cert_path, &key_path).unwrap());
+        server.start().await.unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let mut config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let tcp = TcpStream::connect(server.local_addr().unwrap())
+            .await
+            .unwrap();
+        let tls = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap();
+        assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let (mut client, connection) = h2::client::handshake(tls).await.unwrap();
+        tokio::spawn(connection);
+        let (parts, body, _) = send(&mut client, "GET", "/", b"").await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert!(!body.is_empty());
+
+        server.shutdown().await;
+    }
+}
@@ -0,0 +1,445 @@
+use bytes::Bytes;
+use futures::Stream;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What the hub does when a subscriber's queue is full.
+pub enum SlowConsumer {
+    /// Unsubscribe the subscriber from every topic. Its `Subscription::recv` returns the queued
+    /// messages and then `None`, and `Subscription::was_dropped` returns `true`.
+    Disconnect,
+    /// Skip the message for this subscriber only, counting it in `Subscription::missed`.
+    SkipMessage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The configuration of a `Hub`.
+pub struct HubConfig {
+    /// The number of messages queued per subscriber before it counts as a slow consumer.
+    pub queue_capacity: usize,
+    /// What happens to slow consumers.
+    pub slow_consumer: SlowConsumer,
+    /// The log target slow consumers are reported under. The server's hub logs under the
+    /// server's target, so its warnings follow the server's `debug` setting.
+    pub log_target: &'static str,
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        HubConfig {
+            queue_capacity: 64,
+            slow_consumer: SlowConsumer::Disconnect,
+            log_target: "app::core",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A message published on a topic.
+pub struct HubMessage {
+    /// The topic the message was published on.
+    pub topic: Arc<str>,
+    /// The payload of the message, shared between all subscribers.
+    pub payload: Bytes,
+}
+
+impl HubMessage {
+    /// Returns the payload as text, or `None` if it is not valid UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.payload).ok()
+    }
+}
+
+#[derive(Debug)]
+/// The hub-side state of one subscriber.
+struct Subscriber {
+    /// The sending end of the subscriber's queue.
+    sender: mpsc::Sender<HubMessage>,
+    /// The topics the subscriber listens to.
+    topics: HashSet<Arc<str>>,
+    /// The number of messages skipped because the queue was full.
+    missed: Arc<AtomicU64>,
+    /// Whether the subscriber was disconnected for being too slow.
+    dropped: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Default)]
+/// The subscribers and topics of a hub.
+struct Registry {
+    /// The subscribers, keyed by ID.
+    subscribers: HashMap<u64, Subscriber>,
+    /// The IDs of the subscribers of every topic.
+    topics: HashMap<Arc<str>, HashSet<u64>>,
+}
+
+impl Registry {
+    /// Removes a subscriber from every topic, closing its queue.
+    fn remove(&mut self, id: u64) {
+        let Some(subscriber) = self.subscribers.remove(&id) else {
+            return;
+        };
+        for topic in subscriber.topics.iter() {
+            if let Some(ids) = self.topics.get_mut(topic) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.topics.remove(topic);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// The state shared by every handle to a hub.
+struct HubInner {
+    /// The configuration of the hub.
+    config: HubConfig,
+    /// The subscribers and topics.
+    registry: Mutex<Registry>,
+    /// The ID given to the next subscriber.
+    next_id: AtomicU64,
+    /// Whether the hub has been closed.
+    closed: AtomicBool,
+}
+
+#[derive(Debug, Clone)]
+/// An in-process publish/subscribe hub for fanning messages out to long-lived connections,
+/// such as WebSockets and Server-Sent Events.
+///
+/// Every subscriber has a bounded queue, so publishing never waits for slow clients; what
+/// happens when a queue is full is set by `HubConfig::slow_consumer`. The server's hub is
+/// closed when the server shuts down, which ends every subscription.
+///
+/// # Examples
+///
+/// ```
+/// use rusticore::Hub;
+///
+/// # async fn run() {
+/// let hub = Hub::default();
+/// let mut subscription = hub.subscribe("news");
+/// assert_eq!(hub.presence("news"), 1);
+///
+/// hub.publish("news", "hello");
+/// let message = subscription.recv().await.unwrap();
+/// assert_eq!(message.text(), Some("hello"));
+/// # }
+/// ```
+pub struct Hub {
+    /// The shared state.
+    inner: Arc<HubInner>,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Hub::new(HubConfig::default())
+    }
+}
+
+impl Hub {
+    /// Creates a new, empty `Hub`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The queue capacity and slow consumer policy.
+    ///
+    /// # Returns
+    ///
+    /// A new `Hub` instance.
+    pub fn new(config: HubConfig) -> Self {
+        Hub {
+            inner: Arc::new(HubInner {
+                config,
+                registry: Mutex::new(Registry::default()),
+                next_id: AtomicU64::new(0),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Locks the registry.
+    fn registry(&self) -> MutexGuard<'_, Registry> {
+        self.inner
+            .registry
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Subscribes a new subscriber to a topic.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The first topic to listen to. More can be added with `Subscription::subscribe`.
+    ///
+    /// # Returns
+    ///
+    /// A `Subscription` receiving the messages published from now on. If the hub is closed,
+    /// the subscription is closed too.
+    pub fn subscribe(&self, topic: &str) -> Subscription {
+        let (sender, receiver) = mpsc::channel(self.inner.config.queue_capacity.max(1));
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let missed = Arc::new(AtomicU64::new(0));
+        let dropped = Arc::new(AtomicBool::new(false));
+        let subscription = Subscription {
+            id,
+            hub: self.clone(),
+            receiver,
+            missed: missed.clone(),
+            dropped: dropped.clone(),
+        };
+
+        let mut registry = self.registry();
+        // Checked under the lock, so a concurrent `close` cannot miss this subscriber.
+        if self.is_closed() {
+            return subscription;
+        }
+        registry.subscribers.insert(
+            id,
+            Subscriber {
+                sender,
+                topics: HashSet::new(),
+                missed,
+                dropped,
+            },
+        );
+        drop(registry);
+        self.add_topic(id, topic);
+        subscription
+    }
+
+    /// Adds a topic to a subscriber.
+    fn add_topic(&self, id: u64, topic: &str) {
+        let mut registry = self.registry();
+        let Some(subscriber) = registry.subscribers.get_mut(&id) else {
+            return;
+        };
+        let topic: Arc<str> = Arc::from(topic);
+        subscriber.topics.insert(topic.clone());
+        registry.topics.entry(topic).or_default().insert(id);
+    }
+
+    /// Removes a topic from a subscriber.
+    fn remove_topic(&self, id: u64, topic: &str) {
+        let mut registry = self.registry();
+        if let Some(subscriber) = registry.subscribers.get_mut(&id) {
+            subscriber.topics.remove(topic);
+        }
+        if let Some(ids) = registry.topics.get_mut(topic) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                registry.topics.remove(topic);
+            }
+        }
+    }
+
+    /// Publishes a message to every subscriber of a topic, without waiting for any of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic` - The topic to publish on.
+    /// * `payload` - The payload of the message.
+    ///
+    /// # Returns
+    ///
+    /// The number of subscribers the message was queued for.
+    pub fn publish(&self, topic: &str, payload: impl Into<Bytes>) -> usize {
+        let mut registry = self.registry();
+        let Some((topic, ids)) = registry.topics.get_key_value(topic) else {
+            return 0;
+        };
+        let message = HubMessage {
+            topic: topic.clone(),
+            payload: payload.into(),
+        };
+
+        let mut delivered = 0;
+        let mut slow = Vec::new();
+        for id in ids.iter() {
+            let subscriber = &registry.subscribers[id];
+            match subscriber.sender.try_send(message.clone()) {
+                Ok(()) => delivered += 1,
+                Err(mpsc::error::TrySendError::Full(_)) => match self.inner.config.slow_consumer {
+                    SlowConsumer::Disconnect => slow.push(*id),
+                    SlowConsumer::SkipMessage => {
+                        subscriber.missed.fetch_add(1, Ordering::Relaxed);
+                    }
+                },
+                // The subscription is being dropped and will unregister itself.
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
+            }
+        }
+
+        for id in slow {
+            warn!(
+                target: self.inner.config.log_target,
+                "Disconnecting slow subscriber {id} from {}",
+                message.topic
+            );
+            if let Some(subscriber) = registry.subscribers.get(&id) {
+                subscriber.dropped.store(true, Ordering::Release);
+            }
+            registry.remove(id);
+        }
+        delivered
+    }
+
+    /// Returns the number of subscribers of a topic.
+    pub fn presence(&self, topic: &str) -> usize {
+        self.registry().topics.get(topic).map_or(0, HashSet::len)
+    }
+
+    /// Returns every topic with at least one subscriber, with its number of subscribers.
+    pub fn topics(&self) -> Vec<(String, usize)> {
+        self.registry()
+            .topics
+            .iter()
+            .map(|(topic, ids)| (topic.to_string(), ids.len()))
+            .collect()
+    }
+
+    /// Closes the hub, ending every subscription once its queued messages have been received.
+    /// Later subscriptions are closed immediately and publishing reaches nobody.
+    pub fn close(&self) {
+        let mut registry = self.registry();
+        self.inner.closed.store(true, Ordering::Release);
+        registry.subscribers.clear();
+        registry.topics.clear();
+    }
+
+    /// Checks whether the hub has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Debug)]
+/// A subscriber of a `Hub`, unsubscribed from every topic when dropped.
+pub struct Subscription {
+    /// The ID of the subscriber.
+    id: u64,
+    /// The hub the subscriber belongs to.
+    hub: Hub,
+    /// The receiving end of the subscriber's queue.
+    receiver: mpsc::Receiver<HubMessage>,
+    /// The number of messages skipped because the queue was full.
+    missed: Arc<AtomicU64>,
+    /// Whether the subscriber was disconnected for being too slow.
+    dropped: Arc<AtomicBool>,
+}
+
+impl Subscription {
+    /// Receives the next message.
+    ///
+    /// # Returns
+    ///
+    /// An `Option` containing the next message, or `None` once the hub is closed or the
+    /// subscriber has been disconnected for being too slow.
+    pub async fn recv(&mut self) -> Option<HubMessage> {
+        self.receiver.recv().await
+    }
+
+    /// Adds a topic to listen to.
+    pub fn subscribe(&mut self, topic: &str) {
+        self.hub.add_topic(self.id, topic);
+    }
+
+    /// Stops listening to a topic.
+    pub fn unsubscribe(&mut self, topic: &str) {
+        self.hub.remove_topic(self.id, topic);
+    }
+
+    /// Returns the number of messages skipped because the queue was full, with `SlowConsumer::SkipMessage`.
+    pub fn missed(&self) -> u64 {
+        self.missed.load(Ordering::Relaxed)
+    }
+
+    /// Checks whether the subscriber was disconnected for being too slow, with `SlowConsumer::Disconnect`.
+    pub fn was_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    /// Turns the subscription into a stream of messages, e.g. to feed an `Sse` response.
+    pub fn into_stream(self) -> impl Stream<Item = HubMessage> + Send {
+        futures::stream::unfold(self, |mut subscription| async move {
+            let message = subscription.recv().await?;
+            Some((message, subscription))
+        })
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.hub.registry().remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    /// Tests publishing to several topics, presence counts and unsubscribing.
+    async fn publish() {
+        let hub = Hub::default();
+        let mut a = hub.subscribe("chat");
+        let mut b = hub.subscribe("chat");
+        b.subscribe("alerts");
+        assert_eq!(hub.presence("chat"), 2);
+        assert_eq!(hub.presence("alerts"), 1);
+
+        assert_eq!(hub.publish("chat", "hi"), 2);
+        assert_eq!(hub.publish("alerts", Bytes::from_static(b"fire")), 1);
+        assert_eq!(hub.publish("nobody", "..."), 0);
+        assert_eq!(a.recv().await.unwrap().text(), Some("hi"));
+        assert_eq!(b.recv().await.unwrap().text(), Some("hi"));
+        let alert = b.recv().await.unwrap();
+        assert_eq!(&*alert.topic, "alerts");
+
+        b.unsubscribe("chat");
+        assert_eq!(hub.presence("chat"), 1);
+        drop(a);
+        assert_eq!(hub.presence("chat"), 0);
+        assert_eq!(hub.topics(), vec![("alerts".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    /// Tests both slow consumer policies and closing the hub.
+    async fn slow_consumers_and_close() {
+        let config = HubConfig {
+            queue_capacity: 2,
+            slow_consumer: SlowConsumer::Disconnect,
+            ..HubConfig::default()
+        };
+        let hub = Hub::new(config);
+        let mut slow = hub.subscribe("t");
+        for n in 0..3 {
+            hub.publish("t", n.to_string());
+        }
+        assert!(slow.was_dropped());
+        assert_eq!(hub.presence("t"), 0);
+        assert_eq!(slow.recv().await.unwrap().text(), Some("0"));
+        assert_eq!(slow.recv().await.unwrap().text(), Some("1"));
+        assert_eq!(slow.recv().await, None);
+
+        let hub = Hub::new(HubConfig {
+            slow_consumer: SlowConsumer::SkipMessage,
+            ..config
+        });
+        let mut lagging = hub.subscribe("t");
+        for n in 0..5 {
+            hub.publish("t", n.to_string());
+        }
+        assert_eq!(lagging.missed(), 3);
+        assert!(!lagging.was_dropped());
+        assert_eq!(lagging.recv().await.unwrap().text(), Some("0"));
+
+        hub.close();
+        assert_eq!(lagging.recv().await.unwrap().text(), Some("1"));
+        assert_eq!(lagging.recv().await, None);
+        assert_eq!(hub.subscribe("t").recv().await, None);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::request::Request;
+use crate::response::Response;
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+
+/// The media type of JSON bodies.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents the errors that can occur while reading or writing JSON bodies.
+pub enum JsonError {
+    /// The `Content-Type` header is missing or is not `application/json` (or a `+json` type).
+    UnsupportedContentType,
+    /// The body is not valid JSON or does not match the requested type.
+    Deserialize(String),
+    /// The value could not be serialized into JSON.
+    Serialize(String),
+}
+
+impl JsonError {
+    /// Returns the HTTP status code a handler should respond with for this error.
+    ///
+    /// # Returns
+    ///
+    /// `415 Unsupported Media Type`, `400 Bad Request` or `500 Internal Server Error`.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            JsonError::UnsupportedContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            JsonError::Deserialize(_) => StatusCode::BAD_REQUEST,
+            JsonError::Serialize(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Builds the JSON error body sent for this error, e.g. `{"error":"..."}`.
+    ///
+    /// # Returns
+    ///
+    /// A `String` containing the serialized error body.
+    pub fn to_body(&self) -> String {
+        serde_json::json!({ "error": self.to_string() }).to_string()
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::UnsupportedContentType => {
+                write!(f, "expected Content-Type: {JSON_CONTENT_TYPE}")
+            }
+            JsonError::Deserialize(e) => write!(f, "invalid JSON body: {e}"),
+            JsonError::Serialize(e) => write!(f, "failed to serialize JSON: {e}"),
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+impl Request {
+    /// Deserializes a JSON request body into `T`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized value if successful, or a `JsonError` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns `JsonError::UnsupportedContentType` if the `Content-Type` header is not
+    /// `application/json` or a `+json` type, or `JsonError::Deserialize` if the body is not
+    /// valid JSON for `T`. Either can be sent back with [`Response::json_error`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use rusticore::{Request, Response};
+    /// # use http::StatusCode;
+    /// #[derive(serde::Deserialize, serde::Serialize)]
+    /// struct User {
+    ///     name: String,
+    /// }
+    ///
+    /// async fn create_user(req: &mut Request, res: &mut Response) {
+    ///     match req.json::<User>() {
+    ///         Ok(user) => res.json_value(&user, StatusCode::CREATED).await,
+    ///         Err(e) => res.json_error(&e).await,
+    ///     }
+    /// }
+    /// ```
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError> {
+        let is_json = self.get_header("Content-Type").is_some_and(|value| {
+            let media_type = value.split(';').next().unwrap_or_default().trim();
+            media_type.eq_ignore_ascii_case(JSON_CONTENT_TYPE)
+                || media_type.to_ascii_lowercase().ends_with("+json")
+        });
+        if !is_json {
+            return Err(JsonError::UnsupportedContentType);
+        }
+
+        serde_json::from_slice(self.body()).map_err(|e| JsonError::Deserialize(e.to_string()))
+    }
+}
+
+impl Response {
+    /// Serializes a value and sends it as a JSON response.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to serialize into the response body.
+    /// * `status_code` - The HTTP status code for the response.
+    ///
+    /// # Notes
+    ///
+    /// If the value cannot be serialized, a `500 Internal Server Error` with a JSON error body is sent instead.
+    pub async fn json_value<T: Serialize + ?Sized>(&mut self, value: &T, status_code: StatusCode) {
+        match serde_json::to_string(value) {
+            Ok(body) => self.json(&body, status_code).await,
+            Err(e) => self.json_error(&JsonError::Serialize(e.to_string())).await,
+        }
+    }
+
+    /// Sends a `JsonError` as a JSON response with the matching status code.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The error to send, e.g. from [`Request::json`].
+    pub async fn json_error(&mut self, error: &JsonError) {
+        self.json(&error.to_body(), error.status_code()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::shared_stream;
+    use crate::Server;
+    use serde::Deserialize;
+    use std::sync::Arc;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct User {
+        name: String,
+        age: u8,
+    }
+
+    #[tokio::test]
+    /// Tests the deserialization of JSON request bodies.
+    /// It checks that `+json` media types are accepted and that bad content types and bodies are rejected.
+    async fn request_json() {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (mut client, server_stream) = duplex(1024);
+        let stream = shared_stream(server_stream);
+
+        client
+            .write_all(b"POST / HTTP/1.1\r\nContent-Type: application/vnd.api+json\r\nContent-Length: 23\r\n\r\n{\"name\":\"Ada\",\"age\":36}")
+            .await
+            .unwrap();
+        let req = Request::new(stream.clone(), server.clone()).await.unwrap();
+        assert_eq!(
+            req.json::<User>(),
+            Ok(User {
+                name: "Ada".to_string(),
+                age: 36
+            })
+        );
+
+        client
+            .write_all(
+                b"POST / HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\n{}",
+            )
+            .await
+            .unwrap();
+        let req = Request::new(stream.clone(), server.clone()).await.unwrap();
+        assert_eq!(req.json::<User>(), Err(JsonError::UnsupportedContentType));
+
+        client
+            .write_all(
+                b"POST / HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}",
+            )
+            .await
+            .unwrap();
+        let req = Request::new(stream.clone(), server).await.unwrap();
+        let err = req.json::<User>().unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    /// Tests that values and errors are serialized into JSON responses.
+    async fn response_json() {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (mut client, server_stream) = duplex(1024);
+        let mut res = Response::new(shared_stream(server_stream), "HTTP/1.1", server);
+
+        let user = User {
+            name: "Ada".to_string(),
+            age: 36,
+        };
+        res.json_value(&user, StatusCode::CREATED).await;
+        drop(res);
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert!(output.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(output.contains("Content-Type: application/json\r\n"));
+        assert!(output.ends_with("\r\n\r\n{\"name\":\"Ada\",\"age\":36}"));
+        assert_eq!(
+            JsonError::UnsupportedContentType.to_body(),
+            "{\"error\":\"expected Content-Type: application/json\"}"
+        );
+    }
+}
@@ -1,18 +1,76 @@
+mod body;
 mod buffer_pool;
+mod compression;
+mod cookie;
+mod cookie_jar;
+mod form;
+mod forwarded;
+#[cfg(feature = "http2")]
+mod http2;
+mod hub;
+#[cfg(feature = "json")]
+mod json;
+mod listener;
 mod logging;
+mod middleware;
+mod multipart;
+mod proxy_protocol;
+mod range;
 mod request;
 mod response;
+#[cfg(unix)]
+mod restart;
 mod routing;
+mod sendfile;
 mod server;
+mod session;
+mod sse;
+mod static_files;
+#[cfg(unix)]
+mod systemd;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
+#[cfg(unix)]
+mod unix;
+mod websocket;
 
 use crate::routing::Handler;
+pub use body::BodyWriter;
 pub use buffer_pool::BufferPool;
+pub use compression::Compression;
+pub use cookie::{Cookie, SameSite};
+pub use cookie_jar::CookieJar;
+pub use form::{Form, FormError};
+pub use forwarded::{Cidr, ForwardedHeaders};
+#[cfg(feature = "http2")]
+pub use http2::Http2Config;
+pub use hub::{Hub, HubConfig, HubMessage, SlowConsumer, Subscription};
+#[cfg(feature = "json")]
+pub use json::JsonError;
+pub use listener::{ListenAddress, ListenerConfig};
 pub use logging::init_logging;
+pub use middleware::Middleware;
+pub use multipart::{Field, FieldData, Multipart, MultipartError, MultipartLimits, TempFile};
+pub use proxy_protocol::ProxyHeader;
 pub use request::Request;
 pub use response::Response;
 pub use routing::Route;
 pub use server::Server;
 pub use server::ServerState;
+pub use session::{
+    FileStore, MemoryStore, Session, SessionMiddleware, SessionRecord, SessionStore,
+};
+pub use sse::{Event, Sse, SSE_CONTENT_TYPE};
+pub use static_files::StaticFiles;
+#[cfg(feature = "tls")]
+pub use tls::{PeerCertificate, SubjectAltName, TlsConfig, TlsError, TlsInfo};
+pub use transport::{shared_stream, SharedStream, Transport};
+#[cfg(unix)]
+pub use unix::{PeerCredentials, UnixSocket};
+pub use websocket::{
+    close_code, CloseFrame, Message, WebSocket, WebSocketConfig, WebSocketError, WebSocketSender,
+};
 
 /// Starts the server using default settings.
 ///
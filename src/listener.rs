@@ -0,0 +1,649 @@
+#[cfg(unix)]
+use crate::systemd;
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+#[cfg(unix)]
+use crate::unix::UnixSocket;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Where a listener accepts connections.
+pub enum ListenAddress {
+    /// A TCP address such as `0.0.0.0:80`, `[::]:443` or `localhost:8080`.
+    Tcp(String),
+    #[cfg(unix)]
+    /// A Unix domain socket.
+    Unix(UnixSocket),
+    #[cfg(unix)]
+    /// A TCP or Unix socket that is already listening, e.g. inherited from systemd.
+    Fd(RawFd),
+}
+
+#[derive(Debug, Clone)]
+/// A socket the server listens on, with its own settings.
+///
+/// Every listener of a server shares its routes, middlewares, state and shutdown.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rusticore::{ListenerConfig, Server};
+///
+/// # async fn run() {
+/// let mut server = Server::new("0.0.0.0", 80, false, None, None);
+/// server.listeners.push(ListenerConfig::tcp("[::]:80"));
+/// server.start().await.unwrap();
+/// # }
+/// ```
+pub struct ListenerConfig {
+    /// Where the listener accepts connections.
+    pub address: ListenAddress,
+    /// Whether an IPv6 listener only accepts IPv6 connections, or `None` to decide
+    /// automatically: dual-stack, unless another listener of the server takes the same port
+    /// on IPv4.
+    pub ipv6_only: Option<bool>,
+    /// Whether to set `SO_REUSEPORT`, so other processes can listen on the same TCP address.
+    pub reuse_port: bool,
+    /// Whether connections start with a PROXY protocol header giving the address of the
+    /// client, as sent by a load balancer such as HAProxy.
+    pub proxy_protocol: bool,
+    #[cfg(unix)]
+    /// Whether peers connecting over a Unix socket are trusted proxies, whose forwarding
+    /// headers are believed like those of the server's `trusted_proxies`.
+    pub trust_unix_peers: bool,
+    #[cfg(feature = "tls")]
+    /// The TLS settings of the listener, or `None` to serve plain HTTP.
+    pub tls: Option<TlsConfig>,
+}
+
+impl ListenerConfig {
+    /// Creates a plain HTTP listener on a TCP address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address to bind, e.g. `0.0.0.0:80` or `[::]:80`.
+    ///
+    /// # Returns
+    ///
+    /// A new `ListenerConfig`.
+    pub fn tcp(address: impl Into<String>) -> Self {
+        ListenerConfig {
+            address: ListenAddress::Tcp(address.into()),
+            ipv6_only: None,
+            reuse_port: false,
+            proxy_protocol: false,
+            #[cfg(unix)]
+            trust_unix_peers: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    #[cfg(unix)]
+    /// Creates a plain HTTP listener on a Unix domain socket.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket` - The socket file and its permissions.
+    ///
+    /// # Returns
+    ///
+    /// A new `ListenerConfig`.
+    pub fn unix(socket: UnixSocket) -> Self {
+        ListenerConfig {
+            address: ListenAddress::Unix(socket),
+            ipv6_only: None,
+            reuse_port: false,
+            proxy_protocol: false,
+            #[cfg(unix)]
+            trust_unix_peers: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    #[cfg(unix)]
+    /// Creates a plain HTTP listener on a socket that is already bound and listening, e.g.
+    /// one opened by a parent process.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The file descriptor of a TCP or Unix stream socket.
+    ///
+    /// # Returns
+    ///
+    /// A new `ListenerConfig`.
+    ///
+    /// # Safety
+    ///
+    /// The server takes ownership of the file descriptor when it starts, so it must be open
+    /// and not be used or closed by anything else.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        ListenerConfig {
+            address: ListenAddress::Fd(fd),
+            ipv6_only: None,
+            reuse_port: false,
+            proxy_protocol: false,
+            #[cfg(unix)]
+            trust_unix_peers: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    #[cfg(unix)]
+    /// Creates listeners for the sockets passed by systemd socket activation, in the order
+    /// of the `ListenStream=` lines of the socket unit.
+    ///
+    /// # Returns
+    ///
+    /// A vector of `ListenerConfig`, empty if the process was not socket activated.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use rusticore::{ListenerConfig, Server};
+    ///
+    /// # async fn run() {
+    /// let mut server = Server::new("localhost", 8080, false, None, None);
+    /// let inherited = ListenerConfig::from_systemd();
+    /// if !inherited.is_empty() {
+    ///     server.listen_on_host = false;
+    ///     server.listeners.extend(inherited);
+    /// }
+    /// server.start().await.unwrap();
+    /// # }
+    /// ```
+    pub fn from_systemd() -> Vec<Self> {
+        systemd::listen_fds()
+            .into_iter()
+            // SAFETY: systemd hands these descriptors to this process and nothing else uses them.
+            .map(|fd| unsafe { ListenerConfig::from_raw_fd(fd) })
+            .collect()
+    }
+
+    #[cfg(feature = "tls")]
+    /// Serves HTTPS on the listener.
+    ///
+    /// # Arguments
+    ///
+    /// * `tls` - The certificates and TLS settings of the listener.
+    ///
+    /// # Returns
+    ///
+    /// The updated `ListenerConfig`.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets whether an IPv6 listener only accepts IPv6 connections, instead of also
+    /// accepting IPv4 ones as IPv4-mapped addresses.
+    ///
+    /// # Arguments
+    ///
+    /// * `ipv6_only` - `true` for IPv6 only, `false` for dual-stack.
+    ///
+    /// # Returns
+    ///
+    /// The updated `ListenerConfig`.
+    pub fn ipv6_only(mut self, ipv6_only: bool) -> Self {
+        self.ipv6_only = Some(ipv6_only);
+        self
+    }
+
+    /// Sets `SO_REUSEPORT` on a TCP listener, so several processes can accept connections on
+    /// the same address. This is an alternative to `Server::hot_restart`: start the new
+    /// process alongside the old one, then shut the old one down to drain it.
+    ///
+    /// # Arguments
+    ///
+    /// * `reuse_port` - Whether other sockets may bind the same address and port.
+    ///
+    /// # Returns
+    ///
+    /// The updated `ListenerConfig`.
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    /// Expects every connection to start with a PROXY protocol header, in version 1 or 2, so
+    /// requests report the address of the client instead of the load balancer's.
+    ///
+    /// Connections without a valid header within `Server::proxy_protocol_timeout` are closed,
+    /// so only enable this on listeners that nothing but the load balancer can reach.
+    ///
+    /// # Arguments
+    ///
+    /// * `proxy_protocol` - Whether connections start with a PROXY protocol header.
+    ///
+    /// # Returns
+    ///
+    /// The updated `ListenerConfig`.
+    pub fn proxy_protocol(mut self, proxy_protocol: bool) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    #[cfg(unix)]
+    /// Trusts the peers of Unix socket connections as proxies, e.g. a reverse proxy on the
+    /// same host, so `Request::client_ip`, `Request::scheme` and `Request::host` believe the
+    /// forwarding headers they send. Unix sockets have no address to match against the
+    /// server's `trusted_proxies`, so their peers are not trusted otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `trust` - Whether Unix socket peers are trusted proxies.
+    ///
+    /// # Returns
+    ///
+    /// The updated `ListenerConfig`.
+    pub fn trust_unix_peers(mut self, trust: bool) -> Self {
+        self.trust_unix_peers = trust;
+        self
+    }
+
+    #[cfg(feature = "http2")]
+    /// Checks whether the listener serves HTTPS.
+    pub(crate) fn is_tls(&self) -> bool {
+        #[cfg(feature = "tls")]
+        return self.tls.is_some();
+        #[cfg(not(feature = "tls"))]
+        false
+    }
+}
+
+/// Binds the listeners of a server.
+///
+/// # Arguments
+///
+/// * `configs` - The listeners to bind, in order.
+/// * `inherited` - The sockets handed over by a hot restart, used instead of binding the
+///   listeners at the same positions.
+///
+/// # Returns
+///
+/// An `io::Result` containing the bound listeners, in the same order.
+///
+/// # Errors
+///
+/// Returns the first error resolving or binding an address; the listeners bound so far are
+/// closed again.
+pub(crate) async fn bind_all(
+    configs: Vec<ListenerConfig>,
+    #[cfg(unix)] inherited: &[RawFd],
+) -> io::Result<Vec<Listener>> {
+    // Addresses are resolved first, since dual-stack IPv6 listeners depend on the others.
+    let mut targets = Vec::new();
+    #[cfg(unix)]
+    let mut inherited = inherited.iter();
+    for config in &configs {
+        #[cfg(unix)]
+        if let Some(&fd) = inherited.next() {
+            // A Unix socket handed over keeps its file, which this process removes in turn.
+            let socket = match config.address {
+                ListenAddress::Unix(ref socket) => Some(socket.clone()),
+                _ => None,
+            };
+            targets.push(BindTarget::Fd(fd, socket));
+            continue;
+        }
+        targets.push(match config.address {
+            ListenAddress::Tcp(ref address) => BindTarget::Tcp(resolve(address).await?),
+            #[cfg(unix)]
+            ListenAddress::Unix(ref socket) => BindTarget::Unix(socket.clone()),
+            #[cfg(unix)]
+            ListenAddress::Fd(fd) => BindTarget::Fd(fd, None),
+        });
+    }
+    let ipv4_ports = targets
+        .iter()
+        .filter_map(|target| match target {
+            BindTarget::Tcp(addr) if addr.is_ipv4() && addr.port() != 0 => Some(addr.port()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut listeners: Vec<Listener> = Vec::new();
+    for (config, target) in configs.into_iter().zip(targets) {
+        let socket = match target {
+            BindTarget::Tcp(addr) => {
+                let ipv6_only = config
+                    .ipv6_only
+                    .unwrap_or_else(|| ipv4_ports.contains(&addr.port()));
+                bind_tcp(addr, ipv6_only, config.reuse_port).map(ListenSocket::Tcp)
+            }
+            #[cfg(unix)]
+            BindTarget::Unix(socket) => socket
+                .bind()
+                .map(|listener| ListenSocket::Unix(listener, Some(socket))),
+            #[cfg(unix)]
+            BindTarget::Fd(fd, socket) => inherit(fd, socket),
+        };
+        match socket {
+            Ok(socket) => listeners.push(Listener {
+                socket,
+                config: Arc::new(config),
+            }),
+            Err(e) => {
+                listeners.into_iter().for_each(Listener::close);
+                return Err(e);
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+/// A listener address, resolved and ready to bind.
+enum BindTarget {
+    /// A TCP socket address.
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    /// A Unix domain socket.
+    Unix(UnixSocket),
+    #[cfg(unix)]
+    /// An inherited socket, with the socket file to remove once closed, if any.
+    Fd(RawFd, Option<UnixSocket>),
+}
+
+/// Resolves a TCP address to the first socket address it names.
+async fn resolve(address: &str) -> io::Result<SocketAddr> {
+    tokio::net::lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No address to bind"))
+}
+
+/// Binds a TCP socket, setting `IPV6_V6ONLY` explicitly for IPv6 addresses.
+fn bind_tcp(addr: SocketAddr, ipv6_only: bool, reuse_port: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(reuse_port)?;
+    #[cfg(not(unix))]
+    let _ = reuse_port;
+    if addr.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+#[cfg(unix)]
+/// Takes over a socket that is already listening.
+///
+/// The descriptor is marked close-on-exec, so it does not leak into child processes.
+fn inherit(fd: RawFd, socket_file: Option<UnixSocket>) -> io::Result<ListenSocket> {
+    // SAFETY: `ListenerConfig::from_raw_fd` hands the ownership of the descriptor over.
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    if socket.r#type()? != Type::STREAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The inherited socket is not a stream socket",
+        ));
+    }
+    socket.set_cloexec(true)?;
+    socket.set_nonblocking(true)?;
+    if socket.local_addr()?.is_unix() {
+        let listener = std::os::unix::net::UnixListener::from(OwnedFd::from(socket));
+        Ok(ListenSocket::Unix(
+            UnixListener::from_std(listener)?,
+            socket_file,
+        ))
+    } else {
+        Ok(ListenSocket::Tcp(TcpListener::from_std(socket.into())?))
+    }
+}
+
+/// A bound socket.
+enum ListenSocket {
+    /// A TCP socket.
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    /// A Unix domain socket, with the socket file removed again when the listener is closed,
+    /// unless it was inherited.
+    Unix(UnixListener, Option<UnixSocket>),
+}
+
+/// A socket the server accepts connections on, with the settings it was configured with.
+pub(crate) struct Listener {
+    /// The bound socket.
+    socket: ListenSocket,
+    /// The settings of the listener, shared with its connections.
+    pub(crate) config: Arc<ListenerConfig>,
+}
+
+/// A connection accepted by a `Listener`.
+pub(crate) enum Connection {
+    /// A TCP connection.
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    /// A Unix domain socket connection.
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Returns the address of a TCP listener.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<SocketAddr>`, or `None` for a Unix socket.
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        match self.socket {
+            ListenSocket::Tcp(ref listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            ListenSocket::Unix(..) => None,
+        }
+    }
+
+    /// Waits for the next connection.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the accepted connection.
+    pub(crate) async fn accept(&self) -> io::Result<Connection> {
+        match self.socket {
+            ListenSocket::Tcp(ref listener) => Ok(Connection::Tcp(listener.accept().await?.0)),
+            #[cfg(unix)]
+            ListenSocket::Unix(ref listener, _) => Ok(Connection::Unix(listener.accept().await?.0)),
+        }
+    }
+
+    #[cfg(unix)]
+    /// Returns the file descriptor of the socket, e.g. to hand it over to a new process.
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        match self.socket {
+            ListenSocket::Tcp(ref listener) => listener.as_raw_fd(),
+            ListenSocket::Unix(ref listener, _) => listener.as_raw_fd(),
+        }
+    }
+
+    #[cfg(unix)]
+    /// Stops listening after the socket was handed over to a new process, leaving the socket
+    /// file of a Unix socket in place for it.
+    pub(crate) fn hand_over(self) {
+        drop(self.socket);
+    }
+
+    /// Stops listening, removing the socket file of a Unix socket.
+    pub(crate) fn close(self) {
+        match self.socket {
+            ListenSocket::Tcp(listener) => drop(listener),
+            #[cfg(unix)]
+            ListenSocket::Unix(listener, socket) => {
+                drop(listener);
+                if let Some(socket) = socket {
+                    socket.remove();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Server;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    /// Sends a request for the index route and returns the status line.
+    async fn get<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> String {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    /// Tests serving the same routes on several TCP and Unix socket listeners, with IPv4 and
+    /// IPv6 wildcards sharing a port.
+    async fn serve_listeners() {
+        let port = std::net::TcpListener::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("http.sock");
+
+        let mut server = Server::new("0.0.0.0", port, false, None, None);
+        server.listeners = vec![
+            ListenerConfig::tcp(format!("[::]:{port}")),
+            ListenerConfig::tcp("127.0.0.1:0"),
+            ListenerConfig::unix(UnixSocket::new(&path)),
+        ];
+        server.start().await.unwrap();
+        let addrs = server.local_addrs().to_vec();
+        assert_eq!(addrs.len(), 3);
+        assert_eq!(server.local_addr(), Some(addrs[0]));
+
+        let ok = "HTTP/1.1 200 OK";
+        assert_eq!(
+            get(TcpStream::connect(("127.0.0.1", port)).await.unwrap()).await,
+            ok
+        );
+        assert_eq!(
+            get(TcpStream::connect(("::1", port)).await.unwrap()).await,
+            ok
+        );
+        assert_eq!(get(TcpStream::connect(addrs[2]).await.unwrap()).await, ok);
+        assert_eq!(get(UnixStream::connect(&path).await.unwrap()).await, ok);
+
+        // A failing listener leaves nothing bound behind.
+        let mut clash = Server::new("127.0.0.1", 0, false, None, None);
+        clash.listeners = vec![
+            ListenerConfig::unix(UnixSocket::new(dir.path().join("other.sock"))),
+            ListenerConfig::tcp(addrs[2].to_string()),
+        ];
+        assert!(clash.start().await.is_err());
+        assert!(!dir.path().join("other.sock").exists());
+
+        server.shutdown().await;
+        assert!(TcpStream::connect(("::1", port)).await.is_err());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    /// Tests serving only sockets that were opened before the server started, as with
+    /// systemd socket activation.
+    async fn inherit_listeners() {
+        use std::os::fd::IntoRawFd;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inherited.sock");
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.listen_on_host = false;
+        // SAFETY: the descriptors are released by their owners and used by nothing else.
+        server.listeners = unsafe {
+            vec![
+                ListenerConfig::from_raw_fd(tcp.into_raw_fd()),
+                ListenerConfig::from_raw_fd(unix.into_raw_fd()),
+            ]
+        };
+        server.start().await.unwrap();
+        assert_eq!(server.local_addrs(), [addr]);
+
+        let ok = "HTTP/1.1 200 OK";
+        assert_eq!(get(TcpStream::connect(addr).await.unwrap()).await, ok);
+        assert_eq!(get(UnixStream::connect(&path).await.unwrap()).await, ok);
+
+        // The socket file belongs to whoever created the socket.
+        server.shutdown().await;
+        assert!(path.exists());
+        assert!(TcpStream::connect(addr).await.is_err());
+
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.listen_on_host = false;
+        assert!(server.start().await.is_err());
+    }
+
+    #[tokio::test]
+    /// Tests taking over the listeners of a running process, which keeps accepting on the
+    /// same sockets, and binding a port twice with `SO_REUSEPORT`.
+    async fn hand_over_listeners() {
+        use std::os::fd::IntoRawFd;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("http.sock");
+        let configs = || {
+            vec![
+                ListenerConfig::tcp("127.0.0.1:0").reuse_port(true),
+                ListenerConfig::unix(UnixSocket::new(&path)),
+            ]
+        };
+        let old = bind_all(configs(), &[]).await.unwrap();
+        let addr = old[0].local_addr().unwrap();
+        // The copies a new process receives across `exec`.
+        let fds = old
+            .iter()
+            .map(|listener| {
+                // SAFETY: the listener stays open until the copy is made.
+                let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(listener.as_raw_fd()) };
+                socket2::SockRef::from(&fd)
+                    .try_clone()
+                    .unwrap()
+                    .into_raw_fd()
+            })
+            .collect::<Vec<_>>();
+        let new = bind_all(configs(), &fds).await.unwrap();
+        assert_eq!(new[0].local_addr(), Some(addr));
+
+        // Handing over leaves the socket file for the new listeners.
+        old.into_iter().for_each(Listener::hand_over);
+        assert!(path.exists());
+        let (client, accepted) = tokio::join!(UnixStream::connect(&path), new[1].accept());
+        assert!(client.is_ok() && accepted.is_ok());
+        let (client, accepted) = tokio::join!(TcpStream::connect(addr), new[0].accept());
+        assert!(client.is_ok() && accepted.is_ok());
+
+        let shared = bind_all(
+            vec![ListenerConfig::tcp(addr.to_string()).reuse_port(true)],
+            &[],
+        );
+        assert!(shared.await.is_ok());
+        let taken = bind_all(vec![ListenerConfig::tcp(addr.to_string())], &[]);
+        assert!(taken.await.is_err());
+
+        new.into_iter().for_each(Listener::close);
+        assert!(!path.exists());
+    }
+}
@@ -15,8 +15,20 @@ async fn main() {
         }
     }
 
-    if let Err(e) = server {
-        error!("Server error: {}", e);
-        std::process::exit(1);
+    match server {
+        Ok(s) => {
+            // SIGUSR2 hands the listeners over to a new process, e.g. after an upgrade.
+            #[cfg(unix)]
+            s.hot_restart_on_sigusr2();
+            // Serve until interrupted, then let requests in progress finish.
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => s.shutdown().await,
+                _ = s.stopped() => {}
+            }
+        }
+        Err(e) => {
+            error!("Server error: {}", e);
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,68 @@
+use crate::request::Request;
+use crate::response::Response;
+use futures::future::BoxFuture;
+use std::fmt;
+
+/// A hook into the handling of every request, registered with `Server::add_middleware`.
+///
+/// Middlewares run in the order they were added: `before` runs ahead of the route handler,
+/// `before_send` runs whenever the handler writes the response head, and `after` runs once
+/// the handler has returned, in reverse order. Per-request state can be passed between the
+/// hooks through `Request::extensions` and `Response::extensions`.
+///
+/// # Examples
+///
+/// ```
+/// use futures::future::BoxFuture;
+/// use rusticore::{Middleware, Request, Response};
+///
+/// #[derive(Debug)]
+/// struct PoweredBy;
+///
+/// impl Middleware for PoweredBy {
+///     fn before_send<'a>(&'a self, res: &'a mut Response) -> BoxFuture<'a, ()> {
+///         res.set_header("X-Powered-By", "rusticore");
+///         Box::pin(async {})
+///     }
+/// }
+/// ```
+pub trait Middleware: fmt::Debug + Send + Sync {
+    /// Runs before the route handler.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - A mutable reference to the incoming HTTP request object.
+    /// * `res` - A mutable reference to the HTTP response object.
+    ///
+    /// # Returns
+    ///
+    /// `true` to continue handling the request, or `false` if the middleware has already
+    /// responded and the remaining middlewares and the route handler must be skipped.
+    fn before<'a>(&'a self, req: &'a mut Request, res: &'a mut Response) -> BoxFuture<'a, bool> {
+        let _ = (req, res);
+        Box::pin(async { true })
+    }
+
+    /// Runs right before the response head is written, so headers can still be changed.
+    /// The head waits for it, so work the client may depend on, e.g. saving a session whose
+    /// cookie is about to be sent, can be finished here.
+    ///
+    /// # Arguments
+    ///
+    /// * `res` - A mutable reference to the HTTP response object about to be sent.
+    fn before_send<'a>(&'a self, res: &'a mut Response) -> BoxFuture<'a, ()> {
+        let _ = res;
+        Box::pin(async {})
+    }
+
+    /// Runs after the route handler has returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - A mutable reference to the incoming HTTP request object.
+    /// * `res` - A mutable reference to the HTTP response object.
+    fn after<'a>(&'a self, req: &'a mut Request, res: &'a mut Response) -> BoxFuture<'a, ()> {
+        let _ = (req, res);
+        Box::pin(async {})
+    }
+}
@@ -0,0 +1,809 @@
+use crate::request::Request;
+use http::StatusCode;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// The media type of multipart form bodies.
+pub const MULTIPART_CONTENT_TYPE: &str = "multipart/form-data";
+
+/// The maximum size in bytes of the header block of a single part.
+const MAX_PART_HEADERS_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Clone)]
+/// Limits applied while streaming a `multipart/form-data` body.
+pub struct MultipartLimits {
+    /// The maximum size in bytes of a single non-file field.
+    pub max_field_size: usize,
+    /// The maximum size in bytes of a single file part.
+    pub max_file_size: usize,
+    /// The maximum size in bytes of the whole body.
+    pub max_total_size: usize,
+    /// The maximum number of parts in the body.
+    pub max_fields: usize,
+    /// The size in bytes above which `Field::data` spills a part to a temporary file.
+    pub memory_threshold: usize,
+    /// The directory temporary files are written to. Defaults to the system temporary directory.
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl Default for MultipartLimits {
+    /// Creates limits suitable for typical form uploads: 64 KiB fields, 32 MiB files,
+    /// a 64 MiB body, at most 64 parts, and spilling to disk above 256 KiB.
+    fn default() -> Self {
+        MultipartLimits {
+            max_field_size: 64 * 1024,
+            max_file_size: 32 * 1024 * 1024,
+            max_total_size: 64 * 1024 * 1024,
+            max_fields: 64,
+            memory_threshold: 256 * 1024,
+            temp_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents the errors that can occur while streaming a multipart body.
+pub enum MultipartError {
+    /// The `Content-Type` header is missing or is not `multipart/form-data`.
+    UnsupportedContentType,
+    /// The `Content-Type` header has no `boundary` parameter.
+    MissingBoundary,
+    /// The body is larger than `MultipartLimits::max_total_size`.
+    PayloadTooLarge {
+        /// The maximum size allowed in bytes.
+        limit: usize,
+    },
+    /// A part is larger than `MultipartLimits::max_field_size` or `MultipartLimits::max_file_size`.
+    FieldTooLarge {
+        /// The name of the offending field, if it had one.
+        name: Option<String>,
+        /// The maximum size allowed in bytes.
+        limit: usize,
+    },
+    /// The body has more parts than `MultipartLimits::max_fields`.
+    TooManyFields {
+        /// The maximum number of parts allowed.
+        limit: usize,
+    },
+    /// The body does not follow the `multipart/form-data` format.
+    Malformed(&'static str),
+    /// Reading the body or writing a temporary file failed.
+    Io(String),
+}
+
+impl MultipartError {
+    /// Returns the HTTP status code a handler should respond with for this error.
+    ///
+    /// # Returns
+    ///
+    /// `415 Unsupported Media Type`, `413 Payload Too Large`, `400 Bad Request` or
+    /// `500 Internal Server Error` for I/O failures.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            MultipartError::UnsupportedContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            MultipartError::PayloadTooLarge { .. }
+            | MultipartError::FieldTooLarge { .. }
+            | MultipartError::TooManyFields { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            MultipartError::MissingBoundary | MultipartError::Malformed(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            MultipartError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::UnsupportedContentType => {
+                write!(f, "expected Content-Type: {MULTIPART_CONTENT_TYPE}")
+            }
+            MultipartError::MissingBoundary => write!(f, "multipart boundary is missing"),
+            MultipartError::PayloadTooLarge { limit } => {
+                write!(f, "multipart body exceeds the limit of {limit} bytes")
+            }
+            MultipartError::FieldTooLarge { name, limit } => write!(
+                f,
+                "field {:?} exceeds the limit of {limit} bytes",
+                name.as_deref().unwrap_or_default()
+            ),
+            MultipartError::TooManyFields { limit } => {
+                write!(f, "multipart body has more than {limit} parts")
+            }
+            MultipartError::Malformed(e) => write!(f, "malformed multipart body: {e}"),
+            MultipartError::Io(e) => write!(f, "multipart I/O error: {e}"),
+        }
+    }
+}
+
+impl Error for MultipartError {}
+
+impl From<std::io::Error> for MultipartError {
+    fn from(e: std::io::Error) -> Self {
+        MultipartError::Io(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Represents where the parser currently is within the multipart body.
+enum State {
+    /// Before the first boundary.
+    Preamble,
+    /// Right after a boundary, before knowing whether it is the closing one.
+    Boundary,
+    /// Inside the body of a part.
+    Body,
+    /// After the closing boundary.
+    Done,
+}
+
+/// A streaming reader of `multipart/form-data` bodies, created by [`Request::multipart`].
+///
+/// Parts are yielded one at a time, and their data is read from the connection only as it is consumed,
+/// so large uploads never have to be held in memory.
+pub struct Multipart<'r> {
+    /// The request whose body is being streamed.
+    request: &'r mut Request,
+    /// The delimiter that separates parts, i.e. `\r\n--` followed by the boundary.
+    delimiter: Vec<u8>,
+    /// Bytes read from the body but not yet consumed by the parser.
+    buffer: Vec<u8>,
+    /// The current parser state.
+    state: State,
+    /// The limits applied while streaming.
+    limits: MultipartLimits,
+    /// The number of body bytes read so far.
+    total_read: usize,
+    /// The number of parts yielded so far.
+    fields: usize,
+    /// Whether the whole body has been read from the request.
+    eof: bool,
+}
+
+impl fmt::Debug for Multipart<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Multipart")
+            .field("state", &self.state)
+            .field("limits", &self.limits)
+            .field("total_read", &self.total_read)
+            .field("fields", &self.fields)
+            .finish()
+    }
+}
+
+impl<'r> Multipart<'r> {
+    /// Creates a new `Multipart` reader over a request body.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request whose body will be streamed.
+    /// * `boundary` - The boundary from the request's `Content-Type` header.
+    /// * `limits` - The limits applied while streaming.
+    ///
+    /// # Returns
+    ///
+    /// A new `Multipart` positioned before the first part.
+    pub(crate) fn new(request: &'r mut Request, boundary: &str, limits: MultipartLimits) -> Self {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+
+        Multipart {
+            request,
+            delimiter,
+            // The first boundary is not preceded by a line break, so pretend it is.
+            buffer: b"\r\n".to_vec(),
+            state: State::Preamble,
+            limits,
+            total_read: 0,
+            fields: 0,
+            eof: false,
+        }
+    }
+
+    /// Advances to the next part of the body, skipping any unread data of the current one.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the next `Field`, or `None` once the closing boundary is reached.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `MultipartError` if the body is malformed, exceeds a limit or cannot be read.
+    pub async fn next_field(&mut self) -> Result<Option<Field<'_, 'r>>, MultipartError> {
+        loop {
+            match self.state {
+                State::Preamble => {
+                    if let Some(pos) = find(&self.buffer, &self.delimiter) {
+                        self.buffer.drain(..pos + self.delimiter.len());
+                        self.state = State::Boundary;
+                    } else {
+                        let keep = self.delimiter.len() - 1;
+                        if self.buffer.len() > keep {
+                            self.buffer.drain(..self.buffer.len() - keep);
+                        }
+                        self.fill("missing boundary").await?;
+                    }
+                }
+                State::Boundary => {
+                    while self.buffer.len() < 2 {
+                        self.fill("unexpected end of body").await?;
+                    }
+                    if self.buffer.starts_with(b"--") {
+                        self.state = State::Done;
+                    } else if self.buffer.starts_with(b"\r\n") {
+                        self.buffer.drain(..2);
+                        let headers = self.read_part_headers().await?;
+                        return Ok(Some(Field::new(self, headers)));
+                    } else {
+                        return Err(MultipartError::Malformed("invalid boundary"));
+                    }
+                }
+                State::Body => while self.read_chunk().await?.is_some() {},
+                State::Done => return Ok(None),
+            }
+        }
+    }
+
+    /// Reads and parses the header block of a part.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the part's headers, or a `MultipartError` if they are malformed.
+    async fn read_part_headers(&mut self) -> Result<Vec<(String, String)>, MultipartError> {
+        self.fields += 1;
+        if self.fields > self.limits.max_fields {
+            return Err(MultipartError::TooManyFields {
+                limit: self.limits.max_fields,
+            });
+        }
+
+        let end = loop {
+            if self.buffer.starts_with(b"\r\n") {
+                break 0;
+            }
+            if let Some(pos) = find(&self.buffer, b"\r\n\r\n") {
+                break pos + 2;
+            }
+            if self.buffer.len() > MAX_PART_HEADERS_SIZE {
+                return Err(MultipartError::Malformed("part headers too large"));
+            }
+            self.fill("unexpected end of part headers").await?;
+        };
+
+        let mut headers = Vec::new();
+        for line in self.buffer[..end].split(|&b| b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+            let line = match std::str::from_utf8(line) {
+                Ok(line) => line,
+                Err(_) => return Err(MultipartError::Malformed("invalid UTF-8 in part headers")),
+            };
+            match line.split_once(':') {
+                Some((key, value)) => {
+                    headers.push((key.trim().to_string(), value.trim().to_string()))
+                }
+                None => return Err(MultipartError::Malformed("invalid part header")),
+            }
+        }
+
+        self.buffer.drain(..end + 2);
+        self.state = State::Body;
+        Ok(headers)
+    }
+
+    /// Reads the next chunk of the current part's data.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the next chunk, or `None` once the part has been fully read.
+    async fn read_chunk(&mut self) -> Result<Option<Vec<u8>>, MultipartError> {
+        loop {
+            if self.state != State::Body {
+                return Ok(None);
+            }
+
+            if let Some(pos) = find(&self.buffer, &self.delimiter) {
+                let chunk: Vec<u8> = self.buffer.drain(..pos).collect();
+                self.buffer.drain(..self.delimiter.len());
+                self.state = State::Boundary;
+                return Ok(if chunk.is_empty() { None } else { Some(chunk) });
+            }
+
+            // Hold back enough bytes to detect a delimiter split across reads.
+            let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 {
+                return Ok(Some(self.buffer.drain(..safe).collect()));
+            }
+
+            self.fill("unexpected end of part").await?;
+        }
+    }
+
+    /// Reads more of the body into the parser buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `eof_error` - The error to report if the body ends before the parser is done.
+    async fn fill(&mut self, eof_error: &'static str) -> Result<(), MultipartError> {
+        if self.eof {
+            return Err(MultipartError::Malformed(eof_error));
+        }
+
+        match self.request.read_body_chunk().await {
+            Ok(Some(chunk)) => {
+                self.total_read += chunk.len();
+                if self.total_read > self.limits.max_total_size {
+                    return Err(MultipartError::PayloadTooLarge {
+                        limit: self.limits.max_total_size,
+                    });
+                }
+                self.buffer.extend_from_slice(&chunk);
+                Ok(())
+            }
+            Ok(None) => {
+                self.eof = true;
+                Err(MultipartError::Malformed(eof_error))
+            }
+            Err(e) => Err(MultipartError::Io(e.to_string())),
+        }
+    }
+}
+
+/// A single part of a multipart body, yielded by [`Multipart::next_field`].
+pub struct Field<'a, 'r> {
+    /// The reader this part belongs to.
+    multipart: &'a mut Multipart<'r>,
+    /// The headers of the part.
+    headers: Vec<(String, String)>,
+    /// The `name` parameter of the `Content-Disposition` header.
+    name: Option<String>,
+    /// The `filename` parameter of the `Content-Disposition` header.
+    file_name: Option<String>,
+    /// The number of data bytes read so far.
+    size: usize,
+}
+
+impl fmt::Debug for Field<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Field")
+            .field("headers", &self.headers)
+            .field("name", &self.name)
+            .field("file_name", &self.file_name)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl<'a, 'r> Field<'a, 'r> {
+    /// Creates a new `Field` from the headers of a part.
+    ///
+    /// # Arguments
+    ///
+    /// * `multipart` - The reader the part belongs to.
+    /// * `headers` - The parsed headers of the part.
+    ///
+    /// # Returns
+    ///
+    /// A new `Field` positioned at the start of the part's data.
+    fn new(multipart: &'a mut Multipart<'r>, headers: Vec<(String, String)>) -> Self {
+        let (mut name, mut file_name) = (None, None);
+        if let Some((_, disposition)) = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Disposition"))
+        {
+            for (key, value) in parse_params(disposition) {
+                if key.eq_ignore_ascii_case("name") {
+                    name = Some(value);
+                } else if key.eq_ignore_ascii_case("filename") && file_name.is_none() {
+                    file_name = Some(value);
+                } else if key.eq_ignore_ascii_case("filename*") {
+                    // RFC 5987 extended value, e.g. UTF-8''na%C3%AFve.txt
+                    if let Some((_, encoded)) = value.split_once("''") {
+                        let decoded =
+                            percent_encoding::percent_decode_str(encoded).decode_utf8_lossy();
+                        file_name = Some(decoded.into_owned());
+                    }
+                }
+            }
+        }
+
+        Field {
+            multipart,
+            headers,
+            name,
+            file_name,
+            size: 0,
+        }
+    }
+
+    /// Returns the name of the field, from the `Content-Disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the file name sent by the client, if the part is a file upload.
+    ///
+    /// # Notes
+    ///
+    /// The file name is chosen by the client and must not be trusted as a path on disk.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    /// Returns the `Content-Type` of the part, if the client sent one.
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+    }
+
+    /// Returns the value of a specific header of the part.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A string slice representing the header key to look for (case-insensitive).
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&str>` containing the value of the header if found, or `None` if the header does not exist.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns all headers of the part.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Reads the next chunk of the part's data.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the next chunk, or `None` once the part has been fully read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MultipartError::FieldTooLarge` if the part exceeds its size limit, or another
+    /// `MultipartError` if the body is malformed or cannot be read.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, MultipartError> {
+        let chunk = self.multipart.read_chunk().await?;
+        if let Some(ref chunk) = chunk {
+            self.size += chunk.len();
+            let limit = if self.file_name.is_some() {
+                self.multipart.limits.max_file_size
+            } else {
+                self.multipart.limits.max_field_size
+            };
+            if self.size > limit {
+                return Err(MultipartError::FieldTooLarge {
+                    name: self.name.clone(),
+                    limit,
+                });
+            }
+        }
+        Ok(chunk)
+    }
+
+    /// Reads the whole part into memory.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the part's data, or a `MultipartError` if it cannot be read.
+    pub async fn bytes(&mut self) -> Result<Vec<u8>, MultipartError> {
+        let mut data = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    /// Reads the whole part into memory as UTF-8 text.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the part's text, or a `MultipartError` if it cannot be read or is not valid UTF-8.
+    pub async fn text(&mut self) -> Result<String, MultipartError> {
+        String::from_utf8(self.bytes().await?)
+            .map_err(|_| MultipartError::Malformed("field is not valid UTF-8"))
+    }
+
+    /// Streams the whole part into a new temporary file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `TempFile`, which is deleted when dropped unless persisted.
+    pub async fn save_to_temp(&mut self) -> Result<TempFile, MultipartError> {
+        self.spill(Vec::new()).await
+    }
+
+    /// Reads the whole part, keeping it in memory while it is smaller than
+    /// `MultipartLimits::memory_threshold` and spilling it to a temporary file otherwise.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the part's `FieldData`.
+    pub async fn data(&mut self) -> Result<FieldData, MultipartError> {
+        let mut data = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            data.extend_from_slice(&chunk);
+            if data.len() > self.multipart.limits.memory_threshold {
+                return self.spill(data).await.map(FieldData::File);
+            }
+        }
+        Ok(FieldData::Memory(data))
+    }
+
+    /// Writes already-read data and the rest of the part into a new temporary file.
+    ///
+    /// # Arguments
+    ///
+    /// * `head` - Data of the part that has already been read.
+    async fn spill(&mut self, head: Vec<u8>) -> Result<TempFile, MultipartError> {
+        let dir = self
+            .multipart
+            .limits
+            .temp_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        let (file, path) = tempfile::Builder::new()
+            .prefix("rusticore-upload-")
+            .tempfile_in(dir)?
+            .into_parts();
+        let mut file = tokio::fs::File::from_std(file);
+
+        file.write_all(&head).await?;
+        let mut size = head.len() as u64;
+        while let Some(chunk) = self.chunk().await? {
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+        }
+        file.flush().await?;
+
+        Ok(TempFile { path, size })
+    }
+}
+
+#[derive(Debug)]
+/// The data of a part read with [`Field::data`].
+pub enum FieldData {
+    /// The part was small enough to be kept in memory.
+    Memory(Vec<u8>),
+    /// The part was spilled to a temporary file.
+    File(TempFile),
+}
+
+#[derive(Debug)]
+/// A part saved to a temporary file, deleted when dropped unless persisted.
+pub struct TempFile {
+    /// The path of the file, removed from disk on drop.
+    path: tempfile::TempPath,
+    /// The size of the file in bytes.
+    size: u64,
+}
+
+impl TempFile {
+    /// Returns the path of the temporary file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the size of the temporary file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Moves the temporary file to a permanent location so it is not deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `to` - The destination path.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MultipartError::Io` if the file cannot be moved or copied.
+    pub async fn persist(self, to: impl AsRef<Path>) -> Result<(), MultipartError> {
+        match self.path.persist(to.as_ref()) {
+            Ok(()) => Ok(()),
+            // Renaming fails across file systems, so fall back to copying.
+            Err(e) => {
+                tokio::fs::copy(&e.path, to.as_ref()).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Extracts the boundary parameter from a `multipart/form-data` content type.
+///
+/// # Arguments
+///
+/// * `content_type` - The value of the `Content-Type` header.
+///
+/// # Returns
+///
+/// An `Option<String>` containing the boundary if present and valid, or `None` otherwise.
+pub(crate) fn boundary(content_type: &str) -> Option<String> {
+    parse_params(content_type)
+        .into_iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty() && value.len() <= 70)
+}
+
+/// Parses the `key=value` parameters following a header value, e.g. `form-data; name="a"`.
+///
+/// # Arguments
+///
+/// * `value` - The full header value; everything before the first `;` is skipped.
+///
+/// # Returns
+///
+/// A vector of parameter names and unquoted values.
+fn parse_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = match value.split_once(';') {
+        Some((_, rest)) => rest,
+        None => return params,
+    };
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        let Some(eq) = rest.find('=') else { break };
+        let key = rest[..eq].trim().to_string();
+        rest = rest[eq + 1..].trim_start();
+
+        let mut val = String::new();
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            val.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => val.push(c),
+                }
+            }
+            rest = &quoted[end..];
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            val.push_str(rest[..end].trim());
+            rest = &rest[end..];
+        }
+
+        params.push((key, val));
+    }
+
+    params
+}
+
+/// Finds the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::shared_stream;
+    use crate::Server;
+    use std::sync::Arc;
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    /// Builds a multipart request with a text field and a file part.
+    fn request_bytes(file: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(b"preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHello; world\r\n");
+        body.extend_from_slice(b"--XyZ\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\nContent-Type: text/plain\r\n\r\n");
+        body.extend_from_slice(file);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+
+        let mut request = format!(
+            "POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=\"XyZ\"\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(&body);
+        request
+    }
+
+    #[tokio::test]
+    /// Tests streaming a multipart body whose file part is larger than the duplex buffer.
+    /// It checks that fields, file names, part headers and data are all decoded correctly.
+    async fn stream_fields() {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (mut client, server_stream) = duplex(256);
+        let stream = shared_stream(server_stream);
+        let file: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        let data = request_bytes(&file);
+
+        let write_fut = async move {
+            client.write_all(&data).await.unwrap();
+            client
+        };
+        let read_fut = async {
+            let mut req = Request::new(stream.clone(), server).await.unwrap();
+            let mut multipart = req.multipart().unwrap();
+
+            let mut field = multipart.next_field().await.unwrap().unwrap();
+            assert_eq!(field.name(), Some("title"));
+            assert_eq!(field.file_name(), None);
+            assert_eq!(field.text().await.unwrap(), "Hello; world");
+
+            let mut field = multipart.next_field().await.unwrap().unwrap();
+            assert_eq!(field.name(), Some("upload"));
+            assert_eq!(field.file_name(), Some("a \"b\".txt"));
+            assert_eq!(field.content_type(), Some("text/plain"));
+            assert_eq!(field.bytes().await.unwrap(), file);
+
+            assert!(multipart.next_field().await.unwrap().is_none());
+        };
+
+        tokio::join!(write_fut, read_fut);
+    }
+
+    #[tokio::test]
+    /// Tests that large parts spill to disk and that size limits are enforced.
+    async fn spill_and_limits() {
+        let mut server = Server::new("localhost", 8080, false, None, None);
+        server.multipart_limits.memory_threshold = 1024;
+        server.multipart_limits.max_field_size = 8;
+        let server = Arc::new(server);
+        let file = vec![b'x'; 4096];
+
+        let (mut client, server_stream) = duplex(64 * 1024);
+        let stream = shared_stream(server_stream);
+        client.write_all(&request_bytes(&file)).await.unwrap();
+
+        let mut req = Request::new(stream.clone(), server).await.unwrap();
+        let mut multipart = req.multipart().unwrap();
+
+        let mut field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(
+            field.bytes().await,
+            Err(MultipartError::FieldTooLarge {
+                name: Some("title".to_string()),
+                limit: 8
+            })
+        );
+
+        let mut field = multipart.next_field().await.unwrap().unwrap();
+        match field.data().await.unwrap() {
+            FieldData::File(temp) => {
+                assert_eq!(temp.size(), 4096);
+                assert_eq!(std::fs::read(temp.path()).unwrap(), file);
+            }
+            FieldData::Memory(_) => panic!("Large file should be spilled to disk"),
+        }
+    }
+
+    #[test]
+    /// Tests the parsing of header parameters, including quoted values containing separators.
+    fn params() {
+        assert_eq!(
+            boundary("multipart/form-data; charset=utf-8; boundary=----abc"),
+            Some("----abc".to_string())
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(
+            parse_params("form-data; name=\"a;b\"; filename=x.txt"),
+            vec![
+                ("name".to_string(), "a;b".to_string()),
+                ("filename".to_string(), "x.txt".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use crate::Request;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The signature every PROXY protocol v2 header starts with.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// The longest PROXY protocol v1 header, including the trailing CRLF.
+const V1_MAX_LENGTH: usize = 107;
+/// The type of the TLV carrying the host name the client asked for, e.g. from SNI.
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+/// The type of the TLV carrying the protocol negotiated with ALPN.
+const PP2_TYPE_ALPN: u8 = 0x01;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The PROXY protocol header a load balancer sends ahead of a connection it forwards,
+/// describing the connection it received from the client.
+pub struct ProxyHeader {
+    /// The version of the protocol, 1 for the text format or 2 for the binary one.
+    pub version: u8,
+    /// The address of the client, or `None` for a connection the proxy opened itself, e.g. a
+    /// health check, or an address family that is not TCP over IPv4 or IPv6.
+    pub source: Option<SocketAddr>,
+    /// The address the client connected to on the proxy, if known.
+    pub destination: Option<SocketAddr>,
+    /// The type-length-value fields of a v2 header, in the order they were sent.
+    pub tlvs: Vec<(u8, Vec<u8>)>,
+}
+
+impl ProxyHeader {
+    /// Returns the value of the first TLV of a type.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The type of the TLV, e.g. `0x05` for `PP2_TYPE_UNIQUE_ID`.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&[u8]>` containing the value, or `None` if the proxy did not send it.
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Returns the host name the client asked the proxy for, e.g. with SNI.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&str>` containing the host name, or `None` if the proxy did not send it.
+    pub fn authority(&self) -> Option<&str> {
+        std::str::from_utf8(self.tlv(PP2_TYPE_AUTHORITY)?).ok()
+    }
+
+    /// Returns the application protocol the client negotiated with the proxy using ALPN.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&str>` containing the protocol, e.g. `h2`, or `None` if the proxy did not
+    /// send it.
+    pub fn alpn(&self) -> Option<&str> {
+        std::str::from_utf8(self.tlv(PP2_TYPE_ALPN)?).ok()
+    }
+}
+
+/// Returns the error for a header that does not follow the protocol.
+fn malformed(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Reads the PROXY protocol header at the start of a connection, in either version.
+///
+/// Nothing past the header is read, so the request, or a TLS handshake, follows untouched.
+///
+/// # Arguments
+///
+/// * `stream` - The accepted connection.
+///
+/// # Returns
+///
+/// An `io::Result` containing the parsed header.
+///
+/// # Errors
+///
+/// Returns `InvalidData` if the connection does not start with a well-formed header, or the
+/// error of reading from the connection.
+pub(crate) async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<ProxyHeader> {
+    let mut start = [0u8; 6];
+    stream.read_exact(&mut start).await?;
+    if &start == b"PROXY " {
+        read_v1(stream).await
+    } else if start == V2_SIGNATURE[..6] {
+        read_v2(stream, start).await
+    } else {
+        Err(malformed(
+            "The connection does not start with a PROXY protocol header",
+        ))
+    }
+}
+
+/// Reads the rest of a text header, once `PROXY ` has been read.
+async fn read_v1<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<ProxyHeader> {
+    // Byte by byte, since the request follows right after the line.
+    let mut line = b"PROXY ".to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LENGTH {
+            return Err(malformed("The PROXY protocol header is too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    parse_v1(&line[..line.len() - 2])
+}
+
+/// Parses a text header without its CRLF, e.g. `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443`.
+fn parse_v1(line: &[u8]) -> io::Result<ProxyHeader> {
+    let line = std::str::from_utf8(line).map_err(|_| malformed("Invalid PROXY header"))?;
+    let fields = line.split(' ').collect::<Vec<_>>();
+    let header = |source, destination| ProxyHeader {
+        version: 1,
+        source,
+        destination,
+        tlvs: Vec::new(),
+    };
+    match fields.as_slice() {
+        // Whatever follows is meant to be ignored.
+        ["PROXY", "UNKNOWN", ..] => Ok(header(None, None)),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] =>
+        {
+            let address = |ip: &str, port: &str| -> io::Result<SocketAddr> {
+                let ip = match *family {
+                    "TCP4" => ip.parse::<Ipv4Addr>().map(IpAddr::V4),
+                    _ => ip.parse::<Ipv6Addr>().map(IpAddr::V6),
+                }
+                .map_err(|_| malformed("Invalid address in the PROXY header"))?;
+                // Ports are plain decimal numbers, without a sign or leading zeros.
+                if port.is_empty() || (port.len() > 1 && port.starts_with('0')) {
+                    return Err(malformed("Invalid port in the PROXY header"));
+                }
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| malformed("Invalid port in the PROXY header"))?;
+                Ok(SocketAddr::new(ip, port))
+            };
+            Ok(header(
+                Some(address(source, source_port)?),
+                Some(address(destination, destination_port)?),
+            ))
+        }
+        _ => Err(malformed("Invalid PROXY header")),
+    }
+}
+
+/// Reads the rest of a binary header, once the first bytes of its signature have been read.
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S, start: [u8; 6]) -> io::Result<ProxyHeader> {
+    let mut fixed = [0u8; 16];
+    fixed[..6].copy_from_slice(&start);
+    stream.read_exact(&mut fixed[6..]).await?;
+    if fixed[..12] != V2_SIGNATURE {
+        return Err(malformed("Invalid PROXY protocol v2 signature"));
+    }
+    let length = u16::from_be_bytes([fixed[14], fixed[15]]) as usize;
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+    parse_v2(fixed[12], fixed[13], &payload)
+}
+
+/// Parses the addresses and TLVs of a binary header.
+///
+/// # Arguments
+///
+/// * `version_command` - The version in the high nibble and the command in the low one.
+/// * `family` - The address family in the high nibble and the transport in the low one.
+/// * `payload` - The addresses followed by the TLVs.
+fn parse_v2(version_command: u8, family: u8, payload: &[u8]) -> io::Result<ProxyHeader> {
+    if version_command >> 4 != 2 {
+        return Err(malformed("Unsupported PROXY protocol version"));
+    }
+    let local = match version_command & 0x0f {
+        0x0 => true,
+        0x1 => false,
+        _ => return Err(malformed("Unknown PROXY protocol command")),
+    };
+    let address_length = match family >> 4 {
+        0x0 => 0,
+        0x1 => 12,
+        0x2 => 36,
+        0x3 => 216,
+        _ => return Err(malformed("Unknown PROXY protocol address family")),
+    };
+    if payload.len() < address_length {
+        return Err(malformed("The PROXY header is too short for its addresses"));
+    }
+    let (addresses, mut tlvs) = payload.split_at(address_length);
+
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+    // A LOCAL connection comes from the proxy itself, whatever addresses it carries.
+    let (source, destination) = match family >> 4 {
+        0x1 if !local => {
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::from(
+                    <[u8; 4]>::try_from(&addresses[at..at + 4]).unwrap(),
+                ))
+            };
+            (
+                Some(SocketAddr::new(ip(0), port(8))),
+                Some(SocketAddr::new(ip(4), port(10))),
+            )
+        }
+        0x2 if !local => {
+            let ip = |at: usize| {
+                IpAddr::V6(Ipv6Addr::from(
+                    <[u8; 16]>::try_from(&addresses[at..at + 16]).unwrap(),
+                ))
+            };
+            (
+                Some(SocketAddr::new(ip(0), port(32))),
+                Some(SocketAddr::new(ip(16), port(34))),
+            )
+        }
+        _ => (None, None),
+    };
+
+    let mut fields = Vec::new();
+    while !tlvs.is_empty() {
+        if tlvs.len() < 3 {
+            return Err(malformed("Truncated TLV in the PROXY header"));
+        }
+        let length = u16::from_be_bytes([tlvs[1], tlvs[2]]) as usize;
+        let Some(value) = tlvs.get(3..3 + length) else {
+            return Err(malformed("Truncated TLV in the PROXY header"));
+        };
+        fields.push((tlvs[0], value.to_vec()));
+        tlvs = &tlvs[3 + length..];
+    }
+
+    Ok(ProxyHeader {
+        version: 2,
+        source,
+        destination,
+        tlvs: fields,
+    })
+}
+
+impl Request {
+    /// Returns the PROXY protocol header the load balancer sent ahead of the connection.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&ProxyHeader>`, or `None` if the listener does not expect the PROXY protocol.
+    pub fn proxy_header(&self) -> Option<&ProxyHeader> {
+        self.extensions.get::<ProxyHeader>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ListenerConfig, Response, Route, Server};
+    use http::StatusCode;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpStream;
+
+    /// Builds a v2 header for a TCP over IPv4 connection with the given TLVs.
+    fn v2_header(command: u8, tlvs: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut payload = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        payload.extend_from_slice(&56324u16.to_be_bytes());
+        payload.extend_from_slice(&443u16.to_be_bytes());
+        for (kind, value) in tlvs {
+            payload.push(*kind);
+            payload.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            payload.extend_from_slice(value);
+        }
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20 | command, 0x11]);
+        header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        header.extend_from_slice(&payload);
+        header
+    }
+
+    #[tokio::test]
+    /// Tests reading text headers, leaving the request after them unread.
+    async fn read_v1_headers() {
+        let mut input: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\nGET /";
+        let header = read_header(&mut input).await.unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("[2001:db8::2]:443".parse().unwrap())
+        );
+        assert_eq!(input, b"GET /");
+
+        let mut input: &[u8] = b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n";
+        assert_eq!(read_header(&mut input).await.unwrap().source, None);
+
+        for line in [
+            &b"GET / HTTP/1.1\r\n\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+            b"PROXY TCP4 2001:db8::1 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 056324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 65536 443\r\n",
+            b"PROXY TCP4  192.0.2.1 198.51.100.1 56324 443\r\n",
+        ] {
+            let mut input = line;
+            assert!(read_header(&mut input).await.is_err());
+        }
+        let mut long = b"PROXY UNKNOWN ".to_vec();
+        long.extend_from_slice(&[b'x'; 100]);
+        long.extend_from_slice(b"\r\n");
+        assert!(read_header(&mut long.as_slice()).await.is_err());
+    }
+
+    #[tokio::test]
+    /// Tests reading binary headers with TLVs, LOCAL connections and malformed headers.
+    async fn read_v2_headers() {
+        let mut bytes = v2_header(0x1, &[(0x02, b"example.com"), (0xe0, b"")]);
+        let input = [bytes.as_slice(), b"GET /"].concat();
+        let mut reader = input.as_slice();
+        let header = read_header(&mut reader).await.unwrap();
+        assert_eq!(header.version, 2);
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.1:443".parse().unwrap())
+        );
+        assert_eq!(header.authority(), Some("example.com"));
+        assert_eq!(header.tlv(0xe0), Some(&b""[..]));
+        assert_eq!(header.alpn(), None);
+        assert_eq!(reader, b"GET /");
+
+        let local = v2_header(0x0, &[]);
+        assert_eq!(
+            read_header(&mut local.as_slice()).await.unwrap().source,
+            None
+        );
+
+        // A TLV running past the end of the header.
+        let end = bytes.len();
+        bytes[end - 1] = 0x10;
+        assert!(read_header(&mut bytes.as_slice()).await.is_err());
+        // An unknown command, and a version other than 2.
+        let mut command = v2_header(0x1, &[]);
+        command[12] = 0x22;
+        assert!(read_header(&mut command.as_slice()).await.is_err());
+        command[12] = 0x11;
+        assert!(read_header(&mut command.as_slice()).await.is_err());
+    }
+
+    #[tokio::test]
+    /// Tests serving a listener behind a load balancer, which must send a header in time.
+    async fn serve_behind_proxy() {
+        /// Responds with the address of the client.
+        async fn whoami(req: &mut Request, res: &mut Response) {
+            let body = format!("{:?} {:?}", req.client_addr(), req.peer_addr());
+            res.text(&body, StatusCode::OK).await;
+        }
+
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.listen_on_host = false;
+        server.proxy_protocol_timeout = Duration::from_millis(200);
+        server.listeners = vec![ListenerConfig::tcp("127.0.0.1:0").proxy_protocol(true)];
+        server
+            .add_route(Route::new(
+                "GET",
+                "/whoami",
+                Arc::new(|req, res| Box::pin(whoami(req, res))),
+            ))
+            .await;
+        server.start().await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let local = client.local_addr().unwrap();
+        client
+            .write_all(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n")
+            .await
+            .unwrap();
+        client
+            .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(&format!("Some(192.0.2.1:56324) Some({local})")));
+
+        // Requests without a header, or too slow to send one, are dropped unanswered.
+        for request in [&b"GET /whoami HTTP/1.1\r\nHost: localhost\r\n\r\n"[..], b""] {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(request).await.unwrap();
+            // Closing with the request unread resets the connection.
+            let mut response = Vec::new();
+            let read = client.read_to_end(&mut response).await;
+            assert!(read.is_err() || response.is_empty());
+        }
+
+        server.shutdown().await;
+    }
+}
@@ -0,0 +1,280 @@
+use crate::request::Request;
+use std::ops::Range;
+
+/// The maximum number of ranges served in one response; longer range sets are ignored.
+const MAX_RANGES: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The `Range` and `If-Range` headers of a `GET` request, stored in the response's extensions
+/// by the server so byte bodies can be sent partially.
+pub(crate) struct RangeHeaders {
+    /// The value of the `Range` header.
+    pub(crate) range: String,
+    /// The value of the `If-Range` header, if any.
+    pub(crate) if_range: Option<String>,
+}
+
+impl RangeHeaders {
+    /// Extracts the range headers of a request.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<RangeHeaders>`, or `None` if the request is not a `GET` or has no `Range` header.
+    pub(crate) fn from_request(req: &Request) -> Option<Self> {
+        if req.method() != "GET" {
+            return None;
+        }
+        Some(RangeHeaders {
+            range: req.get_header("Range")?.to_string(),
+            if_range: req.get_header("If-Range").map(str::to_string),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The part of a representation to send in response to a range request.
+pub(crate) enum ByteRanges {
+    /// The whole representation, with a `200 OK`.
+    Full,
+    /// One or more non-overlapping ranges in ascending order, with a `206 Partial Content`.
+    Partial(Vec<Range<u64>>),
+    /// None of the requested ranges overlaps the representation, so a `416` is sent.
+    Unsatisfiable,
+}
+
+/// Decides which part of a representation to send, following RFC 9110 section 14.
+///
+/// Malformed `Range` headers, units other than `bytes`, and `If-Range` validators that do not
+/// match the current representation all result in the whole representation being sent.
+///
+/// # Arguments
+///
+/// * `headers` - The range headers of the request.
+/// * `etag` - The `ETag` of the representation, if any.
+/// * `last_modified` - The `Last-Modified` date of the representation, if any.
+/// * `len` - The length in bytes of the representation.
+///
+/// # Returns
+///
+/// The `ByteRanges` to send.
+pub(crate) fn evaluate(
+    headers: &RangeHeaders,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+    len: u64,
+) -> ByteRanges {
+    if let Some(ref if_range) = headers.if_range
+        && !if_range_matches(if_range.trim(), etag, last_modified)
+    {
+        return ByteRanges::Full;
+    }
+
+    let Some(specs) = headers.range.trim().strip_prefix("bytes=") else {
+        return ByteRanges::Full;
+    };
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let Some((start, end)) = spec.trim().split_once('-') else {
+            return ByteRanges::Full;
+        };
+        let range = match (start.trim(), end.trim()) {
+            ("", "") => return ByteRanges::Full,
+            // A suffix range selects the last `n` bytes.
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => continue,
+                Ok(n) => len.saturating_sub(n)..len,
+                Err(_) => return ByteRanges::Full,
+            },
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return ByteRanges::Full;
+                };
+                let end = match end {
+                    "" => len,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end.saturating_add(1).min(len),
+                        _ => return ByteRanges::Full,
+                    },
+                };
+                if start >= len {
+                    continue;
+                }
+                start..end
+            }
+        };
+        if !range.is_empty() {
+            ranges.push(range);
+        }
+    }
+
+    if ranges.is_empty() {
+        return ByteRanges::Unsatisfiable;
+    }
+
+    // Coalesce overlapping and adjacent ranges so clients cannot amplify the response.
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    if merged.len() > MAX_RANGES {
+        return ByteRanges::Full;
+    }
+    ByteRanges::Partial(merged)
+}
+
+/// Checks an `If-Range` validator against the current representation.
+///
+/// Entity tags must match strongly; dates must match the `Last-Modified` date exactly.
+fn if_range_matches(if_range: &str, etag: Option<&str>, last_modified: Option<&str>) -> bool {
+    if if_range.starts_with('"') {
+        return etag.is_some_and(|etag| etag == if_range);
+    }
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    match (
+        httpdate::parse_http_date(if_range),
+        last_modified.map(httpdate::parse_http_date),
+    ) {
+        (Ok(date), Some(Ok(modified))) => date == modified,
+        _ => false,
+    }
+}
+
+/// Formats the value of a `Content-Range` header for a range.
+pub(crate) fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start, range.end - 1)
+}
+
+/// Generates a random boundary for a `multipart/byteranges` body.
+pub(crate) fn boundary() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("Failed to generate a multipart boundary");
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Formats the delimiter and headers preceding one part of a `multipart/byteranges` body.
+pub(crate) fn part_head(
+    boundary: &str,
+    content_type: Option<&str>,
+    range: &Range<u64>,
+    len: u64,
+) -> String {
+    let mut head = format!("--{boundary}\r\n");
+    if let Some(content_type) = content_type {
+        head.push_str(&format!("Content-Type: {content_type}\r\n"));
+    }
+    head.push_str(&format!(
+        "Content-Range: {}\r\n\r\n",
+        content_range(range, len)
+    ));
+    head
+}
+
+/// Formats the delimiter closing a `multipart/byteranges` body.
+pub(crate) fn closing_delimiter(boundary: &str) -> String {
+    format!("--{boundary}--\r\n")
+}
+
+/// Computes the length of a `multipart/byteranges` body without building it.
+pub(crate) fn multipart_len(
+    boundary: &str,
+    content_type: Option<&str>,
+    ranges: &[Range<u64>],
+    len: u64,
+) -> u64 {
+    let parts: u64 = ranges
+        .iter()
+        .map(|range| {
+            // Each part is followed by a CRLF before the next delimiter.
+            part_head(boundary, content_type, range, len).len() as u64 + range.end - range.start + 2
+        })
+        .sum();
+    parts + closing_delimiter(boundary).len() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::shared_stream;
+    use crate::{Response, Server};
+    use http::StatusCode;
+    use std::sync::Arc;
+    use tokio::io::{duplex, AsyncReadExt};
+
+    /// Builds the range headers of a request.
+    fn headers(range: &str, if_range: Option<&str>) -> RangeHeaders {
+        RangeHeaders {
+            range: range.to_string(),
+            if_range: if_range.map(str::to_string),
+        }
+    }
+
+    /// Builds the expected outcome of a range request.
+    fn partial(ranges: &[(u64, u64)]) -> ByteRanges {
+        ByteRanges::Partial(ranges.iter().map(|&(start, end)| start..end).collect())
+    }
+
+    #[test]
+    /// Tests the parsing of range sets, including suffix, open-ended, overlapping and invalid ranges.
+    fn ranges() {
+        let eval = |range: &str| evaluate(&headers(range, None), None, None, 100);
+        assert_eq!(eval("bytes=0-9"), partial(&[(0, 10)]));
+        assert_eq!(eval("bytes=-10"), partial(&[(90, 100)]));
+        assert_eq!(eval("bytes=-500"), partial(&[(0, 100)]));
+        assert_eq!(eval("bytes=95-"), partial(&[(95, 100)]));
+        assert_eq!(eval("bytes=90-200"), partial(&[(90, 100)]));
+        assert_eq!(
+            eval("bytes=50-59, 0-9,5-14 ,15-19"),
+            partial(&[(0, 20), (50, 60)])
+        );
+        assert_eq!(eval("bytes=100-"), ByteRanges::Unsatisfiable);
+        assert_eq!(eval("bytes=-0"), ByteRanges::Unsatisfiable);
+        assert_eq!(eval("bytes=5-1"), ByteRanges::Full);
+        assert_eq!(eval("bytes=a-b"), ByteRanges::Full);
+        assert_eq!(eval("items=0-1"), ByteRanges::Full);
+    }
+
+    #[test]
+    /// Tests `If-Range` validation against entity tags and dates.
+    fn if_range() {
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let eval = |if_range: &str| {
+            evaluate(
+                &headers("bytes=0-0", Some(if_range)),
+                Some("\"v1\""),
+                Some(date),
+                10,
+            )
+        };
+        assert_eq!(eval("\"v1\""), partial(&[(0, 1)]));
+        assert_eq!(eval("\"v2\""), ByteRanges::Full);
+        assert_eq!(eval("W/\"v1\""), ByteRanges::Full);
+        assert_eq!(eval(date), partial(&[(0, 1)]));
+        assert_eq!(eval("Wed, 21 Oct 2015 07:28:01 GMT"), ByteRanges::Full);
+    }
+
+    #[tokio::test]
+    /// Tests range requests against a byte body sent by a handler.
+    async fn response_bytes() {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (mut client, server_stream) = duplex(4096);
+        let mut res = Response::new(shared_stream(server_stream), "HTTP/1.1", server);
+        res.extensions.insert(headers("bytes=-3", None));
+        res.bytes(b"abcdefgh", "audio/mpeg", StatusCode::OK).await;
+        drop(res);
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        assert!(output.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(output.contains("Accept-Ranges: bytes\r\n"));
+        assert!(output.contains("Content-Range: bytes 5-7/8\r\n"));
+        assert!(output.ends_with("\r\n\r\nfgh"));
+    }
+}
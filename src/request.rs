@@ -1,10 +1,20 @@
+use crate::compression;
+use crate::cookie::{collect_cookies, parse_cookie_header};
+use crate::form::{Form, FormError, FORM_CONTENT_TYPE};
+use crate::forwarded::{self, Forwarded, ForwardedHeaders};
+use crate::multipart::{Multipart, MultipartError, MULTIPART_CONTENT_TYPE};
+use crate::proxy_protocol::ProxyHeader;
+use crate::session::Session;
+use crate::transport::SharedStream;
 use crate::{BufferPool, Server};
 use http::method::Method;
+use http::{Extensions, StatusCode};
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 // use std::io::{BufRead, BufReader};
 // use std::io::{Read, Write};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 use tokio::sync::Mutex;
 
 #[derive(Debug, Clone)]
@@ -14,6 +24,65 @@ struct Span {
     length: usize,
 }
 
+#[derive(Debug, Clone, Copy)]
+/// Why a request could not be read from a connection.
+pub(crate) struct ReadError {
+    /// The status to respond with before closing the connection, or `None` if the client
+    /// cannot be answered, e.g. because it closed the connection.
+    pub(crate) status: Option<StatusCode>,
+    /// What went wrong.
+    pub(crate) message: &'static str,
+}
+
+impl ReadError {
+    /// Creates an error for a connection that failed or closed before a request was read.
+    pub(crate) fn closed(message: &'static str) -> Self {
+        ReadError {
+            status: None,
+            message,
+        }
+    }
+
+    /// Creates an error for a request the client is told about with a status.
+    fn rejected(status: StatusCode, message: &'static str) -> Self {
+        ReadError {
+            status: Some(status),
+            message,
+        }
+    }
+
+    /// Creates an error for a malformed request, answered with `400 Bad Request`.
+    fn bad_request(message: &'static str) -> Self {
+        ReadError::rejected(StatusCode::BAD_REQUEST, message)
+    }
+}
+
+/// Checks whether bytes form an HTTP token, as header names and methods must.
+///
+/// # Arguments
+///
+/// * `bytes` - The bytes to check.
+///
+/// # Returns
+///
+/// `true` if the bytes are not empty and only contain token characters.
+pub(crate) fn is_token(bytes: &[u8]) -> bool {
+    !bytes.is_empty()
+        && bytes
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(b))
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The address of the peer of a TCP connection, stored in the request's extensions.
+pub(crate) struct PeerAddr(pub(crate) SocketAddr);
+
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy)]
+/// Marks a request received from a trusted proxy over a Unix socket, stored in the request's
+/// extensions.
+pub(crate) struct TrustedPeer;
+
 #[allow(dead_code)]
 #[derive(Debug)]
 /// Represents an HTTP request parsed from a `TcpStream`.
@@ -30,6 +99,8 @@ pub struct Request {
     pub path_params: HashMap<String, String>,
     /// A map of query parameters extracted from the request URL.
     pub query_params: HashMap<String, String>,
+    /// Per-request values set by middlewares, e.g. the current `Session`.
+    pub extensions: Extensions,
     /// The buffer containing the raw HTTP request data.
     buffer: Vec<u8>,
     /// A thread-safe buffer pool used to manage memory for request buffers.
@@ -38,6 +109,15 @@ pub struct Request {
     cursor: usize,
     /// A thread-safe server instance that is handling the request.
     server: Arc<Server>,
+    /// The connection the request was read from, used to stream bodies that are not buffered.
+    stream: SharedStream,
+    /// The position in the buffer of the first body byte not yet handed out by `read_body_chunk`.
+    body_offset: usize,
+    /// The number of body bytes still waiting to be read from the stream.
+    body_remaining: usize,
+    /// Bytes read from the connection past the end of this request, i.e. the start of the
+    /// next pipelined request.
+    read_ahead: Vec<u8>,
 }
 
 impl Drop for Request {
@@ -58,11 +138,11 @@ impl Drop for Request {
 
 impl Request {
     /// Creates a new `Request` instance by reading the HTTP request from the
-    /// provided `TcpStream`.
+    /// provided `SharedStream`.
     ///
     /// # Arguments
     ///
-    /// * `stream` - A `TcpStream` or `MockStream` mutable reference representing the incoming connection.
+    /// * `stream` - A `SharedStream` wrapping the `TcpStream` or `DuplexStream` of the incoming connection.
     /// * `server` - A thread-safe mutable reference to the `Server` instance that will handle the request.
     ///
     /// # Returns
@@ -73,10 +153,33 @@ impl Request {
     ///
     /// Returns an error message if the request cannot be parsed, such as if the connection is closed by the peer,
     /// if there is an error reading from the stream, or if the headers are too large.
-    pub async fn new<T: AsyncRead + AsyncWrite + Unpin>(
-        stream: &mut T,
+    pub async fn new(stream: SharedStream, server: Arc<Server>) -> Result<Self, &'static str> {
+        Request::read(stream, server, Vec::new())
+            .await
+            .map_err(|e| e.message)
+    }
+
+    /// Reads the next request on a keep-alive connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - A `SharedStream` wrapping the connection.
+    /// * `server` - A thread-safe mutable reference to the `Server` instance that will handle the request.
+    /// * `read_ahead` - The bytes the previous request read past its end, which are parsed first.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a `Request` instance if successful.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Request::new`], with the status to answer malformed or
+    /// oversized requests with.
+    pub(crate) async fn read(
+        stream: SharedStream,
         server: Arc<Server>,
-    ) -> Result<Self, &'static str> {
+        read_ahead: Vec<u8>,
+    ) -> Result<Self, ReadError> {
         let mut request = Request {
             method: None,
             path: None,
@@ -84,10 +187,15 @@ impl Request {
             headers: Some(Vec::new()),
             path_params: HashMap::new(),
             query_params: HashMap::new(),
+            extensions: Extensions::new(),
             buffer: Vec::new(),
             buffer_pool: Arc::new(Mutex::new(BufferPool::new(10, server.clone()))),
             cursor: 0,
             server: server.clone(),
+            stream: stream.clone(),
+            body_offset: 0,
+            body_remaining: 0,
+            read_ahead,
         };
         let parsed_req = request.parse(stream).await;
         match parsed_req {
@@ -96,11 +204,14 @@ impl Request {
         }
     }
 
-    /// Handles the incoming connection by reading the HTTP request lines and headers from the `TcpStream`.
+    /// Handles the incoming connection by reading the HTTP request lines, headers and body from the `TcpStream`.
+    ///
+    /// `multipart/form-data` bodies are not read here; only the bytes already buffered are kept,
+    /// and the rest is streamed from the connection by [`Request::multipart`].
     ///
     /// # Arguments
     ///
-    /// * `stream` - A `TcpStream` or `MockStream` mutable reference representing the incoming connection.
+    /// * `stream` - A `SharedStream` wrapping the incoming connection.
     ///
     /// # Returns
     ///
@@ -108,33 +219,30 @@ impl Request {
     ///
     /// # Errors
     ///
-    /// Returns an error message if the request cannot be parsed, such as if the connection is closed by the peer,
-    /// if there is an error reading from the stream, or if the headers are too large.
-    async fn parse<T: AsyncRead + AsyncWrite + Unpin>(
-        &mut self,
-        stream: &mut T,
-    ) -> Result<(), &'static str> {
-        let mut buf_reader = BufReader::new(stream);
+    /// Returns a `ReadError` if the connection is closed by the peer or fails, with `400 Bad
+    /// Request` if the request is malformed, `431 Request Header Fields Too Large` or `413
+    /// Payload Too Large` if the headers or body are too large, and `501 Not Implemented` if
+    /// the body has a `Transfer-Encoding`.
+    async fn parse(&mut self, stream: SharedStream) -> Result<(), ReadError> {
+        let mut stream = stream.lock().await;
+        // Bytes left over by the previous request on the connection come before any new ones.
+        let read_ahead = std::mem::take(&mut self.read_ahead);
+        let mut buf_reader = BufReader::new(read_ahead.as_slice().chain(&mut *stream));
         let mut headers_len = 0;
 
         if let Some(buffer) = self.buffer_pool.lock().await.acquire().await {
             self.buffer = buffer;
         } else {
-            return Err("Failed to acquire buffer from pool");
+            return Err(ReadError::closed("Failed to acquire buffer from pool"));
         }
 
         loop {
             let bytes = match buf_reader.read_until(b'\n', self.buffer.as_mut()).await {
-                Ok(0) => Err("Connection closed by peer"),
+                Ok(0) => Err(ReadError::closed("Connection closed by peer")),
                 Ok(n) => Ok(n),
-                Err(_) => Err("Error reading from stream"),
+                Err(_) => Err(ReadError::closed("Error reading from stream")),
             };
-
-            if let Ok(size) = bytes {
-                headers_len += size;
-            } else {
-                return Err("Failed to read from stream");
-            }
+            headers_len += bytes?;
 
             // Checks for the end of the headers section
             if self.buffer.ends_with(b"\r\n\r\n") {
@@ -142,73 +250,156 @@ impl Request {
             }
 
             if headers_len > 4096 {
-                return Err("Headers too large");
+                return Err(ReadError::rejected(
+                    StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                    "Headers too large",
+                ));
             }
         }
 
         // Parse request line (e.g., "GET /path HTTP/1.1")
-        if let Some(line_end) = self.buffer[self.cursor..].iter().position(|&b| b == b'\n') {
+        let Some(line_end) = self.buffer[self.cursor..].iter().position(|&b| b == b'\n') else {
+            return Err(ReadError::bad_request("Invalid request line"));
+        };
+        let line = &self.buffer[self.cursor..self.cursor + line_end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let mut parts = line.split(|&b| b == b' ');
+        let (Some(method), Some(path), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ReadError::bad_request("Invalid request line"));
+        };
+        // The accessors rely on these being valid, so nothing malformed gets past here.
+        if !is_token(method)
+            || path.is_empty()
+            || std::str::from_utf8(path).is_err()
+            || !version.starts_with(b"HTTP/")
+            || !version.is_ascii()
+        {
+            return Err(ReadError::bad_request("Invalid request line"));
+        }
+        self.method = Some(Span {
+            start: self.cursor,
+            length: method.len(),
+        });
+        self.path = Some(Span {
+            start: self.cursor + method.len() + 1,
+            length: path.len(),
+        });
+        self.http_version = Some(Span {
+            start: self.cursor + method.len() + path.len() + 2,
+            length: version.len(),
+        });
+        self.cursor += line_end + 1; // Move cursor to headers start
+
+        // Parse headers
+        loop {
+            let Some(line_end) = self.buffer[self.cursor..].iter().position(|&b| b == b'\n') else {
+                return Err(ReadError::bad_request("Invalid header line"));
+            };
             let line = &self.buffer[self.cursor..self.cursor + line_end];
-            let mut parts = line.split(|&b| b == b' ');
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
 
-            if let Some(method_bytes) = parts.next() {
-                self.method = Some(Span {
-                    start: self.cursor,
-                    length: method_bytes.len(),
-                });
+            if line.is_empty() {
+                self.cursor += line_end + 1; // Move cursor to the request body
+                break; // End of headers
             }
-            let method_len = self.method.as_ref().map_or(0, |s| s.length);
 
-            if let Some(path_bytes) = parts.next() {
-                self.path = Some(Span {
-                    start: self.cursor + method_len + 1,
-                    length: path_bytes.len(),
-                });
+            // Names are tokens, so `Content-Length : 5` is refused rather than guessed at.
+            let Some(colon_pos) = line.iter().position(|&b| b == b':') else {
+                return Err(ReadError::bad_request("Invalid header line"));
+            };
+            if !is_token(&line[..colon_pos]) {
+                return Err(ReadError::bad_request("Invalid header name"));
             }
-
-            if let Some(version_bytes) = parts.next() {
-                self.http_version = Some(Span {
-                    start: self.cursor
-                        + method_len
-                        + 2
-                        + self.path.as_ref().map_or(0, |s| s.length),
-                    length: version_bytes.len() - 1,
-                });
+            let value = &line[colon_pos + 1..];
+            if value.iter().any(|&b| b == b'\r' || b == 0) {
+                return Err(ReadError::bad_request("Invalid header value"));
             }
+            // The value without the whitespace around it.
+            let is_space = |b: &u8| *b == b' ' || *b == b'\t';
+            let value_start = value
+                .iter()
+                .position(|b| !is_space(b))
+                .unwrap_or(value.len());
+            let value_end = value
+                .iter()
+                .rposition(|b| !is_space(b))
+                .map_or(0, |p| p + 1);
+            let key = Span {
+                start: self.cursor,
+                length: colon_pos,
+            };
+            let value = Span {
+                start: self.cursor + colon_pos + 1 + value_start,
+                length: value_end.saturating_sub(value_start),
+            };
+            self.headers.get_or_insert_with(Vec::new).push((key, value));
 
-            self.cursor += line_end + 1; // Move cursor to headers start
-        } else {
-            return Err("Invalid request line");
+            self.cursor += line_end + 1; // Move cursor to the next line
         }
 
-        // Parse headers
-        loop {
-            if let Some(line_end) = self.buffer[self.cursor..].iter().position(|&b| b == b'\n') {
-                let line = &self.buffer[self.cursor..self.cursor + line_end];
+        // A body framed any other way than by a single length could be read differently by a
+        // proxy in front, which would then forward part of it as the next request.
+        if self.raw_headers("Transfer-Encoding").next().is_some() {
+            return Err(if self.raw_headers("Content-Length").next().is_some() {
+                ReadError::bad_request("Transfer-Encoding with Content-Length")
+            } else {
+                ReadError::rejected(StatusCode::NOT_IMPLEMENTED, "Unsupported Transfer-Encoding")
+            });
+        }
 
-                if line.is_empty() || line == b"\r" {
-                    self.cursor += line_end + 3; // Move cursor to the request body
-                    break; // End of headers
+        // Read the body declared by the Content-Length header, if any
+        let lengths = self.raw_headers("Content-Length").collect::<Vec<_>>();
+        let content_length = match lengths.first() {
+            Some(value) if lengths.iter().all(|other| other == value) => {
+                match std::str::from_utf8(value).map(str::parse::<usize>) {
+                    Ok(Ok(n)) => n,
+                    _ => return Err(ReadError::bad_request("Invalid Content-Length header")),
                 }
+            }
+            Some(_) => return Err(ReadError::bad_request("Conflicting Content-Length headers")),
+            None => 0,
+        };
 
-                if let Some(colon_pos) = line.iter().position(|&b| b == b':') {
-                    let key = Span {
-                        start: self.cursor,
-                        length: colon_pos,
-                    };
-                    let value_start = self.cursor + colon_pos + 2; // Skip ": "
-                    let value_end = line.iter().position(|&b| b == b'\r').unwrap_or(line.len());
-                    let value = Span {
-                        start: value_start,
-                        length: value_end - colon_pos - 2,
-                    };
-
-                    self.headers.as_mut().unwrap().push((key, value));
-                }
+        self.body_offset = self.buffer.len();
 
-                self.cursor += line_end + 1; // Move cursor to the next line
+        // Whatever was read past the headers may hold the body and the next pipelined requests.
+        let mut pending = buf_reader.buffer().to_vec();
+        let (unread, _) = buf_reader.into_inner().into_inner();
+        pending.extend_from_slice(unread);
+        let buffered = pending.len().min(content_length);
+
+        if self.has_content_type(MULTIPART_CONTENT_TYPE) {
+            // Keep whatever was read ahead and leave the rest for streaming
+            self.buffer.extend_from_slice(&pending[..buffered]);
+            self.body_remaining = content_length - buffered;
+            pending.drain(..buffered);
+            self.read_ahead = pending;
+            return Ok(());
+        }
+
+        if content_length > self.server.max_body_size {
+            return Err(ReadError::rejected(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Body too large",
+            ));
+        }
+
+        if content_length > 0 {
+            let body_start = self.buffer.len();
+            self.buffer.extend_from_slice(&pending[..buffered]);
+            self.buffer.resize(body_start + content_length, 0);
+            if stream
+                .read_exact(&mut self.buffer[body_start + buffered..])
+                .await
+                .is_err()
+            {
+                return Err(ReadError::closed("Failed to read request body"));
             }
         }
+        pending.drain(..buffered);
+        self.read_ahead = pending;
 
         Ok(())
     }
@@ -262,10 +453,203 @@ impl Request {
     /// # Returns
     ///
     /// A slice of bytes representing the body of the request.
+    ///
+    /// # Notes
+    ///
+    /// `multipart/form-data` bodies are streamed, so only the part read ahead with the headers
+    /// is returned. Use [`Request::multipart`] to read them.
     pub fn body(&self) -> &[u8] {
         &self.buffer[self.cursor..]
     }
 
+    /// Decodes a body sent with a `Content-Encoding` header in place, so `body` returns the
+    /// decoded bytes. The `Content-Encoding` and `Content-Length` headers are left as received.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the body could be decoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns the status to respond with: `415 Unsupported Media Type` for encodings that are
+    /// not enabled or streamed `multipart/form-data` bodies, `413 Payload Too Large` if the
+    /// decoded body exceeds the server's `max_decompressed_size`, and `400 Bad Request` for corrupt data.
+    pub(crate) fn decode_body(&mut self) -> Result<(), StatusCode> {
+        let Some(content_encoding) = self.get_header("Content-Encoding") else {
+            return Ok(());
+        };
+        if content_encoding.trim().eq_ignore_ascii_case("identity") {
+            return Ok(());
+        }
+        if self.has_content_type(MULTIPART_CONTENT_TYPE) {
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+
+        let decoded = compression::decode(
+            content_encoding,
+            self.body(),
+            self.server.max_decompressed_size,
+        )?;
+        self.buffer.truncate(self.cursor);
+        self.buffer.extend_from_slice(&decoded);
+        Ok(())
+    }
+
+    /// Reads the next chunk of a streamed request body.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the next chunk, or `None` once the whole body has been read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the connection is closed or fails before the body is complete.
+    pub(crate) async fn read_body_chunk(&mut self) -> Result<Option<Vec<u8>>, &'static str> {
+        if self.body_offset < self.buffer.len() {
+            let chunk = self.buffer[self.body_offset..].to_vec();
+            self.body_offset = self.buffer.len();
+            return Ok(Some(chunk));
+        }
+
+        if self.body_remaining == 0 {
+            return Ok(None);
+        }
+
+        let mut chunk = vec![0; self.body_remaining.min(8 * 1024)];
+        let n = match self.stream.lock().await.read(&mut chunk).await {
+            Ok(0) => return Err("Connection closed by peer"),
+            Ok(n) => n,
+            Err(_) => return Err("Failed to read request body"),
+        };
+        self.body_remaining -= n;
+        chunk.truncate(n);
+        Ok(Some(chunk))
+    }
+
+    /// Starts streaming a `multipart/form-data` request body.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a `Multipart` reader that yields the fields one at a time,
+    /// or a `MultipartError` if the request cannot be read as multipart.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MultipartError::UnsupportedContentType` if the `Content-Type` header is not
+    /// `multipart/form-data`, `MultipartError::MissingBoundary` if it has no boundary, or
+    /// `MultipartError::PayloadTooLarge` if the declared body exceeds the server's limits.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use rusticore::Request;
+    /// # async fn handler(req: &mut Request) -> Result<(), rusticore::MultipartError> {
+    /// let mut multipart = req.multipart()?;
+    /// while let Some(mut field) = multipart.next_field().await? {
+    ///     if field.file_name().is_some() {
+    ///         let file = field.save_to_temp().await?;
+    ///         println!("saved {} bytes to {:?}", file.size(), file.path());
+    ///     } else {
+    ///         let name = field.name().unwrap_or_default().to_string();
+    ///         println!("{name} = {}", field.text().await?);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn multipart(&mut self) -> Result<Multipart<'_>, MultipartError> {
+        let content_type = match self.get_header("Content-Type") {
+            Some(value) if self.has_content_type(MULTIPART_CONTENT_TYPE) => value,
+            _ => return Err(MultipartError::UnsupportedContentType),
+        };
+        let boundary = match crate::multipart::boundary(content_type) {
+            Some(boundary) => boundary,
+            None => return Err(MultipartError::MissingBoundary),
+        };
+
+        let limits = self.server.multipart_limits.clone();
+        let size = self.buffer.len() - self.body_offset + self.body_remaining;
+        if size > limits.max_total_size {
+            return Err(MultipartError::PayloadTooLarge {
+                limit: limits.max_total_size,
+            });
+        }
+
+        Ok(Multipart::new(self, &boundary, limits))
+    }
+
+    /// Decodes an `application/x-www-form-urlencoded` request body.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the decoded `Form` if successful, or a `FormError` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FormError::UnsupportedContentType` if the `Content-Type` header is not
+    /// `application/x-www-form-urlencoded`, or `FormError::PayloadTooLarge` if the body
+    /// exceeds the server's `max_form_size`.
+    pub fn form(&self) -> Result<Form, FormError> {
+        self.form_body().map(Form::parse)
+    }
+
+    #[cfg(feature = "form")]
+    /// Deserializes an `application/x-www-form-urlencoded` request body into `T`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the deserialized value if successful, or a `FormError` otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Request::form`], or `FormError::Deserialize` if the
+    /// body does not match the shape of `T`.
+    pub fn form_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, FormError> {
+        let body = self.form_body()?;
+        serde_urlencoded::from_bytes(body).map_err(|e| FormError::Deserialize(e.to_string()))
+    }
+
+    /// Checks the content type and size of a form body before it is decoded.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the raw body if it can be decoded as a form, or a `FormError` otherwise.
+    fn form_body(&self) -> Result<&[u8], FormError> {
+        if !self.has_content_type(FORM_CONTENT_TYPE) {
+            return Err(FormError::UnsupportedContentType);
+        }
+
+        let body = self.body();
+        if body.len() > self.server.max_form_size {
+            return Err(FormError::PayloadTooLarge {
+                size: body.len(),
+                limit: self.server.max_form_size,
+            });
+        }
+
+        Ok(body)
+    }
+
+    /// Checks whether the request's `Content-Type` header matches a media type, ignoring any parameters.
+    ///
+    /// # Arguments
+    ///
+    /// * `media_type` - The media type to compare against (e.g., `application/json`).
+    ///
+    /// # Returns
+    ///
+    /// `true` if the `Content-Type` header is present and matches, `false` otherwise.
+    pub fn has_content_type(&self, media_type: &str) -> bool {
+        self.get_header("Content-Type").is_some_and(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .eq_ignore_ascii_case(media_type)
+        })
+    }
+
     /// Returns the value of a specific header from the HTTP request.
     ///
     /// # Arguments
@@ -280,22 +664,258 @@ impl Request {
             for (k, v) in headers {
                 if let Ok(header_key) =
                     std::str::from_utf8(&self.buffer[k.start..k.start + k.length])
+                    && header_key.eq_ignore_ascii_case(key)
                 {
-                    if header_key.eq_ignore_ascii_case(key) {
-                        return Some(
-                            std::str::from_utf8(&self.buffer[v.start..v.start + v.length]).unwrap(),
-                        );
-                    }
+                    // A value that is not UTF-8 cannot be returned as text.
+                    return std::str::from_utf8(&self.buffer[v.start..v.start + v.length]).ok();
                 }
             }
         }
         None
     }
+
+    /// Returns the raw bytes of every value of a header, which may not be UTF-8.
+    fn raw_headers<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.headers.iter().flatten().filter_map(|(k, v)| {
+            let name = &self.buffer[k.start..k.start + k.length];
+            name.eq_ignore_ascii_case(key.as_bytes())
+                .then(|| &self.buffer[v.start..v.start + v.length])
+        })
+    }
+
+    /// Checks whether a comma-separated header, e.g. `Connection`, contains a token, ignoring case.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The header key (case-insensitive).
+    /// * `token` - The token to look for.
+    ///
+    /// # Returns
+    ///
+    /// `true` if any value of the header lists the token.
+    pub(crate) fn header_has_token(&self, key: &str, token: &str) -> bool {
+        self.get_headers(key)
+            .iter()
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Checks whether the client wants the connection kept open after the response.
+    ///
+    /// HTTP/1.1 connections are persistent unless the request says `Connection: close`, while
+    /// HTTP/1.0 clients have to ask with `Connection: keep-alive`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if another request may follow on the connection.
+    pub(crate) fn wants_keep_alive(&self) -> bool {
+        match self.http_version() {
+            "HTTP/1.1" => !self.header_has_token("Connection", "close"),
+            "HTTP/1.0" => self.header_has_token("Connection", "keep-alive"),
+            _ => false,
+        }
+    }
+
+    /// Takes the bytes read past the end of the request, once its body has been read.
+    ///
+    /// # Returns
+    ///
+    /// The start of the next pipelined request, or `None` if part of a streamed body was never
+    /// read, in which case the connection cannot be reused.
+    pub(crate) fn take_read_ahead(&mut self) -> Option<Vec<u8>> {
+        if self.body_remaining > 0 {
+            return None;
+        }
+        Some(std::mem::take(&mut self.read_ahead))
+    }
+
+    /// Returns every value of a header that may be repeated in the HTTP request.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A string slice representing the header key to look for (case-insensitive).
+    ///
+    /// # Returns
+    ///
+    /// A vector of string slices in the order they were sent, empty if the header does not exist.
+    pub fn get_headers(&self, key: &str) -> Vec<&str> {
+        let mut values = Vec::new();
+        if let Some(headers) = &self.headers {
+            for (k, v) in headers {
+                if let Ok(header_key) =
+                    std::str::from_utf8(&self.buffer[k.start..k.start + k.length])
+                    && header_key.eq_ignore_ascii_case(key)
+                    && let Ok(value) =
+                        std::str::from_utf8(&self.buffer[v.start..v.start + v.length])
+                {
+                    values.push(value);
+                }
+            }
+        }
+        values
+    }
+
+    /// Returns all cookies sent with the HTTP request.
+    ///
+    /// # Returns
+    ///
+    /// A `HashMap` of cookie names to values, parsed from every `Cookie` header.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        collect_cookies(self.get_headers("Cookie"))
+    }
+
+    /// Returns the value of a specific cookie sent with the HTTP request.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie to look for (case-sensitive).
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&str>` containing the value of the cookie if found, or `None` if it was not sent.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.get_headers("Cookie")
+            .into_iter()
+            .flat_map(parse_cookie_header)
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the address of the peer the request was received from over TCP, which is the
+    /// load balancer or reverse proxy, if any.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<SocketAddr>`, or `None` if the request was not received over TCP.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.extensions.get::<PeerAddr>().map(|addr| addr.0)
+    }
+
+    /// Returns the address of the client that sent the request: the source address of the
+    /// PROXY protocol header if the listener expects one, or the peer address otherwise.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<SocketAddr>`, or `None` if the address is unknown, e.g. on a Unix socket.
+    pub fn client_addr(&self) -> Option<SocketAddr> {
+        self.extensions
+            .get::<ProxyHeader>()
+            .and_then(|header| header.source)
+            .or_else(|| self.peer_addr())
+    }
+
+    /// Resolves the client connection through the server's `forwarded_headers`, as long as
+    /// they were added by one of the server's `trusted_proxies`, or by a Unix socket peer its
+    /// listener trusts.
+    fn forwarded(&self) -> Option<Forwarded> {
+        let peer = match self.client_addr() {
+            Some(addr) => Some(addr.ip()),
+            #[cfg(unix)]
+            None if self.extensions.get::<TrustedPeer>().is_some() => None,
+            None => return None,
+        };
+        let hops = match self.server.forwarded_headers {
+            ForwardedHeaders::XForwarded => forwarded::parse_x_forwarded(
+                &self.get_headers("X-Forwarded-For"),
+                &self.get_headers("X-Forwarded-Proto"),
+                &self.get_headers("X-Forwarded-Host"),
+            ),
+            ForwardedHeaders::Forwarded => {
+                forwarded::parse_forwarded(&self.get_headers("Forwarded"))
+            }
+        };
+        Some(forwarded::resolve(peer, hops, &self.server.trusted_proxies))
+    }
+
+    /// Returns the IP address of the client that sent the request, looking past the proxies
+    /// in the server's `trusted_proxies`.
+    ///
+    /// The `X-Forwarded-For` or `Forwarded` header, per the server's `forwarded_headers`, is
+    /// read from the end for as long as the address it was received from is trusted. A hop
+    /// that does not identify its client stops the search at the proxy that reported it.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<IpAddr>`, or `None` if the address is unknown, e.g. on a Unix socket whose
+    /// peer is not trusted.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.forwarded().and_then(|forwarded| forwarded.client)
+    }
+
+    /// Returns the scheme the client used, as reported by a trusted proxy with the `proto`
+    /// parameter of `Forwarded` or `X-Forwarded-Proto`, or the scheme of the connection.
+    ///
+    /// # Returns
+    ///
+    /// A `String` such as `http` or `https`.
+    pub fn scheme(&self) -> String {
+        if let Some(proto) = self.forwarded().and_then(|forwarded| forwarded.proto) {
+            return proto;
+        }
+        #[cfg(feature = "tls")]
+        if self.tls().is_some() {
+            return "https".to_string();
+        }
+        "http".to_string()
+    }
+
+    /// Returns the host the client asked for, as reported by a trusted proxy with the `host`
+    /// parameter of `Forwarded` or `X-Forwarded-Host`, or the `Host` header.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<String>` containing the host, with its port if one was given, or `None` if
+    /// the request names no host.
+    pub fn host(&self) -> Option<String> {
+        self.forwarded()
+            .and_then(|forwarded| forwarded.host)
+            .or_else(|| self.get_header("Host").map(str::to_string))
+    }
+
+    /// Returns the session of the request, loaded by a `SessionMiddleware`.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&Session>` containing the session, or `None` if no `SessionMiddleware` is registered.
+    pub fn session(&self) -> Option<&Session> {
+        self.extensions.get::<Session>()
+    }
+
+    /// Returns the value of a cookie signed with [`Response::add_signed_cookie`](crate::Response::add_signed_cookie).
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie to look for.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<String>` containing the verified value, or `None` if the cookie is missing,
+    /// its signature is invalid, or the server has no `cookie_jar` configured.
+    pub fn signed_cookie(&self, name: &str) -> Option<String> {
+        let jar = self.server.cookie_jar.as_ref()?;
+        jar.verify(name, self.cookie(name)?)
+    }
+
+    /// Returns the value of a cookie encrypted with [`Response::add_private_cookie`](crate::Response::add_private_cookie).
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie to look for.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<String>` containing the decrypted value, or `None` if the cookie is missing,
+    /// cannot be authenticated, or the server has no `cookie_jar` configured.
+    pub fn private_cookie(&self, name: &str) -> Option<String> {
+        let jar = self.server.cookie_jar.as_ref()?;
+        jar.decrypt(name, self.cookie(name)?)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transport::shared_stream;
     use http::method::Method;
     use tokio::io::{duplex, AsyncWriteExt};
 
@@ -332,7 +952,8 @@ mod tests {
         let arc_server = Arc::new(server);
 
         // Create a duplex stream (in-memory async stream)
-        let (mut client, mut server_stream) = duplex(1024);
+        let (mut client, server_stream) = duplex(1024);
+        let stream = shared_stream(server_stream);
 
         // Write request data from the "client" side
         let request_data = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
@@ -342,7 +963,7 @@ mod tests {
 
         // Parse request from the "server" side
         let parse_fut = async {
-            let req = Request::new(&mut server_stream, arc_server.clone())
+            let req = Request::new(stream.clone(), arc_server.clone())
                 .await
                 .unwrap();
             assert_eq!(req.method(), Method::GET);
@@ -353,4 +974,217 @@ mod tests {
 
         tokio::join!(write_fut, parse_fut);
     }
+
+    #[tokio::test]
+    /// Tests that malformed requests are answered with `400 Bad Request` instead of being
+    /// dropped or crashing the connection task.
+    async fn test_malformed_requests() {
+        use crate::server::ConnectionInfo;
+
+        let arc_server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let cases: [(&[u8], &str); 8] = [
+            (b"GET / HTTP/1.1\r\nX-Empty:\r\n\r\n", "HTTP/1.1 200"),
+            (
+                b"GET / HTTP/1.1\r\nContent-Length: \xff\r\n\r\n",
+                "HTTP/1.1 400",
+            ),
+            (b"GET / HTTP/1.1\r\nContent-Length:\r\n\r\n", "HTTP/1.1 400"),
+            (
+                b"GET / HTTP/1.1\r\nContent-Length : 0\r\n\r\n",
+                "HTTP/1.1 400",
+            ),
+            (b"GET /\r\nHost: localhost\r\n\r\n", "HTTP/1.1 400"),
+            (b"G\x01T / HTTP/1.1\r\n\r\n", "HTTP/1.1 400"),
+            (
+                b"GET / HTTP/1.1\r\nContent-Length: 0\r\nContent-Length: 5\r\n\r\n",
+                "HTTP/1.1 400",
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 0\r\n\r\n",
+                "HTTP/1.1 400",
+            ),
+        ];
+        for (request, status) in cases {
+            let (mut client, server_stream) = duplex(4096);
+            let connection = tokio::spawn(Server::handle_connection(
+                arc_server.clone(),
+                server_stream,
+                ConnectionInfo::default(),
+            ));
+            client.write_all(request).await.unwrap();
+            client
+                .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).await.unwrap();
+            let response = String::from_utf8_lossy(&response);
+            assert!(response.starts_with(status), "{response}");
+            connection.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    /// Tests the parsing of cookies from one or more `Cookie` headers.
+    async fn test_cookies() {
+        let server = Server::new("localhost", 8080, false, None, None);
+        let arc_server = Arc::new(server);
+        let (mut client, server_stream) = duplex(1024);
+        let stream = shared_stream(server_stream);
+
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\nCookie: sid=abc; theme=\"dark\"\r\nCookie: lang=en\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let req = Request::new(stream.clone(), arc_server).await.unwrap();
+        assert_eq!(req.cookie("sid"), Some("abc"));
+        assert_eq!(req.cookie("theme"), Some("dark"));
+        assert_eq!(req.cookie("lang"), Some("en"));
+        assert_eq!(req.cookie("missing"), None);
+        assert_eq!(req.cookies().len(), 3);
+    }
+
+    #[tokio::test]
+    /// Tests the decoding of a URL-encoded form body.
+    /// It checks that the body is read according to `Content-Length` and decoded into a `Form`.
+    async fn test_form() {
+        let server = Server::new("localhost", 8080, false, None, None);
+        let arc_server = Arc::new(server);
+        let (mut client, server_stream) = duplex(1024);
+        let stream = shared_stream(server_stream);
+
+        let request_data = b"POST /login HTTP/1.1\r\nHost: localhost\r\n\
+            Content-Type: application/x-www-form-urlencoded; charset=utf-8\r\n\
+            Content-Length: 27\r\n\r\nuser=jane+doe&role=a&role=b";
+        client.write_all(request_data).await.unwrap();
+
+        let req = Request::new(stream.clone(), arc_server).await.unwrap();
+        assert_eq!(req.body(), b"user=jane+doe&role=a&role=b");
+        let form = req.form().unwrap();
+        assert_eq!(form.get("user"), Some("jane doe"));
+        assert_eq!(form.get_all("role"), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    /// Tests that forms are rejected when the content type is wrong or the body exceeds the limit.
+    async fn test_form_errors() {
+        let mut server = Server::new("localhost", 8080, false, None, None);
+        server.max_form_size = 4;
+        let arc_server = Arc::new(server);
+        let (mut client, server_stream) = duplex(1024);
+        let stream = shared_stream(server_stream);
+
+        client
+            .write_all(
+                b"POST / HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\r\na=b",
+            )
+            .await
+            .unwrap();
+        let req = Request::new(stream.clone(), arc_server.clone())
+            .await
+            .unwrap();
+        assert_eq!(req.form(), Err(FormError::UnsupportedContentType));
+
+        client
+            .write_all(b"POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 7\r\n\r\na=bcdef")
+            .await
+            .unwrap();
+        let req = Request::new(stream.clone(), arc_server).await.unwrap();
+        let err = req.form().unwrap_err();
+        assert_eq!(err, FormError::PayloadTooLarge { size: 7, limit: 4 });
+        assert_eq!(err.status_code(), http::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[cfg(feature = "form")]
+    #[tokio::test]
+    /// Tests the deserialization of a URL-encoded form body into a typed struct.
+    /// It checks that matching bodies are deserialized and mismatching ones are rejected.
+    async fn test_form_as() {
+        #[derive(serde::Deserialize)]
+        struct Login {
+            user: String,
+            age: u8,
+        }
+
+        let server = Server::new("localhost", 8080, false, None, None);
+        let arc_server = Arc::new(server);
+        let (mut client, server_stream) = duplex(1024);
+        let stream = shared_stream(server_stream);
+
+        client
+            .write_all(b"POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 22\r\n\r\nuser=jane%40doe&age=30")
+            .await
+            .unwrap();
+        let req = Request::new(stream.clone(), arc_server.clone())
+            .await
+            .unwrap();
+        let login = req.form_as::<Login>().unwrap();
+        assert_eq!(login.user, "jane@doe");
+        assert_eq!(login.age, 30);
+
+        client
+            .write_all(b"POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 17\r\n\r\nuser=jane&age=old")
+            .await
+            .unwrap();
+        let req = Request::new(stream.clone(), arc_server).await.unwrap();
+        assert!(matches!(
+            req.form_as::<Login>(),
+            Err(FormError::Deserialize(_))
+        ));
+    }
+
+    #[tokio::test]
+    /// Tests the decoding of compressed request bodies.
+    /// It checks that unsupported encodings are refused, and with the `gzip` feature that bodies
+    /// are decoded in place and decompression bombs are stopped at the server's limit.
+    async fn test_decode_body() {
+        let mut server = Server::new("localhost", 8080, false, None, None);
+        server.max_decompressed_size = 1000;
+        let arc_server = Arc::new(server);
+        let (mut client, server_stream) = duplex(4096);
+        let stream = shared_stream(server_stream);
+
+        client
+            .write_all(b"POST / HTTP/1.1\r\nContent-Encoding: zstd\r\nContent-Length: 3\r\n\r\nabc")
+            .await
+            .unwrap();
+        let mut req = Request::new(stream.clone(), arc_server.clone())
+            .await
+            .unwrap();
+        assert_eq!(req.decode_body(), Err(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+
+        #[cfg(feature = "gzip")]
+        {
+            use flate2::{write::GzEncoder, Compression};
+            use std::io::Write;
+
+            let gzip = |data: &[u8]| {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            };
+
+            for (data, expected) in [
+                (&b"{\"id\":1}"[..], Ok(())),
+                (&[b'0'; 1001][..], Err(StatusCode::PAYLOAD_TOO_LARGE)),
+            ] {
+                let body = gzip(data);
+                let head = format!(
+                    "POST / HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                    body.len()
+                );
+                client.write_all(head.as_bytes()).await.unwrap();
+                client.write_all(&body).await.unwrap();
+                let mut req = Request::new(stream.clone(), arc_server.clone())
+                    .await
+                    .unwrap();
+                assert_eq!(req.decode_body(), expected);
+                if expected.is_ok() {
+                    assert_eq!(req.body(), data);
+                }
+            }
+        }
+    }
 }
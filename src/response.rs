@@ -1,10 +1,16 @@
+use crate::compression;
+use crate::cookie::Cookie;
+use crate::range::{self, ByteRanges, RangeHeaders};
+use crate::request::is_token;
+use crate::transport::SharedStream;
 use crate::Server;
-use http::StatusCode;
+use http::{Extensions, StatusCode};
+use log::{info, warn};
 // use std::net::TcpStream;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
 
 #[derive(Debug)]
 /// Represents an HTTP response that can be sent back to a client.
@@ -14,11 +20,18 @@ pub struct Response {
     /// The HTTP version of the response.
     pub http_version: Arc<String>,
     /// The headers of the response.
-    pub headers: Vec<(&'static str, &'static str)>,
-    /// An optional TCP stream to which the response will be sent.
-    pub tcp_stream: Arc<Mutex<TcpStream>>,
+    pub headers: Vec<(String, String)>,
+    /// The connection to which the response will be sent.
+    pub tcp_stream: SharedStream,
     /// A thread-safe server instance that is handling the response.
     pub server: Arc<Server>,
+    /// Per-request values shared with middlewares, e.g. the current `Session`.
+    pub extensions: Extensions,
+    /// Whether the whole response has been written to the connection, shared with the
+    /// `BodyWriter` of a streamed body, which only sets it once the body is finished.
+    pub(crate) sent: Arc<AtomicBool>,
+    /// Whether the request was a `HEAD`, whose response carries the headers but no body.
+    pub(crate) head_only: bool,
 }
 
 impl Clone for Response {
@@ -34,11 +47,44 @@ impl Clone for Response {
             headers: self.headers.clone(),
             tcp_stream: self.tcp_stream.clone(),
             server: self.server.clone(),
+            extensions: self.extensions.clone(),
+            sent: self.sent.clone(),
+            head_only: self.head_only,
         }
     }
 }
 
+/// Checks whether a header can be written as is: the key is a token, and the value cannot end
+/// the header line.
+pub(crate) fn is_valid_header(key: &str, value: &str) -> bool {
+    is_token(key.as_bytes()) && !value.bytes().any(|b| matches!(b, b'\r' | b'\n' | 0))
+}
+
 impl Response {
+    /// Creates a new `200 OK` response with no headers.
+    ///
+    /// # Arguments
+    ///
+    /// * `tcp_stream` - The connection to which the response will be sent.
+    /// * `http_version` - The HTTP version of the response, usually the request's.
+    /// * `server` - A thread-safe server instance that is handling the response.
+    ///
+    /// # Returns
+    ///
+    /// A new `Response` instance.
+    pub fn new(tcp_stream: SharedStream, http_version: &str, server: Arc<Server>) -> Self {
+        Response {
+            status_code: StatusCode::OK,
+            http_version: Arc::new(http_version.to_string()),
+            headers: vec![],
+            tcp_stream,
+            server,
+            extensions: Extensions::new(),
+            sent: Arc::new(AtomicBool::new(false)),
+            head_only: false,
+        }
+    }
+
     /// Constructs the HTTP response byte from the provided `Response` object.
     ///
     /// # Arguments
@@ -66,8 +112,11 @@ impl Response {
         );
         response_bytes.extend_from_slice(b"\r\n");
 
-        // Write headers
+        // Write headers, leaving out any that would split the head
         for (key, value) in response.headers.iter() {
+            if !is_valid_header(key, value) {
+                continue;
+            }
             response_bytes.extend_from_slice(key.as_bytes());
             response_bytes.extend_from_slice(b": ");
             response_bytes.extend_from_slice(value.as_bytes());
@@ -81,19 +130,299 @@ impl Response {
         response_bytes
     }
 
+    /// Sets a header, replacing any existing values with the same key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The header key (case-insensitive), e.g. `Cache-Control`.
+    /// * `value` - The header value.
+    ///
+    /// # Notes
+    ///
+    /// A header whose key is not a token, or whose value contains CR, LF or NUL, is refused
+    /// and logged, so values taken from user input cannot inject headers.
+    pub fn set_header(&mut self, key: &str, value: impl Into<String>) {
+        let value = value.into();
+        if !self.accepts_header(key, &value) {
+            return;
+        }
+        self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.headers.push((key.to_string(), value));
+    }
+
+    /// Adds a header, keeping any existing values with the same key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The header key, e.g. `Set-Cookie`.
+    /// * `value` - The header value.
+    ///
+    /// # Notes
+    ///
+    /// Invalid headers are refused as with [`Response::set_header`].
+    pub fn add_header(&mut self, key: &str, value: impl Into<String>) {
+        let value = value.into();
+        if !self.accepts_header(key, &value) {
+            return;
+        }
+        self.headers.push((key.to_string(), value));
+    }
+
+    /// Checks a header before it is set, logging it if it is refused.
+    fn accepts_header(&self, key: &str, value: &str) -> bool {
+        let valid = is_valid_header(key, value);
+        if !valid {
+            warn!(target: self.server.get_target(), "Refused the invalid header {key:?}: {value:?}");
+        }
+        valid
+    }
+
+    /// Returns the first value of a header that has been set on the response.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A string slice representing the header key to look for (case-insensitive).
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&str>` containing the value of the header if found, or `None` if the header does not exist.
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Adds a `Set-Cookie` header for a cookie. Multiple cookies can be set on the same response.
+    ///
+    /// # Arguments
+    ///
+    /// * `cookie` - The cookie to set. It is refused and logged unless `Cookie::is_valid`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use rusticore::{Cookie, Response, SameSite};
+    /// # use http::StatusCode;
+    /// # async fn handler(res: &mut Response) {
+    /// res.add_cookie(Cookie::new("theme", "dark").path("/").max_age(std::time::Duration::from_secs(3600)));
+    /// res.add_cookie(Cookie::new("sid", "abc123").http_only(true).secure(true).same_site(SameSite::Lax));
+    /// res.text("Cookies set", StatusCode::OK).await;
+    /// # }
+    /// ```
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        if !cookie.is_valid() {
+            warn!(target: self.server.get_target(), "Refused the invalid cookie {:?}", cookie.name());
+            return;
+        }
+        self.add_header("Set-Cookie", cookie.to_string());
+    }
+
+    /// Adds a `Set-Cookie` header for a cookie signed with the server's `cookie_jar`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cookie` - The cookie to sign and set. Read it back with `Request::signed_cookie`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server has no `cookie_jar` configured.
+    pub fn add_signed_cookie(&mut self, cookie: Cookie) {
+        let jar = self
+            .server
+            .cookie_jar
+            .as_ref()
+            .expect("Server::cookie_jar must be set to use signed cookies");
+        let cookie = jar.sign(cookie);
+        self.add_cookie(cookie);
+    }
+
+    /// Adds a `Set-Cookie` header for a cookie encrypted with the server's `cookie_jar`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cookie` - The cookie to encrypt and set. Read it back with `Request::private_cookie`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server has no `cookie_jar` configured.
+    pub fn add_private_cookie(&mut self, cookie: Cookie) {
+        let jar = self
+            .server
+            .cookie_jar
+            .as_ref()
+            .expect("Server::cookie_jar must be set to use private cookies");
+        let cookie = jar.encrypt(cookie);
+        self.add_cookie(cookie);
+    }
+
+    /// Asks the client to delete a cookie by setting it again with an expiry in the past.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie to delete.
+    /// * `path` - The path the cookie was set with; the client only deletes a cookie if it matches.
+    pub fn remove_cookie(&mut self, name: &str, path: &str) {
+        self.add_cookie(Cookie::removal(name).path(path));
+    }
+
     /// Constructs a new response string from the `Response` instance.
     ///
     /// # Arguments
     ///
     /// * `body` - A string slice representing the body of the response.
     async fn send(&mut self, body: &str) {
-        let response_bytes = self.construct_response_bytes(self, body);
-        self.tcp_stream
+        self.send_bytes(body.as_bytes()).await;
+    }
+
+    /// Sends the response with a byte body.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The body of the response.
+    async fn send_bytes(&mut self, body: &[u8]) {
+        let compressed = compression::compress_body(self, body);
+        let body = compressed.as_deref().unwrap_or(body);
+        // The length delimits the body, so the connection can be reused for the next request.
+        if self.get_header("Content-Length").is_none()
+            && self.get_header("Transfer-Encoding").is_none()
+            && !self.status_code.is_informational()
+            && self.status_code != StatusCode::NO_CONTENT
+        {
+            self.set_header("Content-Length", body.len().to_string());
+        }
+        self.run_before_send().await;
+
+        let mut response_bytes = self.construct_response_bytes(self, "");
+        // A `HEAD` response describes the body, `Content-Length` included, without sending it.
+        if !self.head_only {
+            response_bytes.extend_from_slice(body);
+        }
+        let written = self
+            .tcp_stream
             .lock()
             .await
             .write_all(&response_bytes)
-            .await
-            .expect("Failed to write response to TCP stream");
+            .await;
+        // A client that went away is no reason to bring the server down.
+        match written {
+            Ok(()) => self.mark_sent(),
+            Err(e) => info!(target: self.server.get_target(), "Failed to send the response: {e}"),
+        }
+    }
+
+    /// Sends a byte body, honouring the `Range` and `If-Range` headers of the request.
+    ///
+    /// A `200 OK` response is turned into a `206 Partial Content` with one range, or a
+    /// `multipart/byteranges` body with several, or a `416 Range Not Satisfiable` if no
+    /// requested range overlaps the body. `If-Range` is checked against the `ETag` and
+    /// `Last-Modified` headers set on the response.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The complete body of the response.
+    async fn send_ranged(&mut self, body: &[u8]) {
+        if self.status_code != StatusCode::OK {
+            self.send_bytes(body).await;
+            return;
+        }
+        self.set_header("Accept-Ranges", "bytes");
+
+        let len = body.len() as u64;
+        let ranges = match self.extensions.get::<RangeHeaders>() {
+            Some(headers) => range::evaluate(
+                headers,
+                self.get_header("ETag"),
+                self.get_header("Last-Modified"),
+                len,
+            ),
+            None => ByteRanges::Full,
+        };
+
+        match ranges {
+            ByteRanges::Full => {
+                self.set_header("Content-Length", len.to_string());
+                self.send_bytes(body).await;
+            }
+            ByteRanges::Unsatisfiable => {
+                self.status_code = StatusCode::RANGE_NOT_SATISFIABLE;
+                self.set_header("Content-Range", format!("bytes */{len}"));
+                self.set_header("Content-Length", "0");
+                self.send_bytes(b"").await;
+            }
+            ByteRanges::Partial(ranges) if ranges.len() == 1 => {
+                let range = &ranges[0];
+                self.status_code = StatusCode::PARTIAL_CONTENT;
+                self.set_header("Content-Range", range::content_range(range, len));
+                self.set_header("Content-Length", (range.end - range.start).to_string());
+                self.send_bytes(&body[range.start as usize..range.end as usize])
+                    .await;
+            }
+            ByteRanges::Partial(ranges) => {
+                let boundary = range::boundary();
+                let content_type = self.get_header("Content-Type").map(str::to_string);
+                let mut multipart = Vec::new();
+                for range in ranges.iter() {
+                    let head = range::part_head(&boundary, content_type.as_deref(), range, len);
+                    multipart.extend_from_slice(head.as_bytes());
+                    multipart.extend_from_slice(&body[range.start as usize..range.end as usize]);
+                    multipart.extend_from_slice(b"\r\n");
+                }
+                multipart.extend_from_slice(range::closing_delimiter(&boundary).as_bytes());
+
+                self.status_code = StatusCode::PARTIAL_CONTENT;
+                self.set_header(
+                    "Content-Type",
+                    format!("multipart/byteranges; boundary={boundary}"),
+                );
+                self.set_header("Content-Length", multipart.len().to_string());
+                self.send_bytes(&multipart).await;
+            }
+        }
+    }
+
+    /// Sends the status line and headers only, leaving the body to be written to `tcp_stream` by the caller.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` indicating whether the head was written, which fails if the client
+    /// has gone away.
+    pub(crate) async fn send_head(&mut self) -> io::Result<()> {
+        self.run_before_send().await;
+
+        let head_bytes = self.construct_response_bytes(self, "");
+        self.tcp_stream.lock().await.write_all(&head_bytes).await
+    }
+
+    /// Records that the whole response has been written, so the connection can be reused.
+    pub(crate) fn mark_sent(&self) {
+        self.sent.store(true, Ordering::Release);
+    }
+
+    /// Checks whether the whole response has been written, body included.
+    pub(crate) fn is_sent(&self) -> bool {
+        self.sent.load(Ordering::Acquire)
+    }
+
+    /// Runs the `before_send` hook of every middleware registered on the server.
+    async fn run_before_send(&mut self) {
+        let middlewares = self.server.middlewares.read().await.clone();
+        for middleware in middlewares.iter() {
+            middleware.before_send(self).await;
+        }
+    }
+
+    /// Sends a binary response, e.g. media or a download, supporting range requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The bytes of the response body.
+    /// * `content_type` - The value of the `Content-Type` header.
+    /// * `status_code` - The HTTP status code for the response. Ranges are only served for `200 OK`.
+    pub async fn bytes(&mut self, body: &[u8], content_type: &str, status_code: StatusCode) {
+        self.status_code = status_code;
+        self.set_header("Content-Type", content_type);
+        self.send_ranged(body).await;
     }
 
     /// Sends an HTML response with the appropriate Content-Type header.
@@ -104,8 +433,7 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn html(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers
-            .push(("Content-Type", "text/html; charset=utf-8"));
+        self.set_header("Content-Type", "text/html; charset=utf-8");
         self.send(body).await;
     }
 
@@ -117,7 +445,7 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn json(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers.push(("Content-Type", "application/json"));
+        self.set_header("Content-Type", "application/json");
         self.send(body).await;
     }
 
@@ -129,8 +457,7 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn text(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers
-            .push(("Content-Type", "text/plain; charset=utf-8"));
+        self.set_header("Content-Type", "text/plain; charset=utf-8");
         self.send(body).await;
     }
 
@@ -142,8 +469,7 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn css(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers
-            .push(("Content-Type", "text/css; charset=utf-8"));
+        self.set_header("Content-Type", "text/css; charset=utf-8");
         self.send(body).await;
     }
 
@@ -155,8 +481,7 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn javascript(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers
-            .push(("Content-Type", "application/javascript"));
+        self.set_header("Content-Type", "application/javascript");
         self.send(body).await;
     }
 
@@ -168,8 +493,7 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn xml(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers
-            .push(("Content-Type", "application/xml; charset=utf-8"));
+        self.set_header("Content-Type", "application/xml; charset=utf-8");
         self.send(body).await;
     }
 
@@ -181,7 +505,7 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn pdf(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers.push(("Content-Type", "application/pdf"));
+        self.set_header("Content-Type", "application/pdf");
         self.send(body).await;
     }
 
@@ -193,23 +517,23 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn zip(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers.push(("Content-Type", "application/zip"));
+        self.set_header("Content-Type", "application/zip");
         self.send(body).await;
     }
 
-    /// Sends a PNG image response with the appropriate Content-Type header.
+    /// Sends an MP3 audio response with the appropriate Content-Type header, supporting range requests.
     ///
     /// # Arguments
     ///
-    /// * `body` - A string slice representing the PNG image body of the response.
+    /// * `body` - A string slice representing the MP3 audio body of the response.
     /// * `status_code` - The HTTP status code for the response.
     pub async fn audio_mp3(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers.push(("Content-Type", "audio/mpeg"));
-        self.send(body).await;
+        self.set_header("Content-Type", "audio/mpeg");
+        self.send_ranged(body.as_bytes()).await;
     }
 
-    /// Sends a MP4 video response with the appropriate Content-Type header.
+    /// Sends a MP4 video response with the appropriate Content-Type header, supporting range requests.
     ///
     /// # Arguments
     ///
@@ -217,8 +541,8 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn video_mp4(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers.push(("Content-Type", "video/mp4"));
-        self.send(body).await;
+        self.set_header("Content-Type", "video/mp4");
+        self.send_ranged(body.as_bytes()).await;
     }
 
     /// Sends a PNG image response with the appropriate Content-Type header.
@@ -229,7 +553,7 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn image_png(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers.push(("Content-Type", "image/png"));
+        self.set_header("Content-Type", "image/png");
         self.send(body).await;
     }
 
@@ -241,7 +565,7 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn image_jpeg(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers.push(("Content-Type", "image/jpeg"));
+        self.set_header("Content-Type", "image/jpeg");
         self.send(body).await;
     }
 
@@ -253,7 +577,54 @@ impl Response {
     /// * `status_code` - The HTTP status code for the response.
     pub async fn image_gif(&mut self, body: &str, status_code: StatusCode) {
         self.status_code = status_code;
-        self.headers.push(("Content-Type", "image/gif"));
+        self.set_header("Content-Type", "image/gif");
         self.send(body).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_stream;
+    use tokio::io::{duplex, AsyncReadExt};
+
+    #[tokio::test]
+    /// Tests that headers and cookies that would split the response are never written.
+    async fn refuse_header_injection() {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (mut client, server_stream) = duplex(4096);
+        let mut res = Response::new(shared_stream(server_stream), "HTTP/1.1", server);
+        res.set_header("X-Name", "x\r\nSet-Cookie: admin=1");
+        res.add_header("X-Bad Name", "x");
+        res.add_cookie(Cookie::new("sid", "x; Domain=evil.example"));
+        res.add_cookie(Cookie::new("sid", "abc"));
+        res.headers
+            .push(("X-Pushed".to_string(), "a\nb".to_string()));
+        res.text("ok", StatusCode::OK).await;
+        drop(res);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.contains("Set-Cookie: sid=abc\r\n"));
+        assert!(!response.contains("admin"));
+        assert!(!response.contains("evil"));
+        assert!(!response.contains("X-Bad"));
+        assert!(!response.contains("X-Name"));
+        assert!(!response.contains("X-Pushed"));
+    }
+
+    #[tokio::test]
+    /// Tests that writing to a client that has gone away fails instead of panicking.
+    async fn client_gone() {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (client, server_stream) = duplex(64);
+        drop(client);
+        let mut res = Response::new(shared_stream(server_stream), "HTTP/1.1", server);
+        assert!(res.send_head().await.is_err());
+        res.text("too late", StatusCode::OK).await;
+        assert!(res
+            .start_stream("text/plain", StatusCode::OK)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use socket2::SockRef;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+/// The listening sockets handed over by the previous process, in listener order.
+const LISTEN_FDS: &str = "RUSTICORE_LISTEN_FDS";
+/// The pipe the new process reports on once it is running.
+const READY_FD: &str = "RUSTICORE_READY_FD";
+/// The process that handed the sockets over, so only its direct child takes them.
+const PARENT_PID: &str = "RUSTICORE_PARENT_PID";
+
+/// Checks whether the handoff variables were set by the parent of this process.
+fn from_parent() -> bool {
+    std::env::var(PARENT_PID)
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::os::unix::process::parent_id())
+}
+
+/// Returns the listening sockets handed over by the process this one replaces.
+///
+/// # Returns
+///
+/// The file descriptors of the sockets in listener order, empty if this process was not
+/// started by a hot restart.
+pub(crate) fn inherited_fds() -> Vec<RawFd> {
+    if !from_parent() {
+        return Vec::new();
+    }
+    std::env::var(LISTEN_FDS)
+        .map(|fds| parse_fds(&fds))
+        .unwrap_or_default()
+}
+
+/// Parses a comma-separated list of file descriptors.
+fn parse_fds(fds: &str) -> Vec<RawFd> {
+    fds.split(',')
+        .filter_map(|fd| fd.trim().parse::<RawFd>().ok())
+        .filter(|fd| *fd >= 0)
+        .collect()
+}
+
+/// Tells the process this one replaces that it is running, so it can stop accepting and
+/// drain. Does nothing if this process was not started by a hot restart, or after the
+/// first call.
+pub(crate) fn report_ready() {
+    static REPORTED: std::sync::Once = std::sync::Once::new();
+    if !from_parent() {
+        return;
+    }
+    let Some(fd) = std::env::var(READY_FD)
+        .ok()
+        .and_then(|fd| fd.parse::<RawFd>().ok())
+    else {
+        return;
+    };
+    REPORTED.call_once(|| {
+        // SAFETY: the parent passed the write end of the pipe to this process only.
+        let mut pipe = unsafe { File::from_raw_fd(fd) };
+        let _ = pipe.write_all(b"1");
+    });
+}
+
+/// Starts the new binary with the listening sockets and waits for it to report that it is
+/// running.
+///
+/// The new process runs the same executable path and arguments, so a binary replaced on
+/// disk is picked up.
+///
+/// # Arguments
+///
+/// * `fds` - The listening sockets, in listener order.
+/// * `timeout` - How long the new process may take to start.
+///
+/// # Returns
+///
+/// An `io::Result` containing the process ID of the new process.
+///
+/// # Errors
+///
+/// Returns an error if the process cannot be started, or exits or times out before it
+/// reports that it is running; it is killed in the latter case.
+pub(crate) async fn spawn_successor(fds: &[RawFd], timeout: Duration) -> io::Result<u32> {
+    let (mut ready_reader, ready_writer) = io::pipe()?;
+    let ready_fd = OwnedFd::from(ready_writer);
+    let ready_raw = ready_fd.as_raw_fd();
+    let listen_fds = fds
+        .iter()
+        .map(|fd| fd.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(std::env::args_os().skip(1))
+        .env(LISTEN_FDS, listen_fds)
+        .env(READY_FD, ready_raw.to_string())
+        .env(PARENT_PID, std::process::id().to_string());
+    let inherited = fds
+        .iter()
+        .copied()
+        .chain(std::iter::once(ready_raw))
+        .collect::<Vec<_>>();
+    // SAFETY: only `fcntl`, which is async-signal-safe, runs between fork and exec.
+    unsafe {
+        command.pre_exec(move || {
+            for fd in inherited.iter() {
+                SockRef::from(&BorrowedFd::borrow_raw(*fd)).set_cloexec(false)?;
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    // The pipe only reaches end of file once the child is gone, if it never reports.
+    drop(ready_fd);
+
+    let pid = child.id();
+    let waiting = tokio::task::spawn_blocking(move || {
+        let mut buffer = [0u8; 1];
+        ready_reader.read(&mut buffer)
+    });
+    let failure = match tokio::time::timeout(timeout, waiting).await {
+        Ok(Ok(Ok(1))) => return Ok(pid),
+        Ok(Ok(Ok(_))) => io::Error::other("The new process exited before it was running"),
+        Ok(Ok(Err(e))) => e,
+        Ok(Err(e)) => io::Error::other(e),
+        Err(_) => io::Error::new(io::ErrorKind::TimedOut, "The new process did not start"),
+    };
+    reap(child).await;
+    Err(failure)
+}
+
+/// Kills a new process that failed to start and waits for it to exit.
+async fn reap(mut child: Child) {
+    let _ = tokio::task::spawn_blocking(move || {
+        let _ = child.kill();
+        child.wait()
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests parsing the list of handed over sockets.
+    fn parse_handed_over_fds() {
+        assert_eq!(parse_fds("3,4, 7"), vec![3, 4, 7]);
+        assert_eq!(parse_fds("3,x,-1"), vec![3]);
+        assert!(parse_fds("").is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+use crate::transport::Transport;
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+
+/// The size in bytes of the chunks a file is streamed in when `sendfile(2)` is not available.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Copies a byte range of a file to a transport.
+///
+/// When the transport is a plain `TcpStream` on Linux, the bytes are sent with `sendfile(2)`
+/// so they never pass through userspace buffers. Other transports, e.g. TLS streams or
+/// in-memory pipes, and file systems that do not support `sendfile(2)`, fall back to
+/// buffered streaming.
+///
+/// # Arguments
+///
+/// * `file` - The file to send.
+/// * `range` - The byte range of the file to send.
+/// * `stream` - The transport to send the bytes to.
+///
+/// # Returns
+///
+/// An `io::Result` indicating whether the whole range was sent.
+pub(crate) async fn copy_range(
+    file: &mut File,
+    range: Range<u64>,
+    stream: &mut dyn Transport,
+) -> io::Result<()> {
+    #[allow(unused_mut)]
+    let mut start = range.start;
+
+    #[cfg(target_os = "linux")]
+    if let Some(tcp) = stream.as_any_mut().downcast_mut::<tokio::net::TcpStream>() {
+        match linux::sendfile(file, &mut start, range.end, tcp).await {
+            Ok(()) => return Ok(()),
+            Err(e) if linux::is_unsupported(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    file.seek(SeekFrom::Start(start)).await?;
+    let mut reader = BufReader::with_capacity(CHUNK_SIZE, file.take(range.end - start));
+    tokio::io::copy_buf(&mut reader, stream).await?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use tokio::fs::File;
+    use tokio::io::Interest;
+    use tokio::net::TcpStream;
+
+    /// The maximum number of bytes passed to one `sendfile(2)` call, so one connection
+    /// cannot monopolise a worker thread.
+    const MAX_CHUNK: u64 = 1024 * 1024;
+
+    /// Sends a byte range of a file to a socket with `sendfile(2)`.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The file to send.
+    /// * `offset` - The offset of the next byte to send, advanced as bytes are sent.
+    /// * `end` - The offset one past the last byte to send.
+    /// * `tcp` - The socket to send the bytes to.
+    pub(super) async fn sendfile(
+        file: &File,
+        offset: &mut u64,
+        end: u64,
+        tcp: &TcpStream,
+    ) -> io::Result<()> {
+        let file_fd = file.as_raw_fd();
+        let socket_fd = tcp.as_raw_fd();
+
+        while *offset < end {
+            let count = (end - *offset).min(MAX_CHUNK) as usize;
+            tcp.writable().await?;
+            let result = tcp.try_io(Interest::WRITABLE, || {
+                let mut off = *offset as libc::off_t;
+                // SAFETY: both descriptors are open for the duration of the call, and `off`
+                // is a valid pointer to an `off_t`.
+                let sent = unsafe { libc::sendfile(socket_fd, file_fd, &mut off, count) };
+                if sent < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(sent as u64)
+                }
+            });
+            match result {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "File shrank while being sent",
+                    ));
+                }
+                Ok(sent) => *offset += sent,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether an error means `sendfile(2)` cannot be used for this file.
+    pub(super) fn is_unsupported(e: &io::Error) -> bool {
+        matches!(
+            e.raw_os_error(),
+            Some(libc::EINVAL) | Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    /// Tests copying file ranges to a `TcpStream` with `sendfile(2)` and to an in-memory transport.
+    async fn copy_ranges() {
+        let data: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut tmp, &data).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).await.unwrap();
+            received
+        });
+        let mut file = File::open(tmp.path()).await.unwrap();
+        copy_range(&mut file, 10..2_500_000, &mut server)
+            .await
+            .unwrap();
+        server.shutdown().await.unwrap();
+        drop(server);
+        assert_eq!(reader.await.unwrap(), &data[10..2_500_000]);
+
+        let (mut client, mut server) = duplex(1024);
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).await.unwrap();
+            received
+        });
+        copy_range(&mut file, 5..100_000, &mut server)
+            .await
+            .unwrap();
+        drop(server);
+        assert_eq!(reader.await.unwrap(), &data[5..100_000]);
+    }
+}
@@ -1,15 +1,46 @@
+use crate::compression;
+use crate::cookie_jar::CookieJar;
+use crate::forwarded::{Cidr, ForwardedHeaders};
+#[cfg(feature = "http2")]
+use crate::http2::{self, Http2Config};
+use crate::hub::{Hub, HubConfig};
+use crate::listener::{self, Connection, Listener, ListenerConfig};
 use crate::logging::init_logging;
-use crate::request::Request;
+use crate::middleware::Middleware;
+use crate::multipart::MultipartLimits;
+use crate::proxy_protocol::{self, ProxyHeader};
+use crate::range::RangeHeaders;
+#[cfg(unix)]
+use crate::request::TrustedPeer;
+use crate::request::{PeerAddr, ReadError, Request};
 use crate::response::Response;
+#[cfg(unix)]
+use crate::restart;
 use crate::routing::{index, Handler};
+#[cfg(unix)]
+use crate::systemd;
+#[cfg(feature = "tls")]
+use crate::tls::{self, TlsConfig, TlsInfo};
+use crate::transport::{shared_stream, SharedStream, Transport};
+#[cfg(unix)]
+use crate::unix::{self, PeerCredentials, UnixSocket};
 use crate::Route;
-use http::StatusCode;
-use log::info;
+use http::{Method, StatusCode};
+use log::{info, warn};
 use std::cmp::PartialEq;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::sync::{Mutex, RwLock};
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::fd::RawFd;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::AsyncRead;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, Mutex, RwLock};
+use tokio::task::{JoinHandle, JoinSet};
 
 #[allow(dead_code)]
 #[derive(Debug, PartialEq, Clone)]
@@ -25,6 +56,68 @@ pub enum ServerState {
     Stopped,
 }
 
+#[derive(Debug, Clone, Default)]
+/// What is known about a connection before its first request is read.
+pub(crate) struct ConnectionInfo {
+    /// The address of the peer, if the connection came in over TCP.
+    pub(crate) peer_addr: Option<SocketAddr>,
+    /// The PROXY protocol header sent ahead of the connection, if the listener expects one.
+    pub(crate) proxy_header: Option<ProxyHeader>,
+    #[cfg(feature = "tls")]
+    /// The details of the TLS session, if the connection is encrypted.
+    pub(crate) tls: Option<TlsInfo>,
+    #[cfg(unix)]
+    /// The credentials of the peer, if the connection came in on a Unix socket.
+    pub(crate) peer_credentials: Option<PeerCredentials>,
+    #[cfg(unix)]
+    /// Whether the peer of a Unix socket connection is a trusted proxy.
+    pub(crate) trusted_peer: bool,
+}
+
+impl ConnectionInfo {
+    /// Records the address of the peer of a TCP connection.
+    pub(crate) fn with_peer_addr(mut self, addr: Option<SocketAddr>) -> Self {
+        self.peer_addr = addr;
+        self
+    }
+
+    /// Records the PROXY protocol header sent ahead of the connection.
+    pub(crate) fn with_proxy_header(mut self, header: ProxyHeader) -> Self {
+        self.proxy_header = Some(header);
+        self
+    }
+
+    /// Returns the address of the client, as reported by the PROXY protocol if the listener
+    /// expects it, or of the peer otherwise.
+    fn client_addr(&self) -> Option<SocketAddr> {
+        match self.proxy_header {
+            Some(ref header) => header.source.or(self.peer_addr),
+            None => self.peer_addr,
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    /// Records the TLS session the connection is encrypted with.
+    pub(crate) fn with_tls(mut self, tls: TlsInfo) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    #[cfg(unix)]
+    /// Records the credentials of the peer of a Unix socket connection.
+    pub(crate) fn with_peer_credentials(mut self, credentials: Option<PeerCredentials>) -> Self {
+        self.peer_credentials = credentials;
+        self
+    }
+
+    #[cfg(unix)]
+    /// Records whether the peer of a Unix socket connection is a trusted proxy.
+    pub(crate) fn with_trusted_peer(mut self, trusted: bool) -> Self {
+        self.trusted_peer = trusted;
+        self
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 /// Represents a server configuration with various parameters.
@@ -41,6 +134,64 @@ pub struct Server {
     pub state: Arc<Mutex<ServerState>>,
     /// A vector of routes that the server will handle.
     pub routes: Arc<RwLock<Vec<Route>>>,
+    /// The maximum size in bytes of a request body read into memory.
+    pub max_body_size: usize,
+    /// The maximum size in bytes of a request body once its `Content-Encoding` is decoded.
+    pub max_decompressed_size: usize,
+    /// The maximum size in bytes of a URL-encoded form body.
+    pub max_form_size: usize,
+    /// The limits applied while streaming `multipart/form-data` bodies.
+    pub multipart_limits: MultipartLimits,
+    /// The keys used to sign and encrypt cookies, required for signed and private cookies.
+    pub cookie_jar: Option<CookieJar>,
+    /// The middlewares run around every route handler, in order.
+    pub middlewares: Arc<RwLock<Vec<Arc<dyn Middleware>>>>,
+    /// The publish/subscribe hub shared by handlers, closed when the server shuts down.
+    pub hub: Hub,
+    /// How long requests in progress may take to finish once the server shuts down.
+    pub shutdown_timeout: Duration,
+    /// How long a keep-alive connection may stay idle waiting for its next request.
+    pub keep_alive_timeout: Duration,
+    /// The addresses of the proxies whose `forwarded_headers` are believed by
+    /// `Request::client_ip`, `Request::scheme` and `Request::host`.
+    pub trusted_proxies: Vec<Cidr>,
+    /// The family of forwarding headers the `trusted_proxies` write; the other is ignored.
+    pub forwarded_headers: ForwardedHeaders,
+    /// How long a load balancer may take to send the PROXY protocol header of a connection.
+    pub proxy_protocol_timeout: Duration,
+    /// How long the new process may take to report that it is running in a hot restart.
+    pub restart_timeout: Duration,
+    /// Signals the accept loop to stop.
+    shutdown: Arc<watch::Sender<bool>>,
+    /// Signals that the server has stopped.
+    stopped: Arc<watch::Sender<bool>>,
+    #[cfg(unix)]
+    /// The file descriptors of the listening sockets, once started.
+    listener_fds: Arc<OnceLock<Vec<RawFd>>>,
+    #[cfg(unix)]
+    /// Held while a hot restart is in progress.
+    restarting: Arc<Mutex<()>>,
+    #[cfg(unix)]
+    /// Whether the listening sockets were handed over to a new process.
+    handed_over: Arc<AtomicBool>,
+    /// The task accepting connections, awaited on shutdown.
+    accept_loop: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// The addresses of the TCP listeners, once started.
+    local_addrs: Arc<OnceLock<Vec<SocketAddr>>>,
+    #[cfg(unix)]
+    /// The Unix socket to listen on instead of `host` and `port`, or `None` to listen on TCP.
+    pub unix_socket: Option<UnixSocket>,
+    /// Whether to listen on `host` and `port`, or `unix_socket`. Turn it off to serve only
+    /// `listeners`, e.g. sockets inherited from systemd.
+    pub listen_on_host: bool,
+    /// More sockets to listen on besides `host` and `port`, each with its own settings.
+    pub listeners: Vec<ListenerConfig>,
+    #[cfg(feature = "tls")]
+    /// The TLS settings of the `host` and `port` listener, or `None` to serve plain HTTP.
+    pub tls: Option<TlsConfig>,
+    #[cfg(feature = "http2")]
+    /// The limits of HTTP/2 connections.
+    pub http2: Http2Config,
 }
 
 impl Server {
@@ -79,6 +230,11 @@ impl Server {
             index_handler = Arc::new(|req, res| Box::pin(index(req, res)));
         }
 
+        let hub = Hub::new(HubConfig {
+            log_target: if debug { "app::core" } else { "app::none" },
+            ..HubConfig::default()
+        });
+
         Server {
             host,
             port,
@@ -90,11 +246,43 @@ impl Server {
                 "/",
                 index_handler,
             )]))),
+            max_body_size: 2 * 1024 * 1024,
+            max_decompressed_size: 8 * 1024 * 1024,
+            max_form_size: 16 * 1024,
+            multipart_limits: MultipartLimits::default(),
+            cookie_jar: None,
+            middlewares: Arc::new(RwLock::new(Vec::new())),
+            hub,
+            shutdown_timeout: Duration::from_secs(30),
+            keep_alive_timeout: Duration::from_secs(5),
+            trusted_proxies: Vec::new(),
+            forwarded_headers: ForwardedHeaders::default(),
+            proxy_protocol_timeout: Duration::from_secs(5),
+            restart_timeout: Duration::from_secs(30),
+            shutdown: Arc::new(watch::channel(false).0),
+            stopped: Arc::new(watch::channel(false).0),
+            #[cfg(unix)]
+            listener_fds: Arc::new(OnceLock::new()),
+            #[cfg(unix)]
+            restarting: Arc::new(Mutex::new(())),
+            #[cfg(unix)]
+            handed_over: Arc::new(AtomicBool::new(false)),
+            accept_loop: Arc::new(Mutex::new(None)),
+            local_addrs: Arc::new(OnceLock::new()),
+            #[cfg(unix)]
+            unix_socket: None,
+            listen_on_host: true,
+            listeners: Vec::new(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "http2")]
+            http2: Http2Config::default(),
         }
     }
 
     /// Starts the server, binding it to the specified host and port.
-    /// It initialises logging, listens for incoming connections, and handles requests.
+    /// It initialises logging, then accepts incoming connections in the background until
+    /// `shutdown` is called.
     ///
     /// # Returns
     ///
@@ -106,6 +294,13 @@ impl Server {
             init_logging(None, self.debug);
         }
 
+        #[cfg(unix)]
+        if let Some(ref socket) = self.unix_socket {
+            info!("Starting server at unix:{}", socket.path.display());
+        } else {
+            info!("Starting server at {}:{}", self.host, self.port);
+        }
+        #[cfg(not(unix))]
         info!("Starting server at {}:{}", self.host, self.port);
 
         let target = if self.debug { "app::core" } else { "app::none" };
@@ -118,55 +313,644 @@ impl Server {
             info!(target: target, "Logging output to: {log}");
         }
 
-        // Bind the server to the specified host and port.
-        let listener = TcpListener::bind(format!("{}:{}", self.host, self.port))
-            .await
-            .unwrap();
+        let listeners = self.bind().await?;
 
         let arc_server = Arc::new(self.clone());
 
         let mut state = arc_server.state.lock().await;
         *state = ServerState::Running;
         info!(target: target, "Server state: {:?}", *state);
+        drop(state);
+        self.notify_service_manager("READY=1");
+        #[cfg(unix)]
+        restart::report_ready();
+
+        let accept_loop = tokio::spawn(async move {
+            let mut accepting = JoinSet::new();
+            for listener in listeners {
+                accepting.spawn(Server::serve_listener(arc_server.clone(), listener));
+            }
+            while accepting.join_next().await.is_some() {}
+        });
+        *self.accept_loop.lock().await = Some(accept_loop);
+
+        Ok(())
+    }
+
+    /// Binds every listener: the Unix socket if one is set or `host` and `port` otherwise,
+    /// unless `listen_on_host` is off, followed by `listeners`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the listeners, or an error message if any of them cannot be bound.
+    async fn bind(&self) -> Result<Vec<Listener>, &'static str> {
+        #[allow(unused_mut)]
+        let mut primary = ListenerConfig::tcp(format!("{}:{}", self.host, self.port));
+        #[cfg(unix)]
+        if let Some(ref socket) = self.unix_socket {
+            primary = ListenerConfig::unix(socket.clone());
+        }
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = self.tls {
+            primary = primary.tls(tls.clone());
+        }
 
+        let configs = std::iter::once(primary)
+            .filter(|_| self.listen_on_host)
+            .chain(self.listeners.iter().cloned())
+            .collect::<Vec<_>>();
+        if configs.is_empty() {
+            return Err("No listeners to bind");
+        }
+        let listeners = listener::bind_all(
+            configs,
+            #[cfg(unix)]
+            &restart::inherited_fds(),
+        )
+        .await
+        .map_err(|e| {
+            warn!(target: self.get_target(), "Failed to bind a listener: {e}");
+            "Failed to bind the server address"
+        })?;
+        for listener in listeners.iter().skip(self.listen_on_host as usize) {
+            info!(target: self.get_target(), "Also listening on {:?}", listener.config.address);
+        }
+        let _ = self
+            .local_addrs
+            .set(listeners.iter().filter_map(Listener::local_addr).collect());
+        #[cfg(unix)]
+        let _ = self
+            .listener_fds
+            .set(listeners.iter().map(Listener::as_raw_fd).collect());
+        Ok(listeners)
+    }
+
+    /// Accepts connections on a listener until the server shuts down, then gives the requests
+    /// in progress `shutdown_timeout` to finish.
+    ///
+    /// # Arguments
+    ///
+    /// * `arc_server` - The server the listener belongs to.
+    /// * `listener` - The bound listener.
+    async fn serve_listener(arc_server: Arc<Server>, listener: Listener) {
+        let target = arc_server.get_target();
+        let mut shutdown = arc_server.shutdown_signal();
+        let mut connections = JoinSet::new();
         loop {
-            let (mut stream, _) = listener.accept().await.unwrap();
+            let connection = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        warn!(target: target, "Failed to accept a connection: {e}");
+                        continue;
+                    }
+                },
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            };
+            let config = listener.config.clone();
+            connections.spawn(Server::accept_connection(
+                arc_server.clone(),
+                connection,
+                config,
+            ));
+            while connections.try_join_next().is_some() {}
+        }
 
-            info!(target: target, "New connection from {}", stream.peer_addr().unwrap());
-            let arc_server = arc_server.clone();
+        // Stop accepting, then give in-flight requests time to finish.
+        #[cfg(unix)]
+        if arc_server.handed_over.load(Ordering::SeqCst) {
+            listener.hand_over();
+        } else {
+            listener.close();
+        }
+        #[cfg(not(unix))]
+        listener.close();
+        let drained = async { while connections.join_next().await.is_some() {} };
+        if tokio::time::timeout(arc_server.shutdown_timeout, drained)
+            .await
+            .is_err()
+        {
+            warn!(target: target, "Aborting {} connections still open", connections.len());
+            connections.abort_all();
+        }
+    }
 
-            tokio::spawn(async move {
-                // Create a new request instance for the incoming connection.
-                let server = arc_server.clone();
-                let mut req = match Request::new(&mut stream, server).await {
-                    Ok(r) => r,
-                    Err(_) => return,
+    /// Sets up an accepted connection.
+    ///
+    /// The PROXY protocol header is read first on listeners that expect one. Plain TCP
+    /// connections starting with the HTTP/2 preface are then served `h2c`, and connections on
+    /// a Unix socket make the peer credentials available to their requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `arc_server` - The server the connection was accepted by.
+    /// * `connection` - The accepted connection.
+    /// * `config` - The settings of the listener that accepted it.
+    async fn accept_connection(
+        arc_server: Arc<Server>,
+        connection: Connection,
+        config: Arc<ListenerConfig>,
+    ) {
+        let target = arc_server.get_target();
+        match connection {
+            Connection::Tcp(mut stream) => {
+                let info = ConnectionInfo::default().with_peer_addr(stream.peer_addr().ok());
+                let Some(info) = arc_server
+                    .read_proxy_header(&mut stream, info, &config)
+                    .await
+                else {
+                    return;
+                };
+                if let Some(addr) = info.client_addr() {
+                    info!(target: target, "New connection from {addr}");
+                }
+                #[cfg(feature = "http2")]
+                if !config.is_tls() {
+                    // A client that stalls partway through the preface must not hold the
+                    // connection forever.
+                    let preface = http2::has_preface(&stream);
+                    match tokio::time::timeout(arc_server.keep_alive_timeout, preface).await {
+                        Ok(true) => {
+                            http2::serve(arc_server, stream, info).await;
+                            return;
+                        }
+                        Ok(false) => {}
+                        Err(_) => {
+                            info!(target: target, "Timed out waiting for the first request");
+                            return;
+                        }
+                    }
+                }
+                Server::serve_connection(arc_server, stream, info, &config).await;
+            }
+            #[cfg(unix)]
+            Connection::Unix(mut stream) => {
+                let info = ConnectionInfo::default()
+                    .with_peer_credentials(unix::peer_credentials(&stream))
+                    .with_trusted_peer(config.trust_unix_peers);
+                let Some(info) = arc_server
+                    .read_proxy_header(&mut stream, info, &config)
+                    .await
+                else {
+                    return;
                 };
+                if let Some(addr) = info.client_addr() {
+                    info!(target: target, "New connection from {addr}");
+                } else if let Some(credentials) = info.peer_credentials {
+                    info!(target: target, "New connection from uid {}", credentials.uid);
+                }
+                Server::serve_connection(arc_server, stream, info, &config).await;
+            }
+        }
+    }
 
-                // Handle the request based on its path.
-                let routes = arc_server.routes.read().await;
-                for route in routes.iter() {
-                    let (matched, query_params, path_params) =
-                        Server::match_route(route.path, req.path());
-
-                    if matched {
-                        req.query_params = query_params;
-                        req.path_params = path_params;
-
-                        info!(target: target, "Handling route: {}", req.path());
-                        let res = &mut Response {
-                            status_code: StatusCode::OK,
-                            http_version: Arc::new(req.http_version().to_string()),
-                            headers: vec![],
-                            tcp_stream: Arc::new(Mutex::new(stream)),
-                            server: arc_server.clone(),
-                        };
-                        route.handle(&mut req, res).await;
-                        break;
+    /// Reads the PROXY protocol header of a connection, if its listener expects one.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The accepted connection.
+    /// * `info` - What is known about the connection so far.
+    /// * `config` - The settings of the listener that accepted it.
+    ///
+    /// # Returns
+    ///
+    /// The `ConnectionInfo` with the header, or `None` if the connection must be closed
+    /// because the header is missing, malformed or too slow to arrive.
+    async fn read_proxy_header<S: AsyncRead + Unpin>(
+        &self,
+        stream: &mut S,
+        info: ConnectionInfo,
+        config: &ListenerConfig,
+    ) -> Option<ConnectionInfo> {
+        if !config.proxy_protocol {
+            return Some(info);
+        }
+        let header = tokio::time::timeout(
+            self.proxy_protocol_timeout,
+            proxy_protocol::read_header(stream),
+        )
+        .await;
+        match header {
+            Ok(Ok(header)) => Some(info.with_proxy_header(header)),
+            Ok(Err(e)) => {
+                info!(target: self.get_target(), "Rejected a PROXY protocol header: {e}");
+                None
+            }
+            Err(_) => {
+                info!(target: self.get_target(), "Timed out waiting for a PROXY protocol header");
+                None
+            }
+        }
+    }
+
+    /// Serves a connection, after the TLS handshake if the listener serves HTTPS.
+    ///
+    /// # Arguments
+    ///
+    /// * `arc_server` - The server the connection was accepted by.
+    /// * `stream` - The accepted connection.
+    /// * `info` - What is known about the connection so far.
+    /// * `config` - The settings of the listener that accepted it.
+    async fn serve_connection<T: Transport + 'static>(
+        arc_server: Arc<Server>,
+        stream: T,
+        info: ConnectionInfo,
+        config: &ListenerConfig,
+    ) {
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = config.tls {
+            match tls.accept(stream).await {
+                Ok(stream) => {
+                    let info = info.with_tls(tls::connection_info(&stream));
+                    #[cfg(feature = "http2")]
+                    if info
+                        .tls
+                        .as_ref()
+                        .and_then(|tls| tls.alpn_protocol.as_deref())
+                        == Some("h2")
+                    {
+                        http2::serve(arc_server, stream, info).await;
+                        return;
+                    }
+                    Server::handle_connection(arc_server, stream, info).await;
+                }
+                Err(e) => {
+                    info!(target: arc_server.get_target(), "TLS handshake failed: {e}");
+                }
+            }
+            return;
+        }
+        #[cfg(not(feature = "tls"))]
+        let _ = config;
+
+        Server::handle_connection(arc_server, stream, info).await;
+    }
+
+    /// Handles one connection, reading its requests and running the matching routes.
+    ///
+    /// Connections are kept open for further requests unless the client or the response asks
+    /// to close them. Pipelined requests are served one at a time, so their responses are
+    /// written in the order the requests arrived.
+    ///
+    /// # Arguments
+    ///
+    /// * `arc_server` - The server the connection was accepted by.
+    /// * `stream` - The connection, e.g. a `TcpStream` or a TLS stream.
+    /// * `info` - What is known about the connection, made available to every request.
+    pub(crate) async fn handle_connection<T: Transport + 'static>(
+        arc_server: Arc<Server>,
+        stream: T,
+        info: ConnectionInfo,
+    ) {
+        let stream = shared_stream(stream);
+        let mut shutdown = arc_server.shutdown_signal();
+        let mut read_ahead = Vec::new();
+        let mut first = true;
+        loop {
+            let next = Request::read(stream.clone(), arc_server.clone(), read_ahead);
+            let req = if first {
+                next.await
+            } else {
+                // An idle connection is closed after a while, or as soon as the server stops.
+                tokio::select! {
+                    req = tokio::time::timeout(arc_server.keep_alive_timeout, next) => {
+                        req.unwrap_or(Err(ReadError::closed("Keep-alive timeout")))
                     }
+                    _ = shutdown.wait_for(|stop| *stop) => return,
+                }
+            };
+            let mut req = match req {
+                Ok(req) => req,
+                Err(ReadError {
+                    status: Some(status),
+                    message,
+                }) => {
+                    // The rest of the connection cannot be trusted to be framed correctly.
+                    info!(target: arc_server.get_target(), "Rejected a request: {message}");
+                    let mut res = Response::new(stream.clone(), "HTTP/1.1", arc_server.clone());
+                    res.set_header("Connection", "close");
+                    res.text(status.canonical_reason().unwrap_or_default(), status)
+                        .await;
+                    return;
+                }
+                Err(_) => return,
+            };
+            first = false;
+
+            match Server::handle_request(&arc_server, &stream, &mut req, &info).await {
+                Some(res) if Server::keep_alive(&req, &res) && !*shutdown.borrow() => {}
+                _ => return,
+            }
+            read_ahead = match req.take_read_ahead() {
+                Some(read_ahead) => read_ahead,
+                None => return,
+            };
+        }
+    }
+
+    /// Runs the route matching a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `arc_server` - The server the connection was accepted by.
+    /// * `stream` - The connection the request was read from.
+    /// * `req` - The request to handle.
+    /// * `info` - What is known about the connection.
+    ///
+    /// # Returns
+    ///
+    /// The response written to the client, or `None` if no route matched and nothing was written.
+    async fn handle_request(
+        arc_server: &Arc<Server>,
+        stream: &SharedStream,
+        req: &mut Request,
+        info: &ConnectionInfo,
+    ) -> Option<Response> {
+        let target = arc_server.get_target();
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = info.tls {
+            req.extensions.insert(tls.clone());
+        }
+        #[cfg(unix)]
+        if let Some(credentials) = info.peer_credentials {
+            req.extensions.insert(credentials);
+        }
+        #[cfg(unix)]
+        if info.trusted_peer {
+            req.extensions.insert(TrustedPeer);
+        }
+        if let Some(addr) = info.peer_addr {
+            req.extensions.insert(PeerAddr(addr));
+        }
+        if let Some(ref header) = info.proxy_header {
+            req.extensions.insert(header.clone());
+        }
+
+        let mut res = Response::new(stream.clone(), req.http_version(), arc_server.clone());
+        res.head_only = req.method() == Method::HEAD;
+        if req.wants_keep_alive() {
+            if req.http_version() == "HTTP/1.0" {
+                res.set_header("Connection", "keep-alive");
+            }
+        } else if req.http_version() == "HTTP/1.1" {
+            res.set_header("Connection", "close");
+        }
+
+        // Bodies that cannot be decoded are refused before any route sees them.
+        if let Err(status) = req.decode_body() {
+            if status == StatusCode::UNSUPPORTED_MEDIA_TYPE {
+                res.set_header("Accept-Encoding", compression::accepted_encodings());
+            }
+            res.text(status.canonical_reason().unwrap_or_default(), status)
+                .await;
+            return Some(res);
+        }
+
+        // Handle the request based on its path.
+        let routes = arc_server.routes.read().await;
+        for route in routes.iter() {
+            let (matched, query_params, path_params) = Server::match_route(route.path, req.path());
+
+            if matched {
+                req.query_params = query_params;
+                req.path_params = path_params;
+
+                info!(target: target, "Handling route: {}", req.path());
+                if let Some(range_headers) = RangeHeaders::from_request(req) {
+                    res.extensions.insert(range_headers);
+                }
+                Server::handle_with_middlewares(arc_server, route, req, &mut res).await;
+                return Some(res);
+            }
+        }
+        None
+    }
+
+    /// Checks whether a connection can be reused after a response.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The request that was handled.
+    /// * `res` - The response written to the client.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the client wants to keep the connection and the response ended cleanly.
+    fn keep_alive(req: &Request, res: &Response) -> bool {
+        let closes = res
+            .get_header("Connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"));
+        req.wants_keep_alive() && res.is_sent() && !closes
+    }
+
+    /// Shuts the server down gracefully.
+    ///
+    /// The server stops accepting connections and closes its `hub`, which ends every
+    /// subscription so WebSocket and SSE handlers can say goodbye to their clients. Requests
+    /// in progress get `shutdown_timeout` to finish before their connections are dropped.
+    /// Does nothing if the server is not running.
+    pub async fn shutdown(&self) {
+        let target = self.get_target();
+        {
+            let mut state = self.state.lock().await;
+            if *state != ServerState::Running {
+                return;
+            }
+            *state = ServerState::Stopping;
+            info!(target: target, "Server state: {:?}", *state);
+        }
+        #[cfg(unix)]
+        let handed_over = self.handed_over.load(Ordering::SeqCst);
+        #[cfg(not(unix))]
+        let handed_over = false;
+        // Once handed over, the service lives on in the new process.
+        if !handed_over {
+            self.notify_service_manager("STOPPING=1");
+        }
+
+        self.shutdown.send_replace(true);
+        self.hub.close();
+        if let Some(accept_loop) = self.accept_loop.lock().await.take() {
+            let _ = accept_loop.await;
+        }
+
+        let mut state = self.state.lock().await;
+        *state = ServerState::Stopped;
+        info!(target: target, "Server state: {:?}", *state);
+        self.stopped.send_replace(true);
+    }
+
+    /// Waits until the server has stopped, e.g. after handing its listeners over to a new
+    /// process in a hot restart.
+    pub async fn stopped(&self) {
+        let _ = self.stopped.subscribe().wait_for(|stopped| *stopped).await;
+    }
+
+    #[cfg(unix)]
+    /// Replaces the running process with a new one without dropping connections.
+    ///
+    /// The executable is started again with the same arguments and the listening sockets,
+    /// which it uses instead of binding its listeners. Once it reports that it is running,
+    /// this server stops accepting and shuts down gracefully, letting requests in progress
+    /// finish. Under systemd, the new process becomes the main process of the service, which
+    /// needs `NotifyAccess=all`.
+    ///
+    /// The new process must configure the same listeners, in the same order.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the listeners were handed over and the server drained.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the server is not running, a restart is already in
+    /// progress, or the new process fails to start; the server keeps running in that case.
+    pub async fn hot_restart(&self) -> Result<(), &'static str> {
+        let target = self.get_target();
+        let Ok(_restarting) = self.restarting.try_lock() else {
+            return Err("A restart is already in progress");
+        };
+        if !self.check_state(ServerState::Running).await.0 {
+            return Err("The server is not running");
+        }
+
+        let fds = self.listener_fds.get().cloned().unwrap_or_default();
+        match restart::spawn_successor(&fds, self.restart_timeout).await {
+            Ok(pid) => {
+                info!(target: target, "Handed the listeners over to process {pid}");
+                self.handed_over.store(true, Ordering::SeqCst);
+                self.notify_service_manager(&format!("MAINPID={pid}"));
+                self.shutdown().await;
+                Ok(())
+            }
+            Err(e) => {
+                warn!(target: target, "Hot restart failed: {e}");
+                Err("Failed to start the new process")
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    /// Performs a hot restart every time the process receives `SIGUSR2`, until one succeeds.
+    ///
+    /// # Returns
+    ///
+    /// A `JoinHandle` of the task waiting for the signal; abort it to stop listening for it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use rusticore::Server;
+    ///
+    /// # async fn run() {
+    /// let mut server = Server::new("localhost", 8080, false, None, None);
+    /// server.start().await.unwrap();
+    /// server.hot_restart_on_sigusr2();
+    /// // Runs until a new process takes over.
+    /// server.stopped().await;
+    /// # }
+    /// ```
+    pub fn hot_restart_on_sigusr2(&self) -> JoinHandle<()> {
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut signals = match signal(SignalKind::user_defined2()) {
+                Ok(signals) => signals,
+                Err(e) => {
+                    warn!(target: server.get_target(), "Failed to listen for SIGUSR2: {e}");
+                    return;
                 }
-            });
+            };
+            while signals.recv().await.is_some() {
+                if server.hot_restart().await.is_ok() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Tells the service manager, e.g. systemd with `Type=notify`, about a state change.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The state to report, e.g. `READY=1`.
+    fn notify_service_manager(&self, state: &str) {
+        #[cfg(unix)]
+        if let Err(e) = systemd::notify(state) {
+            warn!(target: self.get_target(), "Failed to notify the service manager: {e}");
         }
+        #[cfg(not(unix))]
+        let _ = state;
+    }
+
+    /// Returns a receiver that is notified once the server starts shutting down.
+    pub(crate) fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    /// Returns the address the server is listening on, e.g. to find the port chosen when
+    /// binding to port 0.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<SocketAddr>` with the address of the first TCP listener, or `None` if the
+    /// server has not been started.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs().first().copied()
+    }
+
+    /// Returns the addresses of every TCP listener, in the order they were configured.
+    ///
+    /// # Returns
+    ///
+    /// A slice of addresses, empty if the server has not been started.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        self.local_addrs.get().map_or(&[], Vec::as_slice)
+    }
+
+    /// Runs a route handler wrapped in the server's middlewares.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The server whose middlewares are run.
+    /// * `route` - The matched route.
+    /// * `req` - A mutable reference to the incoming HTTP request object.
+    /// * `res` - A mutable reference to the HTTP response object.
+    async fn handle_with_middlewares(
+        server: &Server,
+        route: &Route,
+        req: &mut Request,
+        res: &mut Response,
+    ) {
+        let middlewares = server.middlewares.read().await.clone();
+
+        let mut ran = 0;
+        let mut proceed = true;
+        for middleware in middlewares.iter() {
+            ran += 1;
+            if !middleware.before(req, res).await {
+                proceed = false;
+                break;
+            }
+        }
+
+        if proceed {
+            route.handle(req, res).await;
+        }
+
+        for middleware in middlewares[..ran].iter().rev() {
+            middleware.after(req, res).await;
+        }
+    }
+
+    /// Adds a middleware that runs around every route handler.
+    ///
+    /// # Arguments
+    ///
+    /// * `middleware` - The middleware to add. Middlewares run in the order they are added.
+    pub async fn add_middleware(&mut self, middleware: impl Middleware + 'static) {
+        let target = self.get_target();
+        info!(target: target, "Added middleware: {middleware:?}");
+        self.middlewares.write().await.push(Arc::new(middleware));
     }
 
     /// Adds a new route to the server's routing vector.
@@ -220,8 +1004,12 @@ impl Server {
     /// # Returns
     ///
     /// A string slice representing the target for logging.
-    fn get_target(&self) -> &str {
-        if self.debug { "app::core" } else { "app::none" }
+    pub(crate) fn get_target(&self) -> &'static str {
+        if self.debug {
+            "app::core"
+        } else {
+            "app::none"
+        }
     }
 
     /// Matches a given route pattern against a path and extracts query and path parameters.
@@ -258,12 +1046,25 @@ impl Server {
         let route_parts: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
         let path_parts: Vec<&str> = parts[0].trim_end_matches('/').split('/').collect();
 
-        if route_parts.len() != path_parts.len() {
+        // A trailing `{*name}` segment captures the rest of the path, including slashes.
+        let catch_all = route_parts
+            .last()
+            .and_then(|pat| pat.strip_prefix("{*"))
+            .and_then(|pat| pat.strip_suffix('}'));
+        if let Some(key) = catch_all {
+            let fixed = route_parts.len() - 1;
+            if path_parts.len() < fixed {
+                return (false, query_params, path_params);
+            }
+            path_params.insert(key.to_string(), path_parts[fixed..].join("/"));
+        } else if route_parts.len() != path_parts.len() {
             return (false, query_params, path_params);
         }
 
         for (pat, val) in route_parts.iter().zip(path_parts.iter()) {
-            if pat.starts_with('{') && pat.ends_with('}') {
+            if pat.starts_with("{*") {
+                break;
+            } else if pat.starts_with('{') && pat.ends_with('}') {
                 let key = &pat[1..pat.len() - 1];
                 path_params.insert(key.to_string(), val.to_string());
             } else if pat != val {
@@ -278,6 +1079,8 @@ impl Server {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
 
     #[tokio::test]
     /// Tests the creation of a new server instance with default parameters.
@@ -345,5 +1148,132 @@ mod tests {
         assert_eq!(path_params.get("id").unwrap(), "42");
         assert!(query_params.contains_key("key"));
         assert_eq!(query_params.get("key").unwrap(), "value");
+
+        let (matched, _, path_params) =
+            Server::match_route("/assets/{*path}", "/assets/css/site.css?v=2");
+        assert!(matched);
+        assert_eq!(path_params.get("path").unwrap(), "css/site.css");
+        let (matched, _, path_params) = Server::match_route("/assets/{*path}", "/assets/");
+        assert!(matched);
+        assert_eq!(path_params.get("path").unwrap(), "");
+        assert!(!Server::match_route("/assets/{*path}", "/other/site.css").0);
+    }
+
+    #[tokio::test]
+    /// Tests a graceful shutdown, which stops accepting connections and closes hub subscriptions.
+    async fn shutdown() {
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.start().await.unwrap();
+        assert!(server.check_state(ServerState::Running).await.0);
+        let addr = server.local_addr().unwrap();
+        let mut subscription = server.hub.subscribe("events");
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        server.shutdown().await;
+        assert!(server.check_state(ServerState::Stopped).await.0);
+        assert_eq!(subscription.recv().await, None);
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    /// Tests that pipelined requests on a keep-alive connection are all answered, in order,
+    /// that HTTP/1.0 connections close after one response, that a chunked body cannot smuggle
+    /// a request, and that `HEAD` responses carry no body.
+    async fn pipelining() {
+        /// Echoes the request body.
+        async fn echo(req: &mut Request, res: &mut Response) {
+            let body = String::from_utf8_lossy(req.body()).to_string();
+            res.text(&body, StatusCode::CREATED).await;
+        }
+
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server
+            .add_route(Route::new(
+                "POST",
+                "/echo",
+                Arc::new(|req, res| Box::pin(echo(req, res))),
+            ))
+            .await;
+        server.start().await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nfirst\
+                  GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\
+                  Connection: close\r\n\r\nsecond",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        let statuses = response
+            .match_indices("HTTP/1.1 ")
+            .map(|(i, _)| &response[i + 9..i + 12])
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, ["201", "200", "201"]);
+        let first = response.find("\r\n\r\nfirst").unwrap();
+        let second = response.find("\r\n\r\nsecond").unwrap();
+        assert!(first < second);
+        assert!(response.ends_with("second"));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.0\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(1), client.read_to_string(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.0 200"));
+
+        // The response to `HEAD` has no body, so the next response follows its head directly.
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        let (head, rest) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(head.contains("Content-Length: "));
+        assert!(!head.contains("Content-Length: 0"));
+        assert!(rest.starts_with("HTTP/1.1 200"));
+
+        // A chunked body is refused and the connection closed, so its bytes are never read as
+        // the next request.
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  23\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n\r\n0\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(1), client.read_to_string(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 501"));
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+
+        server.shutdown().await;
     }
 }
@@ -0,0 +1,718 @@
+use crate::cookie::{Cookie, SameSite};
+use crate::middleware::Middleware;
+use crate::request::Request;
+use crate::response::Response;
+use crate::Server;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures::future::BoxFuture;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The data of a session as kept by a `SessionStore`.
+pub struct SessionRecord {
+    /// The key/value pairs stored in the session.
+    pub data: HashMap<String, String>,
+    /// The time after which the session is no longer valid.
+    pub expires_at: SystemTime,
+}
+
+impl SessionRecord {
+    /// Checks whether the session has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= SystemTime::now()
+    }
+}
+
+/// A storage backend for session data, keyed by session ID.
+pub trait SessionStore: fmt::Debug + Send + Sync {
+    /// Loads a session.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the record, or `None` if no such session exists.
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>>;
+
+    /// Creates or replaces a session.
+    fn save<'a>(&'a self, id: &'a str, record: &'a SessionRecord) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Deletes a session. Deleting a session that does not exist is not an error.
+    fn destroy<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Deletes every expired session.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the number of sessions deleted.
+    fn cleanup(&self) -> BoxFuture<'_, io::Result<usize>>;
+}
+
+#[derive(Debug, Default)]
+/// A `SessionStore` that keeps sessions in memory. Sessions are lost when the process exits.
+pub struct MemoryStore {
+    /// The sessions, keyed by ID.
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+}
+
+impl MemoryStore {
+    /// Creates a new, empty `MemoryStore`.
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>> {
+        Box::pin(async move { Ok(self.sessions.lock().await.get(id).cloned()) })
+    }
+
+    fn save<'a>(&'a self, id: &'a str, record: &'a SessionRecord) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            self.sessions
+                .lock()
+                .await
+                .insert(id.to_string(), record.clone());
+            Ok(())
+        })
+    }
+
+    fn destroy<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            self.sessions.lock().await.remove(id);
+            Ok(())
+        })
+    }
+
+    fn cleanup(&self) -> BoxFuture<'_, io::Result<usize>> {
+        Box::pin(async move {
+            let mut sessions = self.sessions.lock().await;
+            let before = sessions.len();
+            sessions.retain(|_, record| !record.is_expired());
+            Ok(before - sessions.len())
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A `SessionStore` that keeps one file per session in a directory.
+///
+/// Each file holds the expiry time as Unix seconds on its first line, followed by the
+/// session data URL-encoded on the second line.
+pub struct FileStore {
+    /// The directory holding the session files.
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Creates a new `FileStore`, creating the directory if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory holding the session files.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the `FileStore`, or an error if the directory cannot be created.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir })
+    }
+
+    /// Returns the path of the file holding a session.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ID contains characters that are not valid in a session ID,
+    /// which also prevents IDs from escaping the directory.
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        if id.is_empty()
+            || !id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid session ID",
+            ));
+        }
+        Ok(self.dir.join(format!("{id}.session")))
+    }
+
+    /// Serializes a record into the file format.
+    fn encode(record: &SessionRecord) -> String {
+        let expires = record
+            .expires_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let data = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(record.data.iter())
+            .finish();
+        format!("{expires}\n{data}\n")
+    }
+
+    /// Parses a record from the file format.
+    fn decode(contents: &str) -> Option<SessionRecord> {
+        let mut lines = contents.lines();
+        let expires = lines.next()?.trim().parse::<u64>().ok()?;
+        let data = form_urlencoded::parse(lines.next().unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect();
+        Some(SessionRecord {
+            data,
+            expires_at: UNIX_EPOCH + Duration::from_secs(expires),
+        })
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>> {
+        Box::pin(async move {
+            match tokio::fs::read_to_string(self.path(id)?).await {
+                Ok(contents) => Ok(FileStore::decode(&contents)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn save<'a>(&'a self, id: &'a str, record: &'a SessionRecord) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            // Write to a temporary file first so readers never see a partial session.
+            let path = self.path(id)?;
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, FileStore::encode(record)).await?;
+            tokio::fs::rename(&tmp, &path).await
+        })
+    }
+
+    fn destroy<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.path(id)?).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn cleanup(&self) -> BoxFuture<'_, io::Result<usize>> {
+        Box::pin(async move {
+            let mut removed = 0;
+            let mut entries = tokio::fs::read_dir(&self.dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if path.extension().is_none_or(|ext| ext != "session") {
+                    continue;
+                }
+                let expired = match tokio::fs::read_to_string(&path).await {
+                    Ok(contents) => FileStore::decode(&contents).is_none_or(|r| r.is_expired()),
+                    Err(_) => false,
+                };
+                if expired && tokio::fs::remove_file(&path).await.is_ok() {
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        })
+    }
+}
+
+#[derive(Debug)]
+/// The mutable state behind a `Session` handle.
+struct SessionState {
+    /// The current session ID.
+    id: String,
+    /// The key/value pairs stored in the session.
+    data: HashMap<String, String>,
+    /// Whether the session was created by this request.
+    is_new: bool,
+    /// Whether the data has changed and must be saved.
+    changed: bool,
+    /// The IDs replaced by `Session::regenerate`, to be deleted from the store.
+    stale_ids: Vec<String>,
+    /// Whether the session has been destroyed.
+    destroyed: bool,
+    /// Whether the session cookie has to be sent to the client.
+    cookie_pending: bool,
+}
+
+#[derive(Debug, Clone)]
+/// The session of a request, available through `Request::session` when a `SessionMiddleware` is registered.
+///
+/// Changes are saved to the store right before the response head is written, so the session exists by the
+/// time the client receives its cookie. Later changes are saved once the route handler returns, but the
+/// cookie is only written with the head, so a new or regenerated session must be set up before responding.
+pub struct Session {
+    /// The shared session state, also reachable from the response.
+    state: Arc<StdMutex<SessionState>>,
+}
+
+impl Session {
+    /// Creates a session handle.
+    fn new(id: String, data: HashMap<String, String>, is_new: bool) -> Self {
+        Session {
+            state: Arc::new(StdMutex::new(SessionState {
+                id,
+                data,
+                is_new,
+                changed: false,
+                stale_ids: Vec::new(),
+                destroyed: false,
+                cookie_pending: false,
+            })),
+        }
+    }
+
+    /// Locks the session state.
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the current session ID.
+    pub fn id(&self) -> String {
+        self.state().id.clone()
+    }
+
+    /// Returns the value stored under a key.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state().data.get(key).cloned()
+    }
+
+    /// Stores a value under a key, replacing any previous value.
+    pub fn insert(&self, key: impl Into<String>, value: impl Into<String>) {
+        let mut state = self.state();
+        state.data.insert(key.into(), value.into());
+        state.changed = true;
+        if state.is_new {
+            state.cookie_pending = true;
+        }
+    }
+
+    /// Removes the value stored under a key.
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state();
+        let value = state.data.remove(key);
+        state.changed |= value.is_some();
+        value
+    }
+
+    /// Removes every value from the session.
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.changed |= !state.data.is_empty();
+        state.data.clear();
+    }
+
+    /// Gives the session a new ID while keeping its data.
+    ///
+    /// Call this when the user logs in or their privileges change, so an ID planted by an
+    /// attacker before login cannot be used to hijack the session.
+    pub fn regenerate(&self) {
+        let mut state = self.state();
+        let old = std::mem::replace(&mut state.id, generate_id());
+        if !state.is_new {
+            state.stale_ids.push(old);
+        }
+        state.changed = true;
+        state.cookie_pending = true;
+    }
+
+    /// Deletes the session from the store and asks the client to delete the session cookie.
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.destroyed = true;
+        state.changed = true;
+        state.data.clear();
+        state.cookie_pending = !state.is_new;
+    }
+}
+
+/// Generates a new random session ID with 256 bits of entropy.
+fn generate_id() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).expect("Failed to generate a session ID");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[derive(Debug, Clone)]
+/// A middleware that loads the session of every request from a `SessionStore` and saves it afterwards.
+///
+/// The session ID is kept in an `HttpOnly` cookie, signed when the server has a `cookie_jar`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rusticore::{MemoryStore, Server, SessionMiddleware};
+/// use std::time::Duration;
+///
+/// # async fn run() {
+/// let mut server = Server::new("localhost", 8080, false, None, None);
+/// let sessions = SessionMiddleware::new(MemoryStore::new()).ttl(Duration::from_secs(3600));
+/// sessions.spawn_cleanup(&server, Duration::from_secs(300));
+/// server.add_middleware(sessions).await;
+/// # }
+/// ```
+pub struct SessionMiddleware {
+    /// The store holding the sessions.
+    store: Arc<dyn SessionStore>,
+    /// The name of the session cookie.
+    cookie_name: String,
+    /// How long a session lives after it was last changed.
+    ttl: Duration,
+    /// Whether the session cookie is only sent over HTTPS.
+    secure: bool,
+    /// The cross-site policy of the session cookie.
+    same_site: SameSite,
+}
+
+impl SessionMiddleware {
+    /// Creates a new `SessionMiddleware` with a 24-hour TTL and a `SameSite=Lax` cookie named `rusticore.sid`.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The store holding the sessions.
+    pub fn new(store: impl SessionStore + 'static) -> Self {
+        SessionMiddleware {
+            store: Arc::new(store),
+            cookie_name: "rusticore.sid".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    /// Sets the name of the session cookie.
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Self {
+        self.cookie_name = name.into();
+        self
+    }
+
+    /// Sets how long a session lives after it was last changed.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets whether the session cookie is only sent over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets the cross-site policy of the session cookie.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    /// Spawns a background task that periodically deletes expired sessions from the store.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The server the middleware is added to, which decides where the task logs.
+    /// * `interval` - The time between two cleanups.
+    ///
+    /// # Returns
+    ///
+    /// The `JoinHandle` of the task, which can be aborted to stop it.
+    pub fn spawn_cleanup(&self, server: &Server, interval: Duration) -> JoinHandle<()> {
+        let store = self.store.clone();
+        let target = server.get_target();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match store.cleanup().await {
+                    Ok(0) => {}
+                    Ok(n) => info!(target: target, "Removed {n} expired sessions"),
+                    Err(e) => warn!(target: target, "Failed to clean up sessions: {e}"),
+                }
+            }
+        })
+    }
+
+    /// Writes the changes of a session to the store: the session is saved, or deleted if it
+    /// was destroyed, and only then are the IDs replaced by `Session::regenerate` deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The session of the request.
+    /// * `target` - The log target failures are reported under.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the store is up to date, or `false` if the change could not be written.
+    async fn persist(&self, session: &Session, target: &'static str) -> bool {
+        let (id, record, destroyed) = {
+            let mut state = session.state();
+            if !std::mem::take(&mut state.changed) {
+                return true;
+            }
+            let record = SessionRecord {
+                data: state.data.clone(),
+                expires_at: SystemTime::now() + self.ttl,
+            };
+            (state.id.clone(), record, state.destroyed)
+        };
+
+        let result = if destroyed {
+            self.store.destroy(&id).await
+        } else {
+            self.store.save(&id, &record).await
+        };
+        if let Err(e) = result {
+            warn!(target: target, "Failed to save session: {e}");
+            return false;
+        }
+
+        let stale_ids = std::mem::take(&mut session.state().stale_ids);
+        for stale in stale_ids.iter() {
+            if let Err(e) = self.store.destroy(stale).await {
+                warn!(target: target, "Failed to delete session: {e}");
+            }
+        }
+        true
+    }
+
+    /// Reads the session ID sent by the client, verifying its signature if the server has a `cookie_jar`.
+    fn session_id(&self, req: &Request, res: &Response) -> Option<String> {
+        match res.server.cookie_jar {
+            Some(_) => req.signed_cookie(&self.cookie_name),
+            None => req.cookie(&self.cookie_name).map(str::to_string),
+        }
+    }
+}
+
+impl Middleware for SessionMiddleware {
+    fn before<'a>(&'a self, req: &'a mut Request, res: &'a mut Response) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            let mut loaded = None;
+            if let Some(id) = self.session_id(req, res) {
+                match self.store.load(&id).await {
+                    Ok(Some(record)) if !record.is_expired() => loaded = Some((id, record.data)),
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!(target: res.server.get_target(), "Failed to load session: {e}")
+                    }
+                }
+            }
+
+            let session = match loaded {
+                Some((id, data)) => Session::new(id, data, false),
+                None => Session::new(generate_id(), HashMap::new(), true),
+            };
+            req.extensions.insert(session.clone());
+            res.extensions.insert(session);
+            true
+        })
+    }
+
+    fn before_send<'a>(&'a self, res: &'a mut Response) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Some(session) = res.extensions.get::<Session>().cloned() else {
+                return;
+            };
+            // The client may use the cookie right away, e.g. to follow a redirect after logging
+            // in, so the session is stored first, and the cookie withheld if that fails.
+            if !self.persist(&session, res.server.get_target()).await {
+                return;
+            }
+            let mut state = session.state();
+            if !state.cookie_pending {
+                return;
+            }
+            state.cookie_pending = false;
+
+            if state.destroyed {
+                res.add_cookie(Cookie::removal(self.cookie_name.as_str()).path("/"));
+                return;
+            }
+
+            let cookie = Cookie::new(self.cookie_name.as_str(), state.id.as_str())
+                .path("/")
+                .max_age(self.ttl)
+                .http_only(true)
+                .secure(self.secure)
+                .same_site(self.same_site);
+            drop(state);
+
+            if res.server.cookie_jar.is_some() {
+                res.add_signed_cookie(cookie);
+            } else {
+                res.add_cookie(cookie);
+            }
+        })
+    }
+
+    fn after<'a>(&'a self, req: &'a mut Request, res: &'a mut Response) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            // Changes made after the head was sent, or with no response at all, are kept too.
+            if let Some(session) = req.session().cloned() {
+                self.persist(&session, res.server.get_target()).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::shared_stream;
+    use http::StatusCode;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    /// Sends a request through the session middleware and returns the raw response.
+    async fn roundtrip(
+        server: &Arc<Server>,
+        middleware: &SessionMiddleware,
+        cookie: Option<&str>,
+        handler: impl FnOnce(&Session),
+    ) -> String {
+        let (mut client, server_stream) = duplex(4096);
+        let stream = shared_stream(server_stream);
+        let request = match cookie {
+            Some(cookie) => format!("GET / HTTP/1.1\r\nCookie: {cookie}\r\n\r\n"),
+            None => "GET / HTTP/1.1\r\n\r\n".to_string(),
+        };
+        client.write_all(request.as_bytes()).await.unwrap();
+
+        let mut req = Request::new(stream.clone(), server.clone()).await.unwrap();
+        let mut res = Response::new(stream, "HTTP/1.1", server.clone());
+        assert!(middleware.before(&mut req, &mut res).await);
+        handler(req.session().unwrap());
+        middleware.before_send(&mut res).await;
+        res.text("ok", StatusCode::OK).await;
+        middleware.after(&mut req, &mut res).await;
+        drop((req, res));
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        output
+    }
+
+    /// Extracts the session cookie from a raw response.
+    fn session_cookie(response: &str) -> Option<String> {
+        response
+            .lines()
+            .find_map(|line| line.strip_prefix("Set-Cookie: rusticore.sid="))
+            .map(|value| value.split(';').next().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    /// Tests issuing, loading, regenerating and destroying a session across requests.
+    async fn lifecycle() {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let middleware = SessionMiddleware::new(MemoryStore::new());
+
+        // Untouched sessions are neither stored nor sent to the client.
+        let output = roundtrip(&server, &middleware, None, |_| {}).await;
+        assert_eq!(session_cookie(&output), None);
+
+        let output = roundtrip(&server, &middleware, None, |s| s.insert("user", "ada")).await;
+        let id = session_cookie(&output).unwrap();
+        assert!(output.contains("; HttpOnly; SameSite=Lax"));
+
+        let cookie = format!("rusticore.sid={id}");
+        let output = roundtrip(&server, &middleware, Some(&cookie), |s| {
+            assert_eq!(s.get("user"), Some("ada".to_string()));
+            s.regenerate();
+        })
+        .await;
+        let new_id = session_cookie(&output).unwrap();
+        assert_ne!(id, new_id);
+        assert!(middleware.store.load(&id).await.unwrap().is_none());
+
+        let cookie = format!("rusticore.sid={new_id}");
+        let output = roundtrip(&server, &middleware, Some(&cookie), |s| {
+            assert_eq!(s.get("user"), Some("ada".to_string()));
+            s.destroy();
+        })
+        .await;
+        assert_eq!(session_cookie(&output), Some(String::new()));
+        assert!(middleware.store.load(&new_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    /// Tests that a session is stored before its cookie is sent, and that the cookie is withheld
+    /// if the store cannot save it.
+    async fn saved_before_send() {
+        #[derive(Debug)]
+        /// A store that fails to save anything.
+        struct ReadOnlyStore;
+
+        impl SessionStore for ReadOnlyStore {
+            fn load<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>> {
+                Box::pin(async { Ok(None) })
+            }
+
+            fn save<'a>(
+                &'a self,
+                _: &'a str,
+                _: &'a SessionRecord,
+            ) -> BoxFuture<'a, io::Result<()>> {
+                Box::pin(async { Err(io::Error::other("Read-only store")) })
+            }
+
+            fn destroy<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<()>> {
+                Box::pin(async { Ok(()) })
+            }
+
+            fn cleanup(&self) -> BoxFuture<'_, io::Result<usize>> {
+                Box::pin(async { Ok(0) })
+            }
+        }
+
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let middleware = SessionMiddleware::new(MemoryStore::new());
+        let (mut client, server_stream) = duplex(4096);
+        let stream = shared_stream(server_stream);
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut req = Request::new(stream.clone(), server.clone()).await.unwrap();
+        let mut res = Response::new(stream, "HTTP/1.1", server.clone());
+        assert!(middleware.before(&mut req, &mut res).await);
+        let session = req.session().unwrap().clone();
+        session.insert("user", "ada");
+        middleware.before_send(&mut res).await;
+        let record = middleware.store.load(&session.id()).await.unwrap();
+        assert_eq!(record.unwrap().data.get("user").unwrap(), "ada");
+
+        let middleware = SessionMiddleware::new(ReadOnlyStore);
+        let output = roundtrip(&server, &middleware, None, |s| s.insert("user", "ada")).await;
+        assert!(output.starts_with("HTTP/1.1 200"));
+        assert_eq!(session_cookie(&output), None);
+    }
+
+    #[tokio::test]
+    /// Tests saving, loading, expiring and cleaning up sessions in a `FileStore`.
+    async fn file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStore::new(dir.path()).unwrap();
+        let record = SessionRecord {
+            data: HashMap::from([("msg".to_string(), "a=b & c\nd".to_string())]),
+            expires_at: UNIX_EPOCH + Duration::from_secs(4_000_000_000),
+        };
+        store.save("abc", &record).await.unwrap();
+        assert_eq!(store.load("abc").await.unwrap(), Some(record));
+        assert!(store.load("missing").await.unwrap().is_none());
+        assert!(store.load("../etc/passwd").await.is_err());
+
+        let expired = SessionRecord {
+            data: HashMap::new(),
+            expires_at: SystemTime::now() - Duration::from_secs(1),
+        };
+        store.save("old", &expired).await.unwrap();
+        assert_eq!(store.cleanup().await.unwrap(), 1);
+        assert!(store.load("old").await.unwrap().is_none());
+        assert!(store.load("abc").await.unwrap().is_some());
+    }
+}
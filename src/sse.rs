@@ -0,0 +1,300 @@
+use crate::request::Request;
+use crate::response::Response;
+use futures::{Stream, StreamExt};
+use http::StatusCode;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+
+/// The media type of Server-Sent Events streams.
+pub const SSE_CONTENT_TYPE: &str = "text/event-stream";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Represents one Server-Sent Event.
+///
+/// # Examples
+///
+/// ```
+/// use rusticore::Event;
+///
+/// let event = Event::new("{\"cpu\":42}").event("stats").id("17");
+/// assert_eq!(event.to_string(), "event: stats\nid: 17\ndata: {\"cpu\":42}\n\n");
+/// ```
+pub struct Event {
+    /// The type of the event, dispatched to `addEventListener` listeners in the browser.
+    event: Option<String>,
+    /// The payload of the event.
+    data: String,
+    /// The ID of the event, sent back by the browser in `Last-Event-ID` when it reconnects.
+    id: Option<String>,
+    /// The reconnection delay the browser should use from now on.
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Creates a new event with a payload. Payloads spanning several lines are sent as several `data` fields.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The payload of the event.
+    ///
+    /// # Returns
+    ///
+    /// A new `Event` instance.
+    pub fn new(data: impl Into<String>) -> Self {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the type of the event.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the ID of the event.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the reconnection delay the browser should use from now on.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+impl fmt::Display for Event {
+    /// Formats the event in the `text/event-stream` format, ending with a blank line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Line breaks in single-line fields would start a new field, so they are dropped.
+        let single_line = |value: &str| value.replace(['\r', '\n'], "");
+
+        if let Some(ref event) = self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        if let Some(ref id) = self.id {
+            // An ID containing NUL is ignored by browsers, so none is sent.
+            writeln!(f, "id: {}", single_line(id).replace('\0', ""))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        for line in self.data.split('\n') {
+            writeln!(f, "data: {}", line.strip_suffix('\r').unwrap_or(line))?;
+        }
+        writeln!(f)
+    }
+}
+
+#[derive(Debug)]
+/// A Server-Sent Events response, sent with `Response::sse`.
+///
+/// Comment lines are sent as keep-alive pings while no event is ready, so proxies do not time
+/// out idle connections and disconnected clients are noticed.
+pub struct Sse<S> {
+    /// The events to send.
+    events: S,
+    /// The time without events after which a ping is sent, or `None` to disable pings.
+    keep_alive: Option<Duration>,
+    /// The reconnection delay sent to the browser before the first event.
+    retry: Option<Duration>,
+}
+
+impl<S> Sse<S>
+where
+    S: Stream<Item = Event> + Send,
+{
+    /// Creates a new SSE response sending the events of a stream, with a ping every 15 seconds.
+    ///
+    /// # Arguments
+    ///
+    /// * `events` - The events to send. The response ends when the stream ends.
+    ///
+    /// # Returns
+    ///
+    /// A new `Sse` instance.
+    pub fn new(events: S) -> Self {
+        Sse {
+            events,
+            keep_alive: Some(Duration::from_secs(15)),
+            retry: None,
+        }
+    }
+
+    /// Sets the time without events after which a ping is sent, or disables pings with `None`.
+    pub fn keep_alive(mut self, keep_alive: Option<Duration>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Sets the reconnection delay sent to the browser before the first event.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+impl Request {
+    /// Returns the ID of the last event received by a reconnecting `EventSource`.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&str>` containing the value of the `Last-Event-ID` header, or `None` on the first connection.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.get_header("Last-Event-ID")
+    }
+}
+
+impl Response {
+    /// Sends a Server-Sent Events response, until the event stream ends or the client disconnects.
+    ///
+    /// # Arguments
+    ///
+    /// * `sse` - The events to send and the keep-alive settings.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` that is `Ok` when the event stream has ended, or an error of kind
+    /// `ConnectionAborted` when the client has disconnected. The event stream is dropped
+    /// in both cases, so producers notice that nobody is listening anymore.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use rusticore::{Event, Request, Response, Sse};
+    /// # use futures::StreamExt;
+    /// # use std::time::Duration;
+    /// # async fn handler(req: &mut Request, res: &mut Response) {
+    /// let start = req.last_event_id().and_then(|id| id.parse::<u64>().ok()).map_or(0, |id| id + 1);
+    /// let ticks = futures::stream::iter(start..).then(|n| async move {
+    ///     tokio::time::sleep(Duration::from_secs(1)).await;
+    ///     Event::new(format!("tick {n}")).id(n.to_string())
+    /// });
+    /// let _ = res.sse(Sse::new(ticks)).await;
+    /// # }
+    /// ```
+    pub async fn sse<S>(&mut self, sse: Sse<S>) -> io::Result<()>
+    where
+        S: Stream<Item = Event> + Send,
+    {
+        self.set_header("Cache-Control", "no-cache");
+        let mut writer = self.start_stream(SSE_CONTENT_TYPE, StatusCode::OK).await?;
+        if self.head_only {
+            return writer.finish().await;
+        }
+        let disconnected = |e: io::Error| match e.kind() {
+            io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset => {
+                io::Error::new(io::ErrorKind::ConnectionAborted, e)
+            }
+            _ => e,
+        };
+
+        if let Some(retry) = sse.retry {
+            let retry = format!("retry: {}\n\n", retry.as_millis());
+            writer.write(retry.as_bytes()).await.map_err(disconnected)?;
+        }
+
+        // The timer is still needed when pings are disabled, but its branch is never polled.
+        let period = sse.keep_alive.unwrap_or(Duration::from_secs(3600));
+        let mut pings = interval_at(Instant::now() + period, period);
+        pings.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut events = std::pin::pin!(sse.events);
+        let stream = self.tcp_stream.clone();
+
+        loop {
+            // The client never sends anything on an event stream, so a finished read means
+            // it has gone away. Reads are cancel-safe, so losing the race drops nothing.
+            let closed = async {
+                let mut probe = [0u8; 512];
+                loop {
+                    match stream.lock().await.read(&mut probe).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(_) => continue,
+                    }
+                }
+            };
+
+            let frame = tokio::select! {
+                event = events.next() => match event {
+                    Some(event) => event.to_string(),
+                    None => break,
+                },
+                _ = pings.tick(), if sse.keep_alive.is_some() => ":\n\n".to_string(),
+                _ = closed => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "Client disconnected",
+                    ));
+                }
+            };
+            writer.write(frame.as_bytes()).await.map_err(disconnected)?;
+            pings.reset();
+        }
+
+        writer.finish().await.map_err(disconnected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::shared_stream;
+    use crate::Server;
+    use std::sync::Arc;
+    use tokio::io::duplex;
+
+    #[test]
+    /// Tests the encoding of events, including multi-line payloads and line breaks in fields.
+    fn encode() {
+        let event = Event::new("line 1\r\nline 2\n")
+            .event("up\ndate")
+            .id("7")
+            .retry(Duration::from_secs(3));
+        assert_eq!(
+            event.to_string(),
+            "event: update\nid: 7\nretry: 3000\ndata: line 1\ndata: line 2\ndata: \n\n"
+        );
+        assert_eq!(Event::default().to_string(), "data: \n\n");
+    }
+
+    #[tokio::test]
+    /// Tests sending events and pings, and detecting a client that disconnects while idle.
+    async fn stream() {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (mut client, server_stream) = duplex(4096);
+        let mut res = Response::new(shared_stream(server_stream), "HTTP/1.1", server);
+
+        let events = futures::stream::iter([Event::new("a").id("1"), Event::new("b").id("2")])
+            .chain(futures::stream::pending());
+        let sse = Sse::new(events)
+            .keep_alive(Some(Duration::from_millis(20)))
+            .retry(Duration::from_millis(500));
+        let task = tokio::spawn(async move { res.sse(sse).await });
+
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !String::from_utf8_lossy(&output).contains(":\n\n") {
+            let n = client.read(&mut buffer).await.unwrap();
+            output.extend_from_slice(&buffer[..n]);
+        }
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Content-Type: text/event-stream\r\n"));
+        assert!(output.contains("Cache-Control: no-cache\r\n"));
+        assert!(output.contains("retry: 500\n\n"));
+        assert!(output.contains("id: 1\ndata: a\n\n"));
+        assert!(output.contains("id: 2\ndata: b\n\n"));
+
+        drop(client);
+        let result = tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+    }
+}
@@ -0,0 +1,550 @@
+use crate::range::{self, ByteRanges, RangeHeaders};
+use crate::request::Request;
+use crate::response::Response;
+use crate::routing::Handler;
+use crate::sendfile;
+use http::StatusCode;
+use log::warn;
+use percent_encoding::percent_decode_str;
+use std::fs::Metadata;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug)]
+/// The parameters of a `multipart/byteranges` body being sent.
+struct MultipartRanges {
+    /// The boundary between the parts.
+    boundary: String,
+    /// The `Content-Type` of the file, repeated in every part.
+    content_type: String,
+}
+
+#[derive(Debug, Clone)]
+/// Serves the files of a directory, mounted under a route ending in a `{*path}` segment.
+///
+/// Files are streamed from disk, with `sendfile(2)` on Linux when possible, and sent with a
+/// `Content-Type` guessed from their extension and an `ETag` and `Last-Modified` header so
+/// clients can revalidate them with `If-None-Match` or `If-Modified-Since`. `Range` requests
+/// are answered with `206 Partial Content`. Requests for a directory are served its index file.
+/// Paths that try to leave the directory, e.g. through `..` segments or symbolic links, are
+/// answered with `404`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rusticore::{Route, Server, StaticFiles};
+///
+/// # async fn run() {
+/// let mut server = Server::new("localhost", 8080, false, None, None);
+/// let assets = StaticFiles::new("./public").cache_control("public, max-age=3600");
+/// server.add_route(Route::new("GET", "/assets/{*path}", assets.handler())).await;
+/// # }
+/// ```
+pub struct StaticFiles {
+    /// The directory the files are served from.
+    root: PathBuf,
+    /// The file served for requests to a directory.
+    index_file: Option<String>,
+    /// The value of the `Cache-Control` header sent with every file.
+    cache_control: Option<String>,
+}
+
+impl StaticFiles {
+    /// Creates a new `StaticFiles` serving a directory, with `index.html` as index file.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The directory the files are served from.
+    ///
+    /// # Returns
+    ///
+    /// A new `StaticFiles` instance.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StaticFiles {
+            root: root.into(),
+            index_file: Some("index.html".to_string()),
+            cache_control: None,
+        }
+    }
+
+    /// Sets the file served for requests to a directory, or disables index files with `None`.
+    pub fn index_file(mut self, index_file: Option<&str>) -> Self {
+        self.index_file = index_file.map(str::to_string);
+        self
+    }
+
+    /// Sets the value of the `Cache-Control` header sent with every file.
+    pub fn cache_control(mut self, cache_control: impl Into<String>) -> Self {
+        self.cache_control = Some(cache_control.into());
+        self
+    }
+
+    /// Wraps the `StaticFiles` into a route handler.
+    ///
+    /// # Returns
+    ///
+    /// A `Handler` that serves the file named by the `path` parameter of the route, or by the
+    /// whole request path if the route has no such parameter.
+    pub fn handler(self) -> Handler {
+        let files = Arc::new(self);
+        Arc::new(move |req, res| {
+            let files = files.clone();
+            Box::pin(async move { files.serve(req, res).await })
+        })
+    }
+
+    /// Serves the file requested by a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - A mutable reference to the incoming HTTP request object.
+    /// * `res` - A mutable reference to the HTTP response object to which the file will be sent.
+    pub async fn serve(&self, req: &mut Request, res: &mut Response) {
+        let method = req.method();
+        if method != "GET" && method != "HEAD" {
+            res.set_header("Allow", "GET, HEAD");
+            res.text("Method Not Allowed", StatusCode::METHOD_NOT_ALLOWED)
+                .await;
+            return;
+        }
+        let head_only = method == "HEAD";
+
+        let (request_path, query) = match req.path().split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (req.path().to_string(), None),
+        };
+        let relative = match req.path_params.get("path") {
+            Some(path) => path.clone(),
+            None => request_path.clone(),
+        };
+
+        let Some(path) = self.resolve(&relative).await else {
+            res.text("Not Found", StatusCode::NOT_FOUND).await;
+            return;
+        };
+        let Ok(metadata) = tokio::fs::metadata(&path).await else {
+            res.text("Not Found", StatusCode::NOT_FOUND).await;
+            return;
+        };
+
+        let (path, metadata) = if metadata.is_dir() {
+            // Redirect to the slash-terminated URL so relative links in the index file resolve.
+            if !request_path.ends_with('/') {
+                // Leading slashes are collapsed, since `//host` would redirect to another host.
+                let request_path = format!("/{}", request_path.trim_start_matches('/'));
+                let location = match query {
+                    Some(query) => format!("{request_path}/?{query}"),
+                    None => format!("{request_path}/"),
+                };
+                res.set_header("Location", location);
+                res.text("Moved Permanently", StatusCode::MOVED_PERMANENTLY)
+                    .await;
+                return;
+            }
+            let Some(ref index_file) = self.index_file else {
+                res.text("Not Found", StatusCode::NOT_FOUND).await;
+                return;
+            };
+            let path = path.join(index_file);
+            match tokio::fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_file() => (path, metadata),
+                _ => {
+                    res.text("Not Found", StatusCode::NOT_FOUND).await;
+                    return;
+                }
+            }
+        } else if metadata.is_file() {
+            (path, metadata)
+        } else {
+            res.text("Not Found", StatusCode::NOT_FOUND).await;
+            return;
+        };
+
+        let modified = metadata.modified().ok();
+        res.set_header("ETag", etag(&metadata));
+        if let Some(modified) = modified {
+            res.set_header("Last-Modified", httpdate::fmt_http_date(modified));
+        }
+        if let Some(ref cache_control) = self.cache_control {
+            res.set_header("Cache-Control", cache_control.as_str());
+        }
+
+        if is_not_modified(req, &etag(&metadata), modified) {
+            res.status_code = StatusCode::NOT_MODIFIED;
+            match res.send_head().await {
+                Ok(()) => res.mark_sent(),
+                Err(e) => {
+                    warn!(target: res.server.get_target(), "Failed to send {}: {e}", path.display())
+                }
+            }
+            return;
+        }
+
+        let mut file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) => {
+                warn!(target: res.server.get_target(), "Failed to open {}: {e}", path.display());
+                res.text("Not Found", StatusCode::NOT_FOUND).await;
+                return;
+            }
+        };
+
+        let len = metadata.len();
+        let content_type = content_type(&path);
+        let ranges = match RangeHeaders::from_request(req) {
+            Some(headers) => range::evaluate(
+                &headers,
+                res.get_header("ETag"),
+                res.get_header("Last-Modified"),
+                len,
+            ),
+            None => ByteRanges::Full,
+        };
+        res.set_header("Accept-Ranges", "bytes");
+
+        let (ranges, multipart) = match ranges {
+            ByteRanges::Full => {
+                res.status_code = StatusCode::OK;
+                res.set_header("Content-Type", content_type);
+                res.set_header("Content-Length", len.to_string());
+                (vec![Range { start: 0, end: len }], None)
+            }
+            ByteRanges::Unsatisfiable => {
+                res.status_code = StatusCode::RANGE_NOT_SATISFIABLE;
+                res.set_header("Content-Range", format!("bytes */{len}"));
+                res.set_header("Content-Length", "0");
+                match res.send_head().await {
+                    Ok(()) => res.mark_sent(),
+                    Err(e) => {
+                        warn!(target: res.server.get_target(), "Failed to send {}: {e}", path.display())
+                    }
+                }
+                return;
+            }
+            ByteRanges::Partial(ranges) => {
+                res.status_code = StatusCode::PARTIAL_CONTENT;
+                if let [range] = ranges.as_slice() {
+                    res.set_header("Content-Type", content_type);
+                    res.set_header("Content-Range", range::content_range(range, len));
+                    res.set_header("Content-Length", (range.end - range.start).to_string());
+                    (ranges, None)
+                } else {
+                    let boundary = range::boundary();
+                    let body_len =
+                        range::multipart_len(&boundary, Some(&content_type), &ranges, len);
+                    res.set_header(
+                        "Content-Type",
+                        format!("multipart/byteranges; boundary={boundary}"),
+                    );
+                    res.set_header("Content-Length", body_len.to_string());
+                    let multipart = MultipartRanges {
+                        boundary,
+                        content_type,
+                    };
+                    (ranges, Some(multipart))
+                }
+            }
+        };
+
+        if let Err(e) = res.send_head().await {
+            warn!(target: res.server.get_target(), "Failed to send {}: {e}", path.display());
+            return;
+        }
+        if head_only {
+            res.mark_sent();
+            return;
+        }
+
+        let mut stream = res.tcp_stream.lock().await;
+        let result = async {
+            for range in ranges.iter() {
+                if let Some(ref multipart) = multipart {
+                    let head = range::part_head(
+                        &multipart.boundary,
+                        Some(&multipart.content_type),
+                        range,
+                        len,
+                    );
+                    stream.write_all(head.as_bytes()).await?;
+                }
+                sendfile::copy_range(&mut file, range.clone(), &mut **stream).await?;
+                if multipart.is_some() {
+                    stream.write_all(b"\r\n").await?;
+                }
+            }
+            if let Some(ref multipart) = multipart {
+                let closing = range::closing_delimiter(&multipart.boundary);
+                stream.write_all(closing.as_bytes()).await?;
+            }
+            stream.flush().await
+        };
+        match result.await {
+            Ok(()) => res.mark_sent(),
+            Err(e) => {
+                warn!(target: res.server.get_target(), "Failed to send {}: {e}", path.display())
+            }
+        }
+    }
+
+    /// Maps a URL path onto a path inside the root directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `relative` - The percent-encoded path of the file, relative to the mount point.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<PathBuf>` containing the canonical path of the file, or `None` if it does
+    /// not exist or lies outside the root directory.
+    async fn resolve(&self, relative: &str) -> Option<PathBuf> {
+        let decoded = percent_decode_str(relative).decode_utf8().ok()?;
+        let mut path = self.root.clone();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return None,
+                _ if segment.contains(['\\', '\0']) || Path::new(segment).has_root() => {
+                    return None;
+                }
+                _ => path.push(segment),
+            }
+        }
+
+        // Canonicalising also catches symbolic links pointing outside the root.
+        let root = tokio::fs::canonicalize(&self.root).await.ok()?;
+        let path = tokio::fs::canonicalize(&path).await.ok()?;
+        path.starts_with(&root).then_some(path)
+    }
+}
+
+/// Computes the entity tag of a file from its size and modification time.
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!(
+        "\"{:x}-{:x}{:08x}\"",
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    )
+}
+
+/// Checks the conditional headers of a request against the current state of a file.
+///
+/// `If-None-Match` takes precedence over `If-Modified-Since`, as required by RFC 9110.
+///
+/// # Arguments
+///
+/// * `req` - The incoming HTTP request object.
+/// * `etag` - The entity tag of the file.
+/// * `modified` - The modification time of the file, if known.
+///
+/// # Returns
+///
+/// `true` if the client's cached copy is still fresh and a `304 Not Modified` can be sent.
+fn is_not_modified(req: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = req.get_header("If-None-Match") {
+        // Weak comparison: `W/"x"` matches `"x"`.
+        let strip_weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        return if_none_match
+            .split(',')
+            .any(|tag| tag.trim() == "*" || strip_weak(tag) == etag);
+    }
+
+    let (Some(since), Some(modified)) = (req.get_header("If-Modified-Since"), modified) else {
+        return false;
+    };
+    let Ok(since) = httpdate::parse_http_date(since) else {
+        return false;
+    };
+    // HTTP dates have second precision, so compare the modification time truncated to seconds.
+    let modified = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| UNIX_EPOCH + Duration::from_secs(d.as_secs()))
+        .unwrap_or(modified);
+    modified <= since
+}
+
+/// Guesses the `Content-Type` of a file from its extension.
+fn content_type(path: &Path) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    match (mime.type_(), mime.subtype()) {
+        (mime_guess::mime::TEXT, _) | (_, mime_guess::mime::JAVASCRIPT) => {
+            format!("{mime}; charset=utf-8")
+        }
+        _ => mime.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::shared_stream;
+    use crate::Server;
+    use tokio::io::{duplex, AsyncReadExt};
+
+    /// Serves one request for `path` from `files` and returns the raw response.
+    async fn get(files: &StaticFiles, path: &str, headers: &str) -> String {
+        request(files, &format!("/assets{path}"), path, headers).await
+    }
+
+    /// Serves one request for `target`, mounted at the file `path`, and returns the raw response.
+    async fn request(files: &StaticFiles, target: &str, path: &str, headers: &str) -> String {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (mut client, server_stream) = duplex(64 * 1024);
+        let stream = shared_stream(server_stream);
+        let request = format!("GET {target} HTTP/1.1\r\n{headers}\r\n");
+        client.write_all(request.as_bytes()).await.unwrap();
+
+        let mut req = Request::new(stream.clone(), server.clone()).await.unwrap();
+        req.path_params
+            .insert("path".to_string(), path.trim_start_matches('/').to_string());
+        let mut res = Response::new(stream, "HTTP/1.1", server);
+        files.serve(&mut req, &mut res).await;
+        drop((req, res));
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        output
+    }
+
+    #[tokio::test]
+    /// Tests serving files, index files, directory redirects and path traversal protection.
+    async fn serve() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("public");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("site.css"), "body {}").unwrap();
+        std::fs::write(root.join("docs/index.html"), "<h1>Docs</h1>").unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        let files = StaticFiles::new(&root);
+
+        let output = get(&files, "/site.css", "").await;
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains("Content-Type: text/css; charset=utf-8\r\n"));
+        assert!(output.contains("Content-Length: 7\r\n"));
+        assert!(output.ends_with("\r\n\r\nbody {}"));
+
+        let output = get(&files, "/docs/", "").await;
+        assert!(output.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(output.ends_with("<h1>Docs</h1>"));
+        let output = get(&files, "/docs", "").await;
+        assert!(output.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(output.contains("Location: /assets/docs/\r\n"));
+        let output = request(&files, "//docs", "docs", "").await;
+        assert!(output.contains("Location: /docs/\r\n"));
+
+        for path in [
+            "/../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/missing.js",
+            "/docs/..%5csecret.txt",
+        ] {
+            let output = get(&files, path, "").await;
+            assert!(output.starts_with("HTTP/1.1 404 Not Found\r\n"), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    /// Tests revalidation with `If-None-Match` and `If-Modified-Since`.
+    async fn conditional() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("app.js"), "run()").unwrap();
+        let files = StaticFiles::new(dir.path()).cache_control("no-cache");
+
+        let output = get(&files, "/app.js", "").await;
+        let etag = output
+            .lines()
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .unwrap()
+            .to_string();
+        let last_modified = output
+            .lines()
+            .find_map(|line| line.strip_prefix("Last-Modified: "))
+            .unwrap()
+            .to_string();
+        assert!(output.contains("Cache-Control: no-cache\r\n"));
+
+        let output = get(
+            &files,
+            "/app.js",
+            &format!("If-None-Match: \"x\", W/{etag}\r\n"),
+        )
+        .await;
+        assert!(output.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(output.ends_with("\r\n\r\n"));
+        let output = get(&files, "/app.js", "If-None-Match: \"stale\"\r\n").await;
+        assert!(output.ends_with("run()"));
+
+        let since = format!("If-Modified-Since: {last_modified}\r\n");
+        let output = get(&files, "/app.js", &since).await;
+        assert!(output.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        let output = get(
+            &files,
+            "/app.js",
+            "If-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n",
+        )
+        .await;
+        assert!(output.ends_with("run()"));
+    }
+
+    #[tokio::test]
+    /// Tests single and multiple byte ranges, unsatisfiable ranges and `If-Range`.
+    async fn ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("clip.mp4"), "0123456789").unwrap();
+        let files = StaticFiles::new(dir.path());
+
+        let output = get(&files, "/clip.mp4", "Range: bytes=2-4\r\n").await;
+        assert!(output.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(output.contains("Content-Range: bytes 2-4/10\r\n"));
+        assert!(output.contains("Content-Type: video/mp4\r\n"));
+        assert!(output.ends_with("\r\n\r\n234"));
+
+        let output = get(&files, "/clip.mp4", "Range: bytes=0-0,-2\r\n").await;
+        let (head, body) = output.split_once("\r\n\r\n").unwrap();
+        let boundary = head
+            .split_once("boundary=")
+            .unwrap()
+            .1
+            .lines()
+            .next()
+            .unwrap();
+        let length = head
+            .split_once("Content-Length: ")
+            .unwrap()
+            .1
+            .lines()
+            .next()
+            .unwrap();
+        assert_eq!(length.parse::<usize>().unwrap(), body.len());
+        assert_eq!(
+            body,
+            format!(
+                "--{boundary}\r\nContent-Type: video/mp4\r\nContent-Range: bytes 0-0/10\r\n\r\n0\r\n\
+                 --{boundary}\r\nContent-Type: video/mp4\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+                 --{boundary}--\r\n"
+            )
+        );
+
+        let output = get(&files, "/clip.mp4", "Range: bytes=10-\r\n").await;
+        assert!(output.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(output.contains("Content-Range: bytes */10\r\n"));
+
+        let output = get(
+            &files,
+            "/clip.mp4",
+            "Range: bytes=0-1\r\nIf-Range: \"old\"\r\n",
+        )
+        .await;
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("0123456789"));
+    }
+}
@@ -0,0 +1,90 @@
+use std::io;
+use std::os::fd::RawFd;
+use std::os::unix::net::UnixDatagram;
+
+/// The first file descriptor passed by systemd socket activation.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Returns the listening sockets passed by systemd socket activation.
+///
+/// The sockets are only taken if `LISTEN_PID` names this process, so a child process that
+/// inherits the environment does not mistake them for its own.
+///
+/// # Returns
+///
+/// The file descriptors of the sockets, empty if the process was not socket activated.
+pub(crate) fn listen_fds() -> Vec<RawFd> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    parse_listen_fds(pid.as_deref(), fds.as_deref(), std::process::id())
+}
+
+/// Parses the `LISTEN_PID` and `LISTEN_FDS` variables of socket activation.
+fn parse_listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> Vec<RawFd> {
+    if pid.and_then(|pid| pid.trim().parse::<u32>().ok()) != Some(own_pid) {
+        return Vec::new();
+    }
+    let count = fds
+        .and_then(|fds| fds.trim().parse::<RawFd>().ok())
+        .unwrap_or(0);
+    (LISTEN_FDS_START..LISTEN_FDS_START + count.max(0)).collect()
+}
+
+/// Sends a state change to the service manager, e.g. `READY=1` or `STOPPING=1`.
+///
+/// Does nothing unless the process was started by systemd with `NOTIFY_SOCKET` set, e.g. for
+/// a `Type=notify` service.
+///
+/// # Arguments
+///
+/// * `state` - The newline-separated assignments to send.
+///
+/// # Returns
+///
+/// An `io::Result` indicating whether the message was sent.
+pub(crate) fn notify(state: &str) -> io::Result<()> {
+    match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) if !path.is_empty() => notify_socket(&path, state),
+        _ => Ok(()),
+    }
+}
+
+/// Sends a state change to a notification socket, which may be in the abstract namespace
+/// when its path starts with `@`.
+fn notify_socket(path: &str, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+        return socket.send_to_addr(state.as_bytes(), &addr).map(|_| ());
+    }
+    socket.send_to(state.as_bytes(), path).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Tests reading socket activation variables meant for this process or another one.
+    fn listen_fds_for_this_process() {
+        assert_eq!(parse_listen_fds(Some("42"), Some("2"), 42), vec![3, 4]);
+        assert!(parse_listen_fds(Some("41"), Some("2"), 42).is_empty());
+        assert!(parse_listen_fds(None, Some("2"), 42).is_empty());
+        assert!(parse_listen_fds(Some("42"), Some("-1"), 42).is_empty());
+        assert!(parse_listen_fds(Some("42"), None, 42).is_empty());
+    }
+
+    #[test]
+    /// Tests that state changes are sent as datagrams to the notification socket.
+    fn notify_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        notify_socket(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buffer = [0u8; 64];
+        let n = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"READY=1");
+    }
+}
@@ -0,0 +1,824 @@
+use crate::request::Request;
+use crate::Server;
+use log::{info, warn};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::CertificateDer;
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::task::JoinHandle;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use x509_parser::extensions::GeneralName;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents the errors that can occur while loading TLS certificates and keys.
+pub enum TlsError {
+    /// A PEM file could not be read.
+    Io {
+        /// The path of the file.
+        path: PathBuf,
+        /// The error reported by the operating system.
+        message: String,
+    },
+    /// A PEM file contains no certificate.
+    NoCertificates(PathBuf),
+    /// A PEM file contains no private key.
+    NoPrivateKey(PathBuf),
+    /// A private key is not supported, or does not match its certificate.
+    InvalidKey(String),
+    /// The TLS configuration was rejected by rustls.
+    Config(String),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Io { path, message } => {
+                write!(f, "Failed to read {}: {message}", path.display())
+            }
+            TlsError::NoCertificates(path) => {
+                write!(f, "No certificate found in {}", path.display())
+            }
+            TlsError::NoPrivateKey(path) => {
+                write!(f, "No private key found in {}", path.display())
+            }
+            TlsError::InvalidKey(msg) => write!(f, "Invalid private key: {msg}"),
+            TlsError::Config(msg) => write!(f, "Invalid TLS configuration: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+/// The protocols offered with ALPN by default, in order of preference.
+#[cfg(feature = "http2")]
+const DEFAULT_ALPN_PROTOCOLS: &[&[u8]] = &[b"h2", b"http/1.1"];
+/// The protocols offered with ALPN by default.
+#[cfg(not(feature = "http2"))]
+const DEFAULT_ALPN_PROTOCOLS: &[&[u8]] = &[b"http/1.1"];
+
+/// The default time allowed for a client to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reads the certificates of a PEM file.
+pub(crate) fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let io_error = |e: io::Error| TlsError::Io {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    };
+    let mut reader = BufReader::new(File::open(path).map_err(io_error)?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_error)?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certs)
+}
+
+#[derive(Debug, Clone)]
+/// The PEM files of a certificate chain and its private key.
+struct CertFiles {
+    /// The path of the certificate chain, leaf first.
+    cert_path: PathBuf,
+    /// The path of the private key.
+    key_path: PathBuf,
+}
+
+impl CertFiles {
+    /// Loads the certificate chain and key.
+    fn load(&self, provider: &CryptoProvider) -> Result<Arc<CertifiedKey>, TlsError> {
+        let certs = load_certs(&self.cert_path)?;
+        let mut reader = BufReader::new(File::open(&self.key_path).map_err(|e| TlsError::Io {
+            path: self.key_path.clone(),
+            message: e.to_string(),
+        })?);
+        let key = rustls_pemfile::private_key(&mut reader)
+            .map_err(|e| TlsError::Io {
+                path: self.key_path.clone(),
+                message: e.to_string(),
+            })?
+            .ok_or_else(|| TlsError::NoPrivateKey(self.key_path.clone()))?;
+        CertifiedKey::from_der(certs, key, provider)
+            .map(Arc::new)
+            .map_err(|e| TlsError::InvalidKey(e.to_string()))
+    }
+
+    /// Returns the latest modification time of the two files.
+    fn modified(&self) -> Option<SystemTime> {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        modified(&self.cert_path).max(modified(&self.key_path))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The certificates currently served.
+struct Certificates {
+    /// The files and certificate served when no SNI certificate matches.
+    default: (CertFiles, Arc<CertifiedKey>),
+    /// The files and certificates selected by SNI, keyed by lowercase host name or `*.` wildcard.
+    named: Vec<(String, CertFiles, Arc<CertifiedKey>)>,
+}
+
+#[derive(Debug)]
+/// Selects the certificate of a connection from the server name the client asked for.
+struct CertResolver {
+    /// The cryptography used to load keys.
+    provider: Arc<CryptoProvider>,
+    /// The certificates, swapped as a whole when reloaded.
+    certificates: RwLock<Certificates>,
+}
+
+impl CertResolver {
+    /// Returns a snapshot of the certificates.
+    fn certificates(&self) -> Certificates {
+        self.certificates
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certificates = self.certificates.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(name) = client_hello.server_name() {
+            let name = name.to_ascii_lowercase();
+            let find = |host: &str| {
+                certificates
+                    .named
+                    .iter()
+                    .find(|(named, _, _)| named == host)
+                    .map(|(_, _, key)| key.clone())
+            };
+            let wildcard = || {
+                let (_, parent) = name.split_once('.')?;
+                find(&format!("*.{parent}"))
+            };
+            if let Some(key) = find(&name).or_else(wildcard) {
+                return Some(key);
+            }
+        }
+        Some(certificates.default.1.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The TLS settings of a server: certificates, SNI, ALPN and client certificates.
+///
+/// Certificates are loaded from PEM files when added, and can be reloaded from the same files
+/// at any time, e.g. after a renewal, without restarting the server. Clones share the same
+/// certificates, so a clone can be kept to call `reload` once the original is given to the server.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use rusticore::{Server, TlsConfig};
+/// # use std::time::Duration;
+/// # async fn setup(server: &mut Server) -> Result<(), rusticore::TlsError> {
+/// let tls = TlsConfig::from_pem_files("certs/default.pem", "certs/default.key")?
+///     .with_sni("api.example.com", "certs/api.pem", "certs/api.key")?;
+/// tls.watch(server, Duration::from_secs(60));
+/// server.tls = Some(tls);
+/// # Ok(())
+/// # }
+/// ```
+pub struct TlsConfig {
+    /// The certificates, shared with the rustls configuration.
+    resolver: Arc<CertResolver>,
+    /// The rustls configuration, rebuilt whenever a setting changes.
+    server_config: Arc<ServerConfig>,
+    /// The time allowed for a client to complete the handshake.
+    handshake_timeout: Duration,
+    /// The protocols offered with ALPN, in order of preference.
+    alpn_protocols: Vec<Vec<u8>>,
+    /// The verifier of client certificates, or `None` not to ask for them.
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+}
+
+impl TlsConfig {
+    /// Creates a TLS configuration with a default certificate, negotiating `http/1.1` with ALPN,
+    /// preceded by `h2` with the `http2` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `cert_path` - The path of a PEM file with the certificate chain, leaf first.
+    /// * `key_path` - The path of a PEM file with the private key, in PKCS#8, PKCS#1 or SEC1 format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a new `TlsConfig` instance.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `TlsError` if the files cannot be read or the key does not match the certificate.
+    pub fn from_pem_files(
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self, TlsError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let files = CertFiles {
+            cert_path: cert_path.as_ref().to_path_buf(),
+            key_path: key_path.as_ref().to_path_buf(),
+        };
+        let key = files.load(&provider)?;
+        let resolver = Arc::new(CertResolver {
+            provider,
+            certificates: RwLock::new(Certificates {
+                default: (files, key),
+                named: Vec::new(),
+            }),
+        });
+        let alpn_protocols: Vec<Vec<u8>> =
+            DEFAULT_ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect();
+        let server_config = build_server_config(&resolver, &alpn_protocols, None)?;
+        Ok(TlsConfig {
+            resolver,
+            server_config,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            alpn_protocols,
+            client_verifier: None,
+        })
+    }
+
+    /// Adds a certificate served to clients asking for a host name with SNI.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host name, e.g. `api.example.com`, or a wildcard such as `*.example.com`
+    ///   matching one label.
+    /// * `cert_path` - The path of a PEM file with the certificate chain, leaf first.
+    /// * `key_path` - The path of a PEM file with the private key.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the updated `TlsConfig`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `TlsError` if the files cannot be read or the key does not match the certificate.
+    pub fn with_sni(
+        self,
+        host: &str,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self, TlsError> {
+        let files = CertFiles {
+            cert_path: cert_path.as_ref().to_path_buf(),
+            key_path: key_path.as_ref().to_path_buf(),
+        };
+        let key = files.load(&self.resolver.provider)?;
+        let host = host.to_ascii_lowercase();
+        let mut certificates = self
+            .resolver
+            .certificates
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        certificates.named.retain(|(name, _, _)| *name != host);
+        certificates.named.push((host, files, key));
+        drop(certificates);
+        Ok(self)
+    }
+
+    /// Sets the protocols offered with ALPN, in order of preference.
+    ///
+    /// # Arguments
+    ///
+    /// * `protocols` - The protocol names, e.g. `["http/1.1"]`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the updated `TlsConfig`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `TlsError` if the configuration is rejected by rustls.
+    pub fn alpn_protocols<I, P>(mut self, protocols: I) -> Result<Self, TlsError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        self.alpn_protocols = protocols.into_iter().map(|p| p.as_ref().to_vec()).collect();
+        self.rebuild()?;
+        Ok(self)
+    }
+
+    /// Asks clients for a certificate and verifies it against a bundle of CA certificates,
+    /// for mutual TLS. The verified certificate is available through `Request::peer_certificate`.
+    ///
+    /// The CA bundle is loaded once; `reload` only reloads the server's own certificates.
+    ///
+    /// # Arguments
+    ///
+    /// * `ca_path` - The path of a PEM file with the trusted CA certificates.
+    /// * `required` - Whether clients without a certificate are refused. If `false`, they are
+    ///   let through without a `peer_certificate`, but invalid certificates are still refused.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the updated `TlsConfig`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `TlsError` if the bundle cannot be read or contains an invalid certificate.
+    pub fn with_client_ca(
+        mut self,
+        ca_path: impl AsRef<Path>,
+        required: bool,
+    ) -> Result<Self, TlsError> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(ca_path.as_ref())? {
+            roots
+                .add(cert)
+                .map_err(|e| TlsError::Config(e.to_string()))?;
+        }
+        let builder = WebPkiClientVerifier::builder_with_provider(
+            Arc::new(roots),
+            self.resolver.provider.clone(),
+        );
+        let builder = if required {
+            builder
+        } else {
+            builder.allow_unauthenticated()
+        };
+        let verifier = builder
+            .build()
+            .map_err(|e| TlsError::Config(e.to_string()))?;
+        self.client_verifier = Some(verifier);
+        self.rebuild()?;
+        Ok(self)
+    }
+
+    /// Sets the time allowed for a client to complete the handshake before it is disconnected.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Reloads every certificate and key from disk.
+    ///
+    /// New connections use the reloaded certificates; established ones are not affected.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the certificates were reloaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `TlsError` if any file cannot be loaded, in which case the current
+    /// certificates are kept, so a half-written renewal never takes the server down.
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let current = self.resolver.certificates();
+        let provider = &self.resolver.provider;
+        let default = (current.default.0.clone(), current.default.0.load(provider)?);
+        let mut named = Vec::with_capacity(current.named.len());
+        for (host, files, _) in current.named {
+            let key = files.load(provider)?;
+            named.push((host, files, key));
+        }
+        *self
+            .resolver
+            .certificates
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Certificates { default, named };
+        Ok(())
+    }
+
+    /// Reloads the certificates whenever one of their files changes, logging the outcome under
+    /// the server's log target.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The server the certificates are used by.
+    /// * `interval` - How often the modification times of the files are checked.
+    ///
+    /// # Returns
+    ///
+    /// The `JoinHandle` of the watching task, which can be aborted to stop watching.
+    pub fn watch(&self, server: &Server, interval: Duration) -> JoinHandle<()> {
+        let tls = self.clone();
+        let target = server.get_target();
+        let modified = move |tls: &TlsConfig| {
+            let certificates = tls.resolver.certificates();
+            std::iter::once(&certificates.default.0)
+                .chain(certificates.named.iter().map(|(_, files, _)| files))
+                .filter_map(CertFiles::modified)
+                .max()
+        };
+        tokio::spawn(async move {
+            let mut last = modified(&tls);
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let current = modified(&tls);
+                if current != last {
+                    match tls.reload() {
+                        Ok(()) => {
+                            info!(target: target, "Reloaded TLS certificates");
+                            last = current;
+                        }
+                        Err(e) => warn!(target: target, "Failed to reload TLS certificates: {e}"),
+                    }
+                }
+            }
+        })
+    }
+
+    /// Rebuilds the rustls configuration after a setting has changed.
+    fn rebuild(&mut self) -> Result<(), TlsError> {
+        self.server_config = build_server_config(
+            &self.resolver,
+            &self.alpn_protocols,
+            self.client_verifier.clone(),
+        )?;
+        Ok(())
+    }
+
+    /// Performs the TLS handshake of an accepted connection.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the encrypted stream, or an error if the handshake failed or timed out.
+    pub(crate) async fn accept<S>(&self, stream: S) -> io::Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let acceptor = TlsAcceptor::from(self.server_config.clone());
+        match tokio::time::timeout(self.handshake_timeout, acceptor.accept(stream)).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "TLS handshake timed out",
+            )),
+        }
+    }
+}
+
+/// Builds the rustls configuration serving the resolver's certificates.
+fn build_server_config(
+    resolver: &Arc<CertResolver>,
+    alpn_protocols: &[Vec<u8>],
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let builder = ServerConfig::builder_with_provider(resolver.provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| TlsError::Config(e.to_string()))?;
+    let builder = match client_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(resolver.clone());
+    config.alpn_protocols = alpn_protocols.to_vec();
+    Ok(Arc::new(config))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A name a client certificate is valid for, from its subject alternative name extension.
+pub enum SubjectAltName {
+    /// A DNS name, e.g. `billing.internal`.
+    Dns(String),
+    /// An email address.
+    Email(String),
+    /// A URI, e.g. a SPIFFE ID such as `spiffe://example.org/billing`.
+    Uri(String),
+    /// An IP address.
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The verified certificate a client presented with mutual TLS.
+pub struct PeerCertificate {
+    /// The subject distinguished name, e.g. `CN=billing, O=Example`.
+    pub subject: String,
+    /// The common name of the subject, if any.
+    pub common_name: Option<String>,
+    /// The issuer distinguished name.
+    pub issuer: String,
+    /// The names from the subject alternative name extension.
+    pub subject_alt_names: Vec<SubjectAltName>,
+    /// The DER encoding of the certificate, for checks beyond the parsed fields.
+    pub der: Vec<u8>,
+}
+
+impl PeerCertificate {
+    /// Parses the DER encoding of a certificate.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<PeerCertificate>`, or `None` if the certificate cannot be parsed.
+    fn parse(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        let mut subject_alt_names = Vec::new();
+        if let Ok(Some(extension)) = cert.subject_alternative_name() {
+            for name in extension.value.general_names.iter() {
+                let name = match name {
+                    GeneralName::DNSName(dns) => SubjectAltName::Dns(dns.to_string()),
+                    GeneralName::RFC822Name(email) => SubjectAltName::Email(email.to_string()),
+                    GeneralName::URI(uri) => SubjectAltName::Uri(uri.to_string()),
+                    GeneralName::IPAddress(bytes) => match bytes.len() {
+                        4 => SubjectAltName::Ip(IpAddr::from(<[u8; 4]>::try_from(*bytes).ok()?)),
+                        16 => SubjectAltName::Ip(IpAddr::from(<[u8; 16]>::try_from(*bytes).ok()?)),
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                subject_alt_names.push(name);
+            }
+        }
+
+        Some(PeerCertificate {
+            subject: cert.subject().to_string(),
+            common_name: cert
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(str::to_string),
+            issuer: cert.issuer().to_string(),
+            subject_alt_names,
+            der: der.to_vec(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Details of the TLS session a request was received on.
+pub struct TlsInfo {
+    /// The host name the client asked for with SNI, if any.
+    pub server_name: Option<String>,
+    /// The protocol negotiated with ALPN, e.g. `http/1.1`, if any.
+    pub alpn_protocol: Option<String>,
+    /// The verified client certificate, if the client presented one with mutual TLS.
+    pub peer_certificate: Option<PeerCertificate>,
+}
+
+/// Extracts the TLS details of an established connection.
+pub(crate) fn connection_info<S>(stream: &TlsStream<S>) -> TlsInfo {
+    let connection = stream.get_ref().1;
+    TlsInfo {
+        server_name: connection.server_name().map(str::to_string),
+        alpn_protocol: connection
+            .alpn_protocol()
+            .map(|p| String::from_utf8_lossy(p).into_owned()),
+        peer_certificate: connection
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|cert| PeerCertificate::parse(cert)),
+    }
+}
+
+impl Request {
+    /// Returns the details of the TLS session the request was received on.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&TlsInfo>`, or `None` if the request was received over plain HTTP.
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.extensions.get::<TlsInfo>()
+    }
+
+    /// Returns the verified certificate the client presented with mutual TLS.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&PeerCertificate>`, or `None` if the client presented no certificate.
+    pub fn peer_certificate(&self) -> Option<&PeerCertificate> {
+        self.tls()?.peer_certificate.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Server;
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+        SanType,
+    };
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+    use rustls::ClientConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_rustls::TlsConnector;
+
+    /// Writes a self-signed certificate for some host names and returns the PEM file paths.
+    fn self_signed(dir: &Path, name: &str, hosts: &[&str]) -> (PathBuf, PathBuf) {
+        let hosts = hosts.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+        let certified = rcgen::generate_simple_self_signed(hosts).unwrap();
+        let cert_path = dir.join(format!("{name}.pem"));
+        let key_path = dir.join(format!("{name}.key"));
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    /// The client side of a TLS connection.
+    type ClientStream = tokio_rustls::client::TlsStream<TcpStream>;
+
+    /// Connects to a server with SNI and returns the served certificate and the connection.
+    async fn handshake(
+        addr: std::net::SocketAddr,
+        host: &str,
+        trusted: &[&Path],
+    ) -> io::Result<(CertificateDer<'static>, ClientStream)> {
+        handshake_as(addr, host, trusted, None).await
+    }
+
+    /// Connects to a server like `handshake`, presenting a client certificate chain and key if given.
+    async fn handshake_as(
+        addr: std::net::SocketAddr,
+        host: &str,
+        trusted: &[&Path],
+        client_auth: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    ) -> io::Result<(CertificateDer<'static>, ClientStream)> {
+        let mut roots = RootCertStore::empty();
+        for path in trusted {
+            for cert in load_certs(path).unwrap() {
+                roots.add(cert).unwrap();
+            }
+        }
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+        let mut config = match client_auth {
+            Some((chain, key)) => builder.with_client_auth_cert(chain, key).unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let tcp = TcpStream::connect(addr).await?;
+        let name = ServerName::try_from(host.to_string()).unwrap();
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(name, tcp)
+            .await?;
+        let cert = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+        Ok((cert, stream))
+    }
+
+    #[tokio::test]
+    /// Tests serving HTTPS with ALPN, selecting certificates with SNI, and reloading them from disk.
+    async fn serve_https() {
+        let dir = tempfile::tempdir().unwrap();
+        let (default_cert, default_key) = self_signed(dir.path(), "default", &["localhost"]);
+        let (api_cert, api_key) = self_signed(dir.path(), "api", &["api.test"]);
+        let tls = TlsConfig::from_pem_files(&default_cert, &default_key)
+            .unwrap()
+            .with_sni("API.test", &api_cert, &api_key)
+            .unwrap();
+        assert!(matches!(
+            TlsConfig::from_pem_files(&default_cert, dir.path().join("missing.key")),
+            Err(TlsError::Io { .. })
+        ));
+
+        /// Responds with the TLS details of the request.
+        async fn describe(req: &mut Request, res: &mut crate::Response) {
+            let info = req.tls().cloned().unwrap();
+            let body = format!("{:?} {:?}", info.server_name, info.alpn_protocol);
+            res.text(&body, http::StatusCode::OK).await;
+        }
+
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.tls = Some(tls.clone());
+        server
+            .add_route(crate::Route::new(
+                "GET",
+                "/tls",
+                Arc::new(|req, res| Box::pin(describe(req, res))),
+            ))
+            .await;
+        server.start().await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let (cert, mut stream) = handshake(addr, "localhost", &[&default_cert])
+            .await
+            .unwrap();
+        assert_eq!(cert, load_certs(&default_cert).unwrap()[0]);
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+        stream
+            .write_all(b"GET /tls HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("Some(\"localhost\") Some(\"http/1.1\")"));
+
+        let (cert, _) = handshake(addr, "api.test", &[&api_cert]).await.unwrap();
+        assert_eq!(cert, load_certs(&api_cert).unwrap()[0]);
+
+        // A renewed certificate is served once reloaded; a broken renewal keeps the old one.
+        let old_cert = dir.path().join("old.pem");
+        std::fs::copy(&default_cert, &old_cert).unwrap();
+        let (renewed_cert, _) = self_signed(dir.path(), "default", &["localhost"]);
+        assert!(handshake(addr, "localhost", &[&old_cert]).await.is_ok());
+        tls.reload().unwrap();
+        let (cert, _) = handshake(addr, "localhost", &[&renewed_cert])
+            .await
+            .unwrap();
+        assert_eq!(cert, load_certs(&renewed_cert).unwrap()[0]);
+        std::fs::write(&default_key, "not a key").unwrap();
+        assert!(matches!(tls.reload(), Err(TlsError::NoPrivateKey(_))));
+        assert!(handshake(addr, "localhost", &[&renewed_cert]).await.is_ok());
+
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    /// Tests requiring client certificates signed by a CA, and exposing their subject and SANs.
+    async fn client_certificates() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server_cert, server_key) = self_signed(dir.path(), "server", &["localhost"]);
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Test CA");
+        let ca = params.self_signed(&ca_key).unwrap();
+        let ca_path = dir.path().join("ca.pem");
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+
+        let client_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["billing.internal".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "billing");
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.subject_alt_names.push(SanType::URI(
+            "spiffe://example.org/billing".try_into().unwrap(),
+        ));
+        let client = params.signed_by(&client_key, &ca, &ca_key).unwrap();
+        let client_auth = || {
+            let key = PrivatePkcs8KeyDer::from(client_key.serialize_der());
+            Some((vec![client.der().clone()], PrivateKeyDer::from(key)))
+        };
+
+        /// Responds with the subject and SANs of the client certificate.
+        async fn whoami(req: &mut Request, res: &mut crate::Response) {
+            let body = match req.peer_certificate() {
+                Some(cert) => format!("{:?} {:?}", cert.common_name, cert.subject_alt_names),
+                None => "anonymous".to_string(),
+            };
+            res.text(&body, http::StatusCode::OK).await;
+        }
+
+        let tls = TlsConfig::from_pem_files(&server_cert, &server_key)
+            .unwrap()
+            .with_client_ca(&ca_path, true)
+            .unwrap();
+        let mut server = Server::new("127.0.0.1", 0, false, None, None);
+        server.tls = Some(tls);
+        server
+            .add_route(crate::Route::new(
+                "GET",
+                "/whoami",
+                Arc::new(|req, res| Box::pin(whoami(req, res))),
+            ))
+            .await;
+        server.start().await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        /// Requests `/whoami`, returning an empty string if the server refused the connection.
+        async fn get(mut stream: ClientStream) -> String {
+            let mut response = Vec::new();
+            if stream
+                .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .is_ok()
+            {
+                let _ = stream.read_to_end(&mut response).await;
+            }
+            String::from_utf8_lossy(&response).into_owned()
+        }
+
+        let (_, stream) = handshake_as(addr, "localhost", &[&server_cert], client_auth())
+            .await
+            .unwrap();
+        let response = get(stream).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(
+            "Some(\"billing\") [Dns(\"billing.internal\"), Uri(\"spiffe://example.org/billing\")]"
+        ));
+
+        // With TLS 1.3 the client finishes its handshake before the server refuses it.
+        if let Ok((_, stream)) = handshake(addr, "localhost", &[&server_cert]).await {
+            assert_eq!(get(stream).await, "");
+        }
+        server.shutdown().await;
+    }
+}
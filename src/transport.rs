@@ -0,0 +1,63 @@
+use std::any::Any;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::Mutex;
+
+/// A bidirectional byte stream that requests are read from and responses are written to.
+///
+/// It is implemented for every `AsyncRead + AsyncWrite` type, such as a `TcpStream`
+/// or an in-memory `DuplexStream`.
+pub trait Transport: AsyncRead + AsyncWrite + Debug + Send + Unpin {
+    /// Returns the transport as `Any`, so its concrete type can be recovered, e.g. to send
+    /// files with `sendfile(2)` when it is a plain `TcpStream`.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: AsyncRead + AsyncWrite + Debug + Send + Unpin + 'static> Transport for T {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// A connection shared between a `Request` and the `Response` written back to it.
+pub type SharedStream = Arc<Mutex<Box<dyn Transport>>>;
+
+/// Wraps a transport so it can be shared between a `Request` and its `Response`.
+///
+/// # Arguments
+///
+/// * `stream` - The transport to wrap, e.g. a `TcpStream`.
+///
+/// # Returns
+///
+/// A `SharedStream` owning the transport.
+///
+/// # Examples
+///
+/// ```
+/// use rusticore::shared_stream;
+///
+/// let (_client, server) = tokio::io::duplex(1024);
+/// let stream = shared_stream(server);
+/// ```
+pub fn shared_stream<T: Transport + 'static>(stream: T) -> SharedStream {
+    Arc::new(Mutex::new(Box::new(stream)))
+}
+
+/// Takes the transport out of a shared stream, e.g. when the connection is upgraded to another protocol.
+///
+/// The shared stream is left with a placeholder that reads nothing and discards writes, so the
+/// `Request` and `Response` still holding it cannot interfere with the new protocol.
+///
+/// # Arguments
+///
+/// * `stream` - The shared stream to take the transport from.
+///
+/// # Returns
+///
+/// The transport, owned by the caller.
+pub(crate) async fn detach(stream: &SharedStream) -> Box<dyn Transport> {
+    let placeholder = tokio::io::join(tokio::io::empty(), tokio::io::sink());
+    std::mem::replace(&mut *stream.lock().await, Box::new(placeholder))
+}
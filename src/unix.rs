@@ -0,0 +1,202 @@
+use crate::Request;
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::{UnixListener, UnixStream};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A Unix domain socket the server listens on instead of a TCP address, e.g. behind a local
+/// reverse proxy.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rusticore::{Server, UnixSocket};
+///
+/// # async fn run() {
+/// let mut server = Server::new("localhost", 0, false, None, None);
+/// server.unix_socket = Some(UnixSocket::new("/run/app/http.sock").mode(0o660));
+/// server.start().await.unwrap();
+/// # }
+/// ```
+pub struct UnixSocket {
+    /// The path of the socket file.
+    pub path: PathBuf,
+    /// The permissions of the socket file, or `None` to keep those set by the umask.
+    pub mode: Option<u32>,
+}
+
+impl UnixSocket {
+    /// Creates a Unix socket listener option for a path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the socket file, created when the server starts.
+    ///
+    /// # Returns
+    ///
+    /// A new `UnixSocket` keeping the default permissions.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        UnixSocket {
+            path: path.as_ref().to_path_buf(),
+            mode: None,
+        }
+    }
+
+    /// Sets the permissions of the socket file, which decide who may connect.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The permission bits, e.g. `0o660` for the owner and group only.
+    ///
+    /// # Returns
+    ///
+    /// The updated `UnixSocket`.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Binds the socket, removing a stale socket file left behind by a server that did not
+    /// shut down cleanly.
+    ///
+    /// # Returns
+    ///
+    /// An `io::Result` containing the listener.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AddrInUse` if another server is listening on the path, `AlreadyExists` if the
+    /// path is not a socket, or the error of binding or setting the permissions.
+    pub(crate) fn bind(&self) -> io::Result<UnixListener> {
+        match fs::symlink_metadata(&self.path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                // A socket nobody answers on is stale; a live one belongs to another server.
+                if std::os::unix::net::UnixStream::connect(&self.path).is_ok() {
+                    return Err(io::ErrorKind::AddrInUse.into());
+                }
+                fs::remove_file(&self.path)?;
+            }
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "The socket path exists and is not a socket",
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let listener = UnixListener::bind(&self.path)?;
+        if let Some(mode) = self.mode {
+            fs::set_permissions(&self.path, Permissions::from_mode(mode))?;
+        }
+        Ok(listener)
+    }
+
+    /// Removes the socket file once the server stops listening.
+    pub(crate) fn remove(&self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The credentials of the process on the other end of a Unix socket, from `SO_PEERCRED`.
+pub struct PeerCredentials {
+    /// The user ID of the peer process.
+    pub uid: u32,
+    /// The group ID of the peer process.
+    pub gid: u32,
+    /// The process ID of the peer, if the platform reports it.
+    pub pid: Option<i32>,
+}
+
+/// Reads the credentials of the peer of a Unix socket connection.
+///
+/// # Arguments
+///
+/// * `stream` - The accepted connection.
+///
+/// # Returns
+///
+/// An `Option<PeerCredentials>`, or `None` if the platform cannot tell.
+pub(crate) fn peer_credentials(stream: &UnixStream) -> Option<PeerCredentials> {
+    let credentials = stream.peer_cred().ok()?;
+    Some(PeerCredentials {
+        uid: credentials.uid(),
+        gid: credentials.gid(),
+        pid: credentials.pid(),
+    })
+}
+
+impl Request {
+    /// Returns the credentials of the process that sent the request over a Unix socket.
+    ///
+    /// # Returns
+    ///
+    /// An `Option<&PeerCredentials>`, or `None` if the request was not received on a Unix socket.
+    pub fn peer_credentials(&self) -> Option<&PeerCredentials> {
+        self.extensions.get::<PeerCredentials>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Response, Route, Server};
+    use http::StatusCode;
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    /// Tests serving requests on a Unix socket, with its permissions, the peer credentials,
+    /// stale socket cleanup and removal on shutdown.
+    async fn serve_unix_socket() {
+        /// Responds with the user ID of the peer.
+        async fn whoami(req: &mut Request, res: &mut Response) {
+            let credentials = req.peer_credentials().copied().unwrap();
+            let body = format!("{} {}", credentials.uid, credentials.pid.is_some());
+            res.text(&body, StatusCode::OK).await;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("http.sock");
+        // A socket left behind by a server that crashed.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let mut server = Server::new("localhost", 0, false, None, None);
+        server.unix_socket = Some(UnixSocket::new(&path).mode(0o660));
+        server
+            .add_route(Route::new(
+                "GET",
+                "/whoami",
+                Arc::new(|req, res| Box::pin(whoami(req, res))),
+            ))
+            .await;
+        server.start().await.unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        // The socket is live now, so a second server must not take it over.
+        let mut other = Server::new("localhost", 0, false, None, None);
+        other.unix_socket = Some(UnixSocket::new(&path));
+        assert!(other.start().await.is_err());
+
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        client
+            .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        let uid = fs::metadata(&path).unwrap().uid();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(&format!("{uid} true")));
+
+        server.shutdown().await;
+        assert!(!path.exists());
+    }
+}
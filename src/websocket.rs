@@ -0,0 +1,966 @@
+use crate::request::Request;
+use crate::response::Response;
+use crate::transport::{detach, Transport};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use http::StatusCode;
+use sha1::{Digest, Sha1};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::sync::Mutex;
+
+/// The GUID appended to the client's key to compute `Sec-WebSocket-Accept`, from RFC 6455.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest payload of a control frame.
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// The frame opcodes defined by RFC 6455.
+mod opcode {
+    pub const CONTINUATION: u8 = 0x0;
+    pub const TEXT: u8 = 0x1;
+    pub const BINARY: u8 = 0x2;
+    pub const CLOSE: u8 = 0x8;
+    pub const PING: u8 = 0x9;
+    pub const PONG: u8 = 0xA;
+}
+
+/// The close codes sent by the server.
+pub mod close_code {
+    /// The connection is closed normally.
+    pub const NORMAL: u16 = 1000;
+    /// The server is going away, e.g. shutting down.
+    pub const GOING_AWAY: u16 = 1001;
+    /// The peer violated the protocol.
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    /// A text message was not valid UTF-8.
+    pub const INVALID_PAYLOAD: u16 = 1007;
+    /// A message exceeded the size limits.
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The limits and options of a WebSocket connection.
+pub struct WebSocketConfig {
+    /// The maximum size in bytes of a received message, after reassembly and decompression.
+    pub max_message_size: usize,
+    /// The maximum size in bytes of a received frame. Sent messages larger than this are fragmented.
+    pub max_frame_size: usize,
+    /// The subprotocols supported by the server, in order of preference.
+    pub protocols: Vec<String>,
+    /// Whether the `permessage-deflate` extension is accepted when the client offers it.
+    #[cfg(feature = "websocket-deflate")]
+    pub permessage_deflate: bool,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        WebSocketConfig {
+            max_message_size: 16 * 1024 * 1024,
+            max_frame_size: 16 * 1024 * 1024,
+            protocols: Vec::new(),
+            #[cfg(feature = "websocket-deflate")]
+            permessage_deflate: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The code and reason of a close frame.
+pub struct CloseFrame {
+    /// The close code, e.g. `1000` for a normal closure.
+    pub code: u16,
+    /// The reason for closing, for debugging.
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents a WebSocket message.
+pub enum Message {
+    /// A UTF-8 text message.
+    Text(String),
+    /// A binary message.
+    Binary(Vec<u8>),
+    /// A ping. Received pings are answered with a pong automatically.
+    Ping(Vec<u8>),
+    /// A pong, sent in reply to a ping or as a unidirectional heartbeat.
+    Pong(Vec<u8>),
+    /// A close frame, with its code and reason if the peer sent one.
+    Close(Option<CloseFrame>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Represents the errors that can occur during a WebSocket handshake or connection.
+pub enum WebSocketError {
+    /// The request is not a valid WebSocket upgrade; a `400` or `426` has been sent.
+    Handshake(&'static str),
+    /// The client violated the protocol; the connection has been closed with `1002`.
+    Protocol(&'static str),
+    /// A text message or close reason was not valid UTF-8; the connection has been closed with `1007`.
+    InvalidUtf8,
+    /// A frame or message exceeded the configured limit; the connection has been closed with `1009`.
+    MessageTooLarge {
+        /// The limit in bytes.
+        limit: usize,
+    },
+    /// A message was sent after the connection was closed.
+    ConnectionClosed,
+    /// The connection failed, e.g. the client disconnected without a close frame.
+    Io(String),
+}
+
+impl WebSocketError {
+    /// Returns the close code sent to the client for this error, if the connection is closed because of it.
+    pub fn close_code(&self) -> Option<u16> {
+        match self {
+            WebSocketError::Protocol(_) => Some(close_code::PROTOCOL_ERROR),
+            WebSocketError::InvalidUtf8 => Some(close_code::INVALID_PAYLOAD),
+            WebSocketError::MessageTooLarge { .. } => Some(close_code::MESSAGE_TOO_BIG),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Handshake(msg) => write!(f, "Invalid WebSocket handshake: {msg}"),
+            WebSocketError::Protocol(msg) => write!(f, "WebSocket protocol error: {msg}"),
+            WebSocketError::InvalidUtf8 => write!(f, "WebSocket message is not valid UTF-8"),
+            WebSocketError::MessageTooLarge { limit } => {
+                write!(f, "WebSocket message exceeds {limit} bytes")
+            }
+            WebSocketError::ConnectionClosed => write!(f, "WebSocket connection is closed"),
+            WebSocketError::Io(msg) => write!(f, "WebSocket I/O error: {msg}"),
+        }
+    }
+}
+
+impl Error for WebSocketError {}
+
+impl From<io::Error> for WebSocketError {
+    fn from(e: io::Error) -> Self {
+        WebSocketError::Io(e.to_string())
+    }
+}
+
+/// Computes the `Sec-WebSocket-Accept` value for a `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    STANDARD.encode(sha1.finalize())
+}
+
+impl Request {
+    /// Checks whether the request asks to upgrade the connection to a WebSocket.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the request is a `GET` with `Upgrade: websocket` and `Connection: Upgrade` headers.
+    pub fn is_websocket_upgrade(&self) -> bool {
+        self.method() == "GET"
+            && self.header_has_token("Upgrade", "websocket")
+            && self.header_has_token("Connection", "upgrade")
+    }
+}
+
+impl Response {
+    /// Upgrades the connection of a request to a WebSocket, following RFC 6455.
+    ///
+    /// The client's key and version are validated, the first subprotocol in `config.protocols`
+    /// that the client offers is selected, and a `101 Switching Protocols` is sent. Invalid
+    /// requests are answered with `400 Bad Request`, or `426 Upgrade Required` for an unsupported
+    /// version. After the upgrade the connection belongs to the returned `WebSocket`, and the
+    /// response can no longer be used.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The upgrade request.
+    /// * `config` - The limits and options of the connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `WebSocket`, or a `WebSocketError::Handshake` if the request is not a valid upgrade.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use rusticore::{Message, Request, Response, WebSocketConfig};
+    /// # async fn handler(req: &mut Request, res: &mut Response) {
+    /// let Ok(mut ws) = res.websocket(req, WebSocketConfig::default()).await else {
+    ///     return;
+    /// };
+    /// while let Some(Ok(message)) = ws.recv().await {
+    ///     if let Message::Text(text) = message {
+    ///         let _ = ws.send(Message::Text(text)).await;
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub async fn websocket(
+        &mut self,
+        req: &Request,
+        config: WebSocketConfig,
+    ) -> Result<WebSocket, WebSocketError> {
+        if !req.is_websocket_upgrade() {
+            return self.reject("Missing upgrade headers").await;
+        }
+        if req.get_header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+            self.set_header("Sec-WebSocket-Version", "13");
+            self.text(
+                "Unsupported WebSocket version",
+                StatusCode::UPGRADE_REQUIRED,
+            )
+            .await;
+            return Err(WebSocketError::Handshake("Unsupported WebSocket version"));
+        }
+        let key = match req.get_header("Sec-WebSocket-Key").map(str::trim) {
+            Some(key) if STANDARD.decode(key).is_ok_and(|k| k.len() == 16) => key,
+            _ => return self.reject("Invalid Sec-WebSocket-Key").await,
+        };
+
+        let offered: Vec<&str> = req
+            .get_headers("Sec-WebSocket-Protocol")
+            .into_iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        let protocol = config
+            .protocols
+            .iter()
+            .find(|p| offered.contains(&p.as_str()))
+            .cloned();
+
+        #[cfg(feature = "websocket-deflate")]
+        let deflate = config.permessage_deflate && deflate::negotiate(req);
+        #[cfg(not(feature = "websocket-deflate"))]
+        let deflate = false;
+
+        self.status_code = StatusCode::SWITCHING_PROTOCOLS;
+        self.set_header("Upgrade", "websocket");
+        self.set_header("Connection", "Upgrade");
+        self.set_header("Sec-WebSocket-Accept", accept_key(key));
+        if let Some(ref protocol) = protocol {
+            self.set_header("Sec-WebSocket-Protocol", protocol.as_str());
+        }
+        #[cfg(feature = "websocket-deflate")]
+        if deflate {
+            self.set_header("Sec-WebSocket-Extensions", deflate::RESPONSE);
+        }
+        self.send_head().await?;
+
+        let transport = detach(&self.tcp_stream).await;
+        let (reader, writer) = tokio::io::split(transport);
+        Ok(WebSocket {
+            reader: BufReader::new(reader),
+            sender: WebSocketSender {
+                writer: Arc::new(Mutex::new(writer)),
+                closed: Arc::new(AtomicBool::new(false)),
+                max_frame_size: config.max_frame_size,
+                deflate,
+            },
+            protocol,
+            config,
+            deflate,
+            partial: None,
+            finished: false,
+        })
+    }
+
+    /// Answers an invalid upgrade request with `400 Bad Request`.
+    async fn reject<T>(&mut self, reason: &'static str) -> Result<T, WebSocketError> {
+        self.text(reason, StatusCode::BAD_REQUEST).await;
+        Err(WebSocketError::Handshake(reason))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The sending half of a WebSocket, which can be cloned and moved to other tasks, e.g. to
+/// push messages while another task receives.
+pub struct WebSocketSender {
+    /// The writing half of the connection.
+    writer: Arc<Mutex<WriteHalf<Box<dyn Transport>>>>,
+    /// Whether a close frame has been sent.
+    closed: Arc<AtomicBool>,
+    /// The size data messages are fragmented at.
+    max_frame_size: usize,
+    /// Whether `permessage-deflate` was negotiated.
+    deflate: bool,
+}
+
+impl WebSocketSender {
+    /// Sends a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send. Sending `Message::Close` closes the connection.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the message was sent, or `WebSocketError::ConnectionClosed`
+    /// if a close frame has already been sent.
+    pub async fn send(&self, message: Message) -> Result<(), WebSocketError> {
+        let (op, payload) = match message {
+            Message::Text(text) => (opcode::TEXT, text.into_bytes()),
+            Message::Binary(data) => (opcode::BINARY, data),
+            Message::Ping(data) => (opcode::PING, data),
+            Message::Pong(data) => (opcode::PONG, data),
+            Message::Close(frame) => {
+                let frame = frame.unwrap_or(CloseFrame {
+                    code: close_code::NORMAL,
+                    reason: String::new(),
+                });
+                return self.close(frame.code, &frame.reason).await;
+            }
+        };
+        if self.closed.load(Ordering::Acquire) {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+
+        if op >= opcode::CLOSE {
+            if payload.len() > MAX_CONTROL_PAYLOAD {
+                return Err(WebSocketError::MessageTooLarge {
+                    limit: MAX_CONTROL_PAYLOAD,
+                });
+            }
+            return self.write_frame(true, false, op, &payload).await;
+        }
+
+        #[cfg(feature = "websocket-deflate")]
+        let payload = if self.deflate {
+            deflate::compress(&payload)?
+        } else {
+            payload
+        };
+
+        // Fragments of one message must not be interleaved with other messages.
+        let mut writer = self.writer.lock().await;
+        let mut chunks = payload.chunks(self.max_frame_size.max(1)).peekable();
+        let mut first = true;
+        if chunks.peek().is_none() {
+            let frame = encode_frame(true, self.deflate, op, &[]);
+            writer.write_all(&frame).await?;
+        }
+        while let Some(chunk) = chunks.next() {
+            let fin = chunks.peek().is_none();
+            let op = if first { op } else { opcode::CONTINUATION };
+            let frame = encode_frame(fin, first && self.deflate, op, chunk);
+            writer.write_all(&frame).await?;
+            first = false;
+        }
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Sends a close frame. Further messages cannot be sent, but messages already on their
+    /// way from the client can still be received until its close frame arrives.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The close code, e.g. `close_code::NORMAL`.
+    /// * `reason` - The reason for closing, truncated to fit in a control frame.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the close frame was sent.
+    pub async fn close(&self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.write_frame(true, false, opcode::CLOSE, &payload).await
+    }
+
+    /// Checks whether a close frame has been sent.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Writes one frame and flushes it.
+    async fn write_frame(
+        &self,
+        fin: bool,
+        rsv1: bool,
+        op: u8,
+        payload: &[u8],
+    ) -> Result<(), WebSocketError> {
+        let frame = encode_frame(fin, rsv1, op, payload);
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&frame).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Shuts down the writing half of the connection.
+    async fn shutdown(&self) {
+        let _ = self.writer.lock().await.shutdown().await;
+    }
+}
+
+/// Encodes an unmasked frame, as sent by a server.
+fn encode_frame(fin: bool, rsv1: bool, op: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(((fin as u8) << 7) | ((rsv1 as u8) << 6) | op);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[derive(Debug)]
+/// A frame read from the client, with its payload unmasked.
+struct Frame {
+    /// Whether this is the last frame of a message.
+    fin: bool,
+    /// Whether the `RSV1` bit, used by `permessage-deflate`, is set.
+    rsv1: bool,
+    /// The opcode of the frame.
+    op: u8,
+    /// The unmasked payload.
+    payload: Vec<u8>,
+}
+
+#[derive(Debug)]
+/// A WebSocket connection, created by `Response::websocket`.
+pub struct WebSocket {
+    /// The reading half of the connection.
+    reader: BufReader<ReadHalf<Box<dyn Transport>>>,
+    /// The sending half of the connection.
+    sender: WebSocketSender,
+    /// The negotiated subprotocol.
+    protocol: Option<String>,
+    /// The limits and options of the connection.
+    config: WebSocketConfig,
+    /// Whether `permessage-deflate` was negotiated.
+    deflate: bool,
+    /// The opcode, compression flag and payload of a fragmented message being reassembled.
+    partial: Option<(u8, bool, Vec<u8>)>,
+    /// Whether the closing handshake has completed or the connection failed.
+    finished: bool,
+}
+
+impl WebSocket {
+    /// Returns the subprotocol selected during the handshake.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Returns a sending half that can be moved to other tasks.
+    pub fn sender(&self) -> WebSocketSender {
+        self.sender.clone()
+    }
+
+    /// Sends a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the message was sent.
+    pub async fn send(&self, message: Message) -> Result<(), WebSocketError> {
+        self.sender.send(message).await
+    }
+
+    /// Sends a close frame with a code and reason.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The close code, e.g. `close_code::NORMAL`.
+    /// * `reason` - The reason for closing.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating whether the close frame was sent.
+    pub async fn close(&self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.sender.close(code, reason).await
+    }
+
+    /// Receives the next message, reassembling fragmented messages.
+    ///
+    /// Pings are answered automatically before being returned. When the client's close frame
+    /// arrives it is answered, unless a close frame was already sent, and returned as
+    /// `Message::Close`. If the client violates the protocol or a limit, the connection is closed
+    /// with the matching close code and the error is returned.
+    ///
+    /// # Returns
+    ///
+    /// An `Option` containing the next message or error, or `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<Result<Message, WebSocketError>> {
+        if self.finished {
+            return None;
+        }
+        let result = self.read_message().await;
+        match result {
+            Ok(Message::Close(_)) => {
+                self.finished = true;
+                self.sender.shutdown().await;
+            }
+            Ok(_) => {}
+            Err(ref e) => {
+                self.finished = true;
+                if let Some(code) = e.close_code() {
+                    let _ = self.sender.close(code, "").await;
+                }
+                self.sender.shutdown().await;
+            }
+        }
+        Some(result)
+    }
+
+    /// Reads frames until a complete message or a control frame has been received.
+    async fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            let frame = self.read_frame().await?;
+            match frame.op {
+                opcode::CLOSE | opcode::PING | opcode::PONG => {
+                    if !frame.fin || frame.rsv1 {
+                        return Err(WebSocketError::Protocol("Invalid control frame"));
+                    }
+                    match frame.op {
+                        opcode::PING => {
+                            if !self.sender.is_closed() {
+                                self.sender
+                                    .write_frame(true, false, opcode::PONG, &frame.payload)
+                                    .await?;
+                            }
+                            return Ok(Message::Ping(frame.payload));
+                        }
+                        opcode::PONG => return Ok(Message::Pong(frame.payload)),
+                        _ => return self.closed_by_client(&frame.payload).await,
+                    }
+                }
+                opcode::TEXT | opcode::BINARY => {
+                    if self.partial.is_some() {
+                        return Err(WebSocketError::Protocol("Expected a continuation frame"));
+                    }
+                    if frame.rsv1 && !self.deflate {
+                        return Err(WebSocketError::Protocol("Unexpected RSV1 bit"));
+                    }
+                    self.partial = Some((frame.op, frame.rsv1, frame.payload));
+                }
+                opcode::CONTINUATION => {
+                    let Some((_, _, ref mut payload)) = self.partial else {
+                        return Err(WebSocketError::Protocol("Unexpected continuation frame"));
+                    };
+                    if frame.rsv1 {
+                        return Err(WebSocketError::Protocol("Unexpected RSV1 bit"));
+                    }
+                    if payload.len() + frame.payload.len() > self.config.max_message_size {
+                        return Err(WebSocketError::MessageTooLarge {
+                            limit: self.config.max_message_size,
+                        });
+                    }
+                    payload.extend_from_slice(&frame.payload);
+                }
+                _ => return Err(WebSocketError::Protocol("Unknown opcode")),
+            }
+
+            if frame.fin
+                && let Some((op, compressed, payload)) = self.partial.take()
+            {
+                #[cfg(feature = "websocket-deflate")]
+                let payload = if compressed {
+                    deflate::decompress(&payload, self.config.max_message_size)?
+                } else {
+                    payload
+                };
+                #[cfg(not(feature = "websocket-deflate"))]
+                let _ = compressed;
+
+                return match op {
+                    opcode::TEXT => String::from_utf8(payload)
+                        .map(Message::Text)
+                        .map_err(|_| WebSocketError::InvalidUtf8),
+                    _ => Ok(Message::Binary(payload)),
+                };
+            }
+        }
+    }
+
+    /// Handles the close frame of the client, answering it if no close frame was sent yet.
+    async fn closed_by_client(&mut self, payload: &[u8]) -> Result<Message, WebSocketError> {
+        let frame = match payload.len() {
+            0 => None,
+            1 => return Err(WebSocketError::Protocol("Invalid close frame")),
+            _ => {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+                if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+                    return Err(WebSocketError::Protocol("Invalid close code"));
+                }
+                let reason = std::str::from_utf8(&payload[2..])
+                    .map_err(|_| WebSocketError::InvalidUtf8)?
+                    .to_string();
+                Some(CloseFrame { code, reason })
+            }
+        };
+
+        if !self.sender.is_closed() {
+            let code = frame.as_ref().map_or(close_code::NORMAL, |f| f.code);
+            let _ = self.sender.close(code, "").await;
+        }
+        Ok(Message::Close(frame))
+    }
+
+    /// Reads one frame, validating its header and unmasking its payload.
+    async fn read_frame(&mut self) -> Result<Frame, WebSocketError> {
+        let mut head = [0u8; 2];
+        self.reader.read_exact(&mut head).await?;
+        let fin = head[0] & 0x80 != 0;
+        let rsv1 = head[0] & 0x40 != 0;
+        let op = head[0] & 0x0F;
+        if head[0] & 0x30 != 0 {
+            return Err(WebSocketError::Protocol("Unexpected RSV2 or RSV3 bit"));
+        }
+        if head[1] & 0x80 == 0 {
+            return Err(WebSocketError::Protocol("Client frames must be masked"));
+        }
+
+        let len = match head[1] & 0x7F {
+            126 => self.reader.read_u16().await? as u64,
+            127 => self.reader.read_u64().await?,
+            len => len as u64,
+        };
+        if op >= opcode::CLOSE && len > MAX_CONTROL_PAYLOAD as u64 {
+            return Err(WebSocketError::Protocol("Control frame too large"));
+        }
+        let limit = self.config.max_frame_size.min(self.config.max_message_size);
+        if len > limit as u64 {
+            return Err(WebSocketError::MessageTooLarge { limit });
+        }
+
+        let mut mask = [0u8; 4];
+        self.reader.read_exact(&mut mask).await?;
+        let mut payload = vec![0u8; len as usize];
+        self.reader.read_exact(&mut payload).await?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Frame {
+            fin,
+            rsv1,
+            op,
+            payload,
+        })
+    }
+}
+
+#[cfg(feature = "websocket-deflate")]
+/// The `permessage-deflate` extension from RFC 7692, without context takeover in either direction.
+mod deflate {
+    use super::WebSocketError;
+    use crate::request::Request;
+    use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+    /// The `Sec-WebSocket-Extensions` value sent when the extension is accepted.
+    pub(super) const RESPONSE: &str =
+        "permessage-deflate; server_no_context_takeover; client_no_context_takeover";
+
+    /// The bytes removed from the end of every compressed message.
+    const TAIL: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+    /// Checks whether the client offers an acceptable `permessage-deflate` configuration.
+    ///
+    /// Offers asking the server for a window smaller than the default are declined, since the
+    /// compressor always uses a 32 KiB window.
+    pub(super) fn negotiate(req: &Request) -> bool {
+        req.get_headers("Sec-WebSocket-Extensions")
+            .iter()
+            .flat_map(|value| value.split(','))
+            .any(|offer| {
+                let mut params = offer.split(';').map(str::trim);
+                params.next() == Some("permessage-deflate")
+                    && params.all(|param| {
+                        let name = param.split('=').next().unwrap_or_default().trim();
+                        match name {
+                            "server_no_context_takeover"
+                            | "client_no_context_takeover"
+                            | "client_max_window_bits" => true,
+                            "server_max_window_bits" => param
+                                .split_once('=')
+                                .is_some_and(|(_, bits)| bits.trim().trim_matches('"') == "15"),
+                            _ => false,
+                        }
+                    })
+            })
+    }
+
+    /// Compresses a message payload.
+    pub(super) fn compress(data: &[u8]) -> Result<Vec<u8>, WebSocketError> {
+        let mut compress = Compress::new(Compression::fast(), false);
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        loop {
+            let consumed = compress.total_in() as usize;
+            compress
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(|e| WebSocketError::Io(e.to_string()))?;
+            if compress.total_in() as usize == data.len() && out.len() < out.capacity() {
+                break;
+            }
+            out.reserve(out.capacity().max(64));
+        }
+        if out.ends_with(&TAIL) {
+            out.truncate(out.len() - TAIL.len());
+        }
+        Ok(out)
+    }
+
+    /// Decompresses a message payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns `WebSocketError::MessageTooLarge` if the decompressed payload exceeds `limit`
+    /// bytes, which stops compression bombs early.
+    pub(super) fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, WebSocketError> {
+        let mut input = Vec::with_capacity(data.len() + TAIL.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&TAIL);
+
+        let mut decompress = Decompress::new(false);
+        let mut out = Vec::with_capacity((data.len() * 4).clamp(64, limit + 1));
+        loop {
+            let (before_in, before_out) = (decompress.total_in(), decompress.total_out());
+            let consumed = before_in as usize;
+            let status = decompress
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|_| WebSocketError::Protocol("Invalid compressed data"))?;
+            if out.len() > limit {
+                return Err(WebSocketError::MessageTooLarge { limit });
+            }
+            let done = decompress.total_in() as usize == input.len();
+            if status == Status::StreamEnd || (done && out.len() < out.capacity()) {
+                return Ok(out);
+            }
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity().min(limit + 1 - out.len()).max(1));
+            } else if decompress.total_in() == before_in && decompress.total_out() == before_out {
+                return Err(WebSocketError::Protocol("Invalid compressed data"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::shared_stream;
+    use crate::Server;
+    use tokio::io::{duplex, DuplexStream};
+
+    /// Encodes a masked frame, as sent by a client.
+    fn client_frame(fin: bool, rsv1: bool, op: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = encode_frame(fin, rsv1, op, payload);
+        let offset = frame.len() - payload.len();
+        frame[1] |= 0x80;
+        frame.splice(offset..offset, mask);
+        for (i, byte) in frame[offset + 4..].iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        frame
+    }
+
+    /// Reads one unmasked frame sent by the server.
+    async fn server_frame(client: &mut DuplexStream) -> (u8, Vec<u8>) {
+        let mut head = [0u8; 2];
+        client.read_exact(&mut head).await.unwrap();
+        let len = match head[1] {
+            126 => client.read_u16().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut payload = vec![0u8; len];
+        client.read_exact(&mut payload).await.unwrap();
+        (head[0], payload)
+    }
+
+    /// Performs a handshake with extra request headers, returning the client side, the
+    /// response head and the result of the upgrade.
+    async fn handshake(
+        headers: &str,
+        config: WebSocketConfig,
+    ) -> (DuplexStream, String, Result<WebSocket, WebSocketError>) {
+        let server = Arc::new(Server::new("localhost", 8080, false, None, None));
+        let (mut client, server_stream) = duplex(256 * 1024);
+        let stream = shared_stream(server_stream);
+        let request = format!(
+            "GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+             Connection: keep-alive, Upgrade\r\n{headers}\r\n"
+        );
+        client.write_all(request.as_bytes()).await.unwrap();
+
+        let req = Request::new(stream.clone(), server.clone()).await.unwrap();
+        let mut res = Response::new(stream, "HTTP/1.1", server);
+        let ws = res.websocket(&req, config).await;
+
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(client.read_u8().await.unwrap());
+        }
+        (client, String::from_utf8(head).unwrap(), ws)
+    }
+
+    const KEY: &str =
+        "Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+
+    #[tokio::test]
+    /// Tests the handshake, including subprotocol negotiation and rejected requests.
+    async fn upgrade() {
+        let config = WebSocketConfig {
+            protocols: vec!["v2.chat".to_string(), "v1.chat".to_string()],
+            ..WebSocketConfig::default()
+        };
+        let headers = format!("{KEY}Sec-WebSocket-Protocol: v1.chat, v2.chat\r\n");
+        let (_client, head, ws) = handshake(&headers, config).await;
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(head.contains("Sec-WebSocket-Protocol: v2.chat\r\n"));
+        assert_eq!(ws.unwrap().protocol(), Some("v2.chat"));
+
+        let headers = "Sec-WebSocket-Version: 8\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+        let (_, head, ws) = handshake(headers, WebSocketConfig::default()).await;
+        assert!(head.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(matches!(ws, Err(WebSocketError::Handshake(_))));
+
+        let headers = "Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: c2hvcnQ=\r\n";
+        let (_, head, ws) = handshake(headers, WebSocketConfig::default()).await;
+        assert!(head.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(ws.is_err());
+    }
+
+    #[tokio::test]
+    /// Tests fragmented messages with interleaved pings, outgoing fragmentation and the closing handshake.
+    async fn messages() {
+        let config = WebSocketConfig {
+            max_frame_size: 200,
+            ..WebSocketConfig::default()
+        };
+        let (mut client, _, ws) = handshake(KEY, config).await;
+        let mut ws = ws.unwrap();
+
+        let mut input = client_frame(false, false, opcode::TEXT, "Hel".as_bytes());
+        input.extend(client_frame(true, false, opcode::PING, b"hb"));
+        input.extend(client_frame(
+            true,
+            false,
+            opcode::CONTINUATION,
+            "lo ✓".as_bytes(),
+        ));
+        client.write_all(&input).await.unwrap();
+
+        assert_eq!(ws.recv().await, Some(Ok(Message::Ping(b"hb".to_vec()))));
+        assert_eq!(server_frame(&mut client).await, (0x8A, b"hb".to_vec()));
+        assert_eq!(
+            ws.recv().await,
+            Some(Ok(Message::Text("Hello ✓".to_string())))
+        );
+
+        ws.send(Message::Binary(vec![7; 300])).await.unwrap();
+        assert_eq!(server_frame(&mut client).await, (0x02, vec![7; 200]));
+        assert_eq!(server_frame(&mut client).await, (0x80, vec![7; 100]));
+
+        let mut close = 1000u16.to_be_bytes().to_vec();
+        close.extend_from_slice(b"bye");
+        client
+            .write_all(&client_frame(true, false, opcode::CLOSE, &close))
+            .await
+            .unwrap();
+        let frame = CloseFrame {
+            code: 1000,
+            reason: "bye".to_string(),
+        };
+        assert_eq!(ws.recv().await, Some(Ok(Message::Close(Some(frame)))));
+        assert_eq!(
+            server_frame(&mut client).await,
+            (0x88, 1000u16.to_be_bytes().to_vec())
+        );
+        assert_eq!(ws.recv().await, None);
+        assert_eq!(
+            ws.send(Message::Text("late".to_string())).await,
+            Err(WebSocketError::ConnectionClosed)
+        );
+    }
+
+    #[tokio::test]
+    /// Tests that unmasked frames, oversized messages and invalid UTF-8 close the connection.
+    async fn violations() {
+        let cases: [(Vec<u8>, u16); 3] = [
+            (encode_frame(true, false, opcode::TEXT, b"hi"), 1002),
+            (client_frame(true, false, opcode::BINARY, &[0; 2000]), 1009),
+            (client_frame(true, false, opcode::TEXT, &[0xff, 0xfe]), 1007),
+        ];
+        for (input, code) in cases {
+            let config = WebSocketConfig {
+                max_message_size: 1024,
+                ..WebSocketConfig::default()
+            };
+            let (mut client, _, ws) = handshake(KEY, config).await;
+            let mut ws = ws.unwrap();
+            client.write_all(&input).await.unwrap();
+            let error = ws.recv().await.unwrap().unwrap_err();
+            assert_eq!(error.close_code(), Some(code));
+            assert_eq!(
+                server_frame(&mut client).await,
+                (0x88, code.to_be_bytes().to_vec())
+            );
+            assert_eq!(ws.recv().await, None);
+        }
+    }
+
+    #[cfg(feature = "websocket-deflate")]
+    #[tokio::test]
+    /// Tests negotiating `permessage-deflate` and exchanging compressed messages.
+    async fn permessage_deflate() {
+        let headers = format!(
+            "{KEY}Sec-WebSocket-Extensions: permessage-deflate; server_max_window_bits=10, \
+             permessage-deflate; client_max_window_bits\r\n"
+        );
+        let (mut client, head, ws) = handshake(&headers, WebSocketConfig::default()).await;
+        assert!(head.contains(&format!(
+            "Sec-WebSocket-Extensions: {}\r\n",
+            deflate::RESPONSE
+        )));
+        let mut ws = ws.unwrap();
+
+        let text = "compressible ".repeat(100);
+        let compressed = deflate::compress(text.as_bytes()).unwrap();
+        assert!(compressed.len() < text.len() / 4);
+        client
+            .write_all(&client_frame(true, true, opcode::TEXT, &compressed))
+            .await
+            .unwrap();
+        assert_eq!(ws.recv().await, Some(Ok(Message::Text(text.clone()))));
+
+        ws.send(Message::Text(text.clone())).await.unwrap();
+        let (head, payload) = server_frame(&mut client).await;
+        assert_eq!(head, 0xC1);
+        assert_eq!(
+            deflate::decompress(&payload, 1 << 20).unwrap(),
+            text.as_bytes()
+        );
+        assert!(matches!(
+            deflate::decompress(&compressed, 100),
+            Err(WebSocketError::MessageTooLarge { limit: 100 })
+        ));
+    }
+}
@@ -12,5 +12,5 @@ async fn test_server() {
         .check_state(ServerState::Running)
         .await
         .0;
-    assert_eq!(is_running, true, "Server should return true on success");
+    assert!(is_running, "Server should return true on success");
 }